@@ -0,0 +1,48 @@
+use babel::{Conversation, Groq, GroqModel, LLMBuilder};
+use futures::StreamExt;
+
+#[tokio::main]
+async fn main() -> Result<(), String> {
+    let groq_llm = LLMBuilder::<Groq>::new()
+        .model(GroqModel::Llama33_70bVersatile)
+        .max_tokens(1024)
+        .build()?;
+
+    // The conversation owns the history and appends every reply automatically
+    let mut conversation = Conversation::new()
+        .system_prompt("You are a concise Rust tutor.".to_string());
+
+    let response = conversation
+        .chat(&groq_llm, "What are the key features of Rust?".to_string())
+        .await?;
+    println!("AI: {}\n", response);
+
+    let response = conversation
+        .chat(&groq_llm, "What advantages does Rust have over C++?".to_string())
+        .await?;
+    println!("AI: {}\n", response);
+
+    // Streaming works the same way
+    print!("AI: ");
+    let mut stream = conversation
+        .stream_chat(
+            &groq_llm,
+            "Give me a simple example of Rust's ownership system.".to_string(),
+        )
+        .await;
+    while let Some(result) = stream.next().await {
+        if let Some(content) = result?.get_content() {
+            print!("{}", content);
+        }
+    }
+    drop(stream);
+    println!();
+
+    println!(
+        "\n{} messages, ~{} tokens",
+        conversation.len(),
+        conversation.estimated_tokens()
+    );
+
+    Ok(())
+}
@@ -1,5 +1,4 @@
 use babel::{ChatMessage, Groq, GroqModel, LLMBuilder};
-use tokio;
 
 #[tokio::main]
 async fn main() -> Result<(), String> {
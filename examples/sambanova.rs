@@ -1,5 +1,4 @@
 use babel::{ChatMessage, SambaNova, SambaNovaModel, LLMBuilder};
-use tokio;
 
 #[tokio::main]
 async fn main() -> Result<(), String> {
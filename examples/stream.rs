@@ -1,6 +1,5 @@
 use babel::{ChatMessage, LLMBuilder, OpenRouter, OpenRouterModel};
 use futures::StreamExt;
-use tokio;
 
 #[tokio::main]
 async fn main() -> Result<(), String> {
@@ -0,0 +1,84 @@
+use babel::mock_server::{MockServer, MockServerConfig};
+use std::time::Duration;
+
+const USAGE: &str = "Usage: babel-mock-server [OPTIONS]
+
+OpenAI-compatible server for tests. Serves POST /v1/chat/completions
+(streaming and non-streaming) and GET /v1/models.
+
+Options:
+  --host <HOST>            Address to listen on [default: 127.0.0.1]
+  --port <PORT>            Port to listen on, 0 for any free port [default: 8080]
+  --reply <TEXT>           Answer every completion with TEXT instead of echoing
+                           the last user message
+  --model <ID>             Model id listed by /v1/models; repeat for more
+  --latency-ms <MS>        Delay before each response
+  --chunk-delay-ms <MS>    Delay before each streamed chunk
+  --error-rate <RATE>      Share of completions (0.0-1.0) that fail
+  --error-status <STATUS>  HTTP status of failed completions [default: 500]
+  --fail-first <N>         Fail the first N completions
+  -h, --help               Print this help";
+
+fn parse<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+fn parse_args() -> Result<(String, MockServerConfig), String> {
+    let mut host = "127.0.0.1".to_string();
+    let mut port: u16 = 8080;
+    let mut models = Vec::new();
+    let mut config = MockServerConfig::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--host" => host = parse(&arg, args.next())?,
+            "--port" => port = parse(&arg, args.next())?,
+            "--reply" => config = config.reply(&parse::<String>(&arg, args.next())?),
+            "--model" => models.push(parse(&arg, args.next())?),
+            "--latency-ms" => config = config.latency(Duration::from_millis(parse(&arg, args.next())?)),
+            "--chunk-delay-ms" => config = config.chunk_delay(Duration::from_millis(parse(&arg, args.next())?)),
+            "--error-rate" => config = config.error_rate(parse(&arg, args.next())?),
+            "--error-status" => config = config.error_status(parse(&arg, args.next())?),
+            "--fail-first" => config = config.fail_first(parse(&arg, args.next())?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+
+    if !models.is_empty() {
+        config = config.models(models);
+    }
+    Ok((format!("{}:{}", host, port), config))
+}
+
+#[tokio::main]
+async fn main() {
+    let (addr, config) = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let server = match MockServer::bind(&addr, config).await {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("Listening on {}", server.base_url());
+
+    tokio::select! {
+        _ = server.wait() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
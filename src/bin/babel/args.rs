@@ -0,0 +1,263 @@
+use crate::client::PROVIDERS;
+
+pub const USAGE: &str = "Usage: babel [chat] [OPTIONS]
+       babel ask [OPTIONS] [--json] <PROMPT>...
+       babel models [--provider <PROVIDER>]
+
+`chat` starts an interactive session with streaming markdown output.
+`ask` sends one prompt, with any text piped to stdin placed before it,
+and prints the reply. API keys are read from {PROVIDER}_API_KEY in the
+environment or a .env file.
+
+Options:
+  -p, --provider <PROVIDER>   groq, openrouter, sambanova or mock [default: groq]
+  -m, --model <MODEL>         Model id, e.g. llama-3.3-70b-versatile
+  -s, --system <PROMPT>       System prompt
+  -t, --temperature <TEMP>    Sampling temperature
+      --max-tokens <N>        Maximum tokens per reply
+      --plain                 Print replies without markdown rendering
+      --json                  ask: print the reply, model and usage as JSON
+  -h, --help                  Print this help
+
+Exit codes: 0 success, 1 other error, 2 invalid arguments,
+3 authentication, 4 rate limited, 5 network or provider unavailable";
+
+// Settings shared by every command that talks to a model
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub provider: String,
+    pub model: Option<String>,
+    pub system: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub plain: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            provider: "groq".to_string(),
+            model: None,
+            system: None,
+            temperature: None,
+            max_tokens: None,
+            plain: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Chat(Options),
+    Ask {
+        options: Options,
+        prompt: Vec<String>,
+        json: bool,
+    },
+    Models(Option<String>),
+    Help,
+}
+
+fn value<I: Iterator<Item = String>>(flag: &str, args: &mut I) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{} needs a value", flag))
+}
+
+fn parse_value<T: std::str::FromStr, I: Iterator<Item = String>>(flag: &str, args: &mut I) -> Result<T, String> {
+    let raw = value(flag, args)?;
+    raw.parse().map_err(|_| format!("Invalid value for {}: {}", flag, raw))
+}
+
+fn provider(name: String) -> Result<String, String> {
+    let name = name.to_lowercase();
+    if PROVIDERS.contains(&name.as_str()) {
+        Ok(name)
+    } else {
+        Err(format!("Unknown provider {} (expected one of: {})", name, PROVIDERS.join(", ")))
+    }
+}
+
+// Parse one option shared by the chat commands into `options`.
+// Returns false if `arg` is not such an option.
+pub fn parse_option<I: Iterator<Item = String>>(
+    arg: &str,
+    args: &mut I,
+    options: &mut Options,
+) -> Result<bool, String> {
+    match arg {
+        "-p" | "--provider" => options.provider = provider(value(arg, args)?)?,
+        "-m" | "--model" => options.model = Some(value(arg, args)?),
+        "-s" | "--system" => options.system = Some(value(arg, args)?),
+        "-t" | "--temperature" => options.temperature = Some(parse_value(arg, args)?),
+        "--max-tokens" => options.max_tokens = Some(parse_value(arg, args)?),
+        "--plain" => options.plain = true,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.peekable();
+
+    match args.peek().map(String::as_str) {
+        Some("models") => {
+            args.next();
+            let mut filter = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "-p" | "--provider" => filter = Some(provider(value(&arg, &mut args)?)?),
+                    "-h" | "--help" => return Ok(Command::Help),
+                    _ => return Err(format!("Unknown argument: {}", arg)),
+                }
+            }
+            return Ok(Command::Models(filter));
+        }
+        Some("ask") => {
+            args.next();
+            let mut options = Options::default();
+            let mut prompt = Vec::new();
+            let mut json = false;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "-h" | "--help" => return Ok(Command::Help),
+                    "--json" => json = true,
+                    // Everything after -- is prompt, even if it looks like a flag
+                    "--" => prompt.extend(args.by_ref()),
+                    _ if parse_option(&arg, &mut args, &mut options)? => {}
+                    _ if arg.starts_with('-') && arg.len() > 1 => {
+                        return Err(format!("Unknown argument: {}", arg));
+                    }
+                    _ => prompt.push(arg),
+                }
+            }
+            return Ok(Command::Ask { options, prompt, json });
+        }
+        Some("chat") => {
+            args.next();
+        }
+        _ => {}
+    }
+
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+        if !parse_option(&arg, &mut args, &mut options)? {
+            return Err(format!("Unknown argument: {}", arg));
+        }
+    }
+    Ok(Command::Chat(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn ask(options: Options, prompt: &[&str], json: bool) -> Command {
+        Command::Ask {
+            options,
+            prompt: prompt.iter().map(|word| word.to_string()).collect(),
+            json,
+        }
+    }
+
+    #[test]
+    fn no_arguments_start_a_chat_with_the_defaults() {
+        assert_eq!(parse_args(&[]), Ok(Command::Chat(Options::default())));
+        assert_eq!(parse_args(&["chat"]), Ok(Command::Chat(Options::default())));
+    }
+
+    #[test]
+    fn chat_options_are_parsed() {
+        let command = parse_args(&[
+            "chat",
+            "-p",
+            "OpenRouter",
+            "--model",
+            "m",
+            "-s",
+            "Be brief",
+            "-t",
+            "0.2",
+            "--max-tokens",
+            "64",
+            "--plain",
+        ]);
+
+        let options = Options {
+            provider: "openrouter".to_string(),
+            model: Some("m".to_string()),
+            system: Some("Be brief".to_string()),
+            temperature: Some(0.2),
+            max_tokens: Some(64),
+            plain: true,
+        };
+        assert_eq!(command, Ok(Command::Chat(options)));
+    }
+
+    #[test]
+    fn ask_collects_the_prompt_around_its_options() {
+        let command = parse_args(&["ask", "what", "-p", "mock", "is", "--json", "rust"]);
+
+        let options = Options {
+            provider: "mock".to_string(),
+            ..Options::default()
+        };
+        assert_eq!(command, Ok(ask(options, &["what", "is", "rust"], true)));
+    }
+
+    #[test]
+    fn everything_after_a_double_dash_is_prompt() {
+        let command = parse_args(&["ask", "explain", "--", "--json", "-p"]);
+
+        assert_eq!(
+            command,
+            Ok(ask(Options::default(), &["explain", "--json", "-p"], false))
+        );
+        assert_eq!(
+            parse_args(&["ask", "-"]),
+            Ok(ask(Options::default(), &["-"], false))
+        );
+    }
+
+    #[test]
+    fn models_takes_an_optional_provider() {
+        assert_eq!(parse_args(&["models"]), Ok(Command::Models(None)));
+        assert_eq!(
+            parse_args(&["models", "--provider", "groq"]),
+            Ok(Command::Models(Some("groq".to_string())))
+        );
+    }
+
+    #[test]
+    fn help_wins_anywhere() {
+        assert_eq!(parse_args(&["--help"]), Ok(Command::Help));
+        assert_eq!(parse_args(&["ask", "hi", "-h"]), Ok(Command::Help));
+        assert_eq!(parse_args(&["models", "-h"]), Ok(Command::Help));
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        let error = |args: &[&str]| parse_args(args).unwrap_err();
+
+        assert_eq!(error(&["--verbose"]), "Unknown argument: --verbose");
+        assert_eq!(
+            error(&["ask", "--verbose", "hi"]),
+            "Unknown argument: --verbose"
+        );
+        assert_eq!(error(&["models", "extra"]), "Unknown argument: extra");
+        assert_eq!(error(&["-m"]), "-m needs a value");
+        assert_eq!(error(&["-t", "warm"]), "Invalid value for -t: warm");
+        assert_eq!(
+            error(&["--max-tokens", "-1"]),
+            "Invalid value for --max-tokens: -1"
+        );
+        assert!(
+            error(&["-p", "openai"]).starts_with("Unknown provider openai (expected one of: groq")
+        );
+    }
+}
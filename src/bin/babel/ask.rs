@@ -0,0 +1,59 @@
+use babel::Conversation;
+use serde_json::json;
+use std::io::{self, IsTerminal, Read};
+
+use crate::args::Options;
+use crate::client::Client;
+use crate::error::{CliError, ErrorKind};
+use crate::output::{collect_stream, print_stream};
+
+// Piped input, placed before the prompt as context
+fn read_stdin() -> Result<Option<String>, String> {
+    let mut stdin = io::stdin();
+    if stdin.is_terminal() {
+        return Ok(None);
+    }
+    let mut input = String::new();
+    stdin
+        .read_to_string(&mut input)
+        .map_err(|e| format!("Failed to read stdin: {}", e))?;
+    Ok(Some(input).filter(|input| !input.trim().is_empty()))
+}
+
+pub async fn run(options: Options, prompt: Vec<String>, json: bool) -> Result<(), CliError> {
+    let prompt = prompt.join(" ");
+    let message = match read_stdin()? {
+        Some(context) if prompt.is_empty() => context,
+        Some(context) => format!("{}\n\n{}", context.trim_end(), prompt),
+        None => prompt,
+    };
+    if message.trim().is_empty() {
+        return Err(CliError::new(
+            ErrorKind::Usage,
+            "Nothing to ask: pass a prompt or pipe text to stdin".to_string(),
+        ));
+    }
+
+    let client = Client::build(&options)?;
+    let mut conversation = Conversation::new();
+    if let Some(system) = &options.system {
+        conversation = conversation.system_prompt(system.clone());
+    }
+
+    if json {
+        let stream = client.stream_chat(&mut conversation, message).await;
+        let (response, usage) = collect_stream(stream).await.map_err(|e| client.error(e))?;
+        let output = json!({
+            "provider": options.provider,
+            "model": client.model_id(),
+            "response": response,
+            "usage": usage,
+        });
+        println!("{}", output);
+        return Ok(());
+    }
+
+    let render = !options.plain && io::stdout().is_terminal();
+    let stream = client.stream_chat(&mut conversation, message).await;
+    print_stream(stream, render).await.map_err(|e| client.error(e))
+}
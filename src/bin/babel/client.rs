@@ -0,0 +1,257 @@
+use async_trait::async_trait;
+use babel::{
+    Conversation, Groq, GroqModel, HttpRequest, HttpResponse, LLMBuilder, LLMClient, Mock, MockModel, Model,
+    OpenRouter, OpenRouterModel, Provider, SambaNova, SambaNovaModel, StreamResponse, Transport,
+};
+use futures::stream::{Stream, StreamExt};
+use parking_lot::Mutex;
+use reqwest::StatusCode;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::args::Options;
+use crate::error::{CliError, ErrorKind};
+
+pub const PROVIDERS: &[&str] = &["groq", "openrouter", "sambanova", "mock"];
+
+pub type ReplyStream<'a> = Pin<Box<dyn Stream<Item = Result<StreamResponse, String>> + Send + 'a>>;
+
+// Model used when none is given on the command line
+pub fn default_model(provider: &str) -> &'static str {
+    match provider {
+        "openrouter" => OpenRouterModel::MetaLlama3370BInstruct.model_id(),
+        "sambanova" => SambaNovaModel::DeepSeekV3_0324.model_id(),
+        "mock" => MockModel::Echo.model_id(),
+        _ => GroqModel::Llama33_70bVersatile.model_id(),
+    }
+}
+
+pub fn model_ids(provider: &str) -> Vec<&'static str> {
+    match provider {
+        "groq" => GroqModel::ALL.iter().map(Model::model_id).collect(),
+        "openrouter" => OpenRouterModel::ALL.iter().map(Model::model_id).collect(),
+        "sambanova" => SambaNovaModel::ALL.iter().map(Model::model_id).collect(),
+        "mock" => MockModel::ALL.iter().map(Model::model_id).collect(),
+        _ => Vec::new(),
+    }
+}
+
+// How the last request ended, to tell what kind of error it failed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Status(StatusCode),
+    // No response, or the body broke off
+    Failed,
+}
+
+// Transport that remembers the outcome of the last request sent through it
+#[derive(Debug)]
+struct Observed {
+    inner: Arc<dyn Transport>,
+    last: Arc<Mutex<Option<Outcome>>>,
+}
+
+#[async_trait]
+impl Transport for Observed {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, String> {
+        let result = self.inner.send(request).await;
+        *self.last.lock() = Some(match &result {
+            Ok(response) => Outcome::Status(response.status),
+            Err(_) => Outcome::Failed,
+        });
+
+        let mut response = result?;
+        let last = self.last.clone();
+        response.body = Box::pin(response.body.inspect(move |chunk| {
+            if chunk.is_err() {
+                *last.lock() = Some(Outcome::Failed);
+            }
+        }));
+        Ok(response)
+    }
+}
+
+// A client for whichever provider was picked at runtime
+enum Llm {
+    Groq(LLMClient<Groq>),
+    OpenRouter(LLMClient<OpenRouter>),
+    SambaNova(LLMClient<SambaNova>),
+    Mock(LLMClient<Mock>),
+}
+
+pub struct Client {
+    llm: Llm,
+    last: Arc<Mutex<Option<Outcome>>>,
+}
+
+fn observe<P: Provider>(
+    builder: LLMBuilder<P>,
+    inner: Arc<dyn Transport>,
+    last: &Arc<Mutex<Option<Outcome>>>,
+) -> LLMBuilder<P> {
+    builder.transport(Observed {
+        inner,
+        last: last.clone(),
+    })
+}
+
+macro_rules! build_client {
+    ($provider:ident, $model:ident, $options:expr, $last:expr) => {{
+        let options = $options;
+        let model_id = options
+            .model
+            .clone()
+            .unwrap_or_else(|| default_model(&options.provider).to_string());
+        let model = $model::from_model_id(&model_id).ok_or_else(|| {
+            format!(
+                "Unknown {} model {} (run `babel models --provider {}` to list them)",
+                options.provider, model_id, options.provider
+            )
+        })?;
+
+        let builder = LLMBuilder::<$provider>::new().model(model);
+        let mut builder = observe(builder, $provider::default_transport(), $last);
+        if let Some(temperature) = options.temperature {
+            builder = builder.temperature(temperature);
+        }
+        if let Some(max_tokens) = options.max_tokens {
+            builder = builder.max_tokens(max_tokens);
+        }
+        // The model is set, so building only fails without an API key
+        Llm::$provider(builder.build().map_err(|e| CliError::new(ErrorKind::Auth, e))?)
+    }};
+}
+
+macro_rules! with_client {
+    ($client:expr, |$inner:ident| $body:expr) => {
+        match $client {
+            Llm::Groq($inner) => $body,
+            Llm::OpenRouter($inner) => $body,
+            Llm::SambaNova($inner) => $body,
+            Llm::Mock($inner) => $body,
+        }
+    };
+}
+
+impl Client {
+    pub fn build(options: &Options) -> Result<Self, CliError> {
+        let last = Arc::new(Mutex::new(None));
+        let llm = match options.provider.as_str() {
+            "groq" => build_client!(Groq, GroqModel, options, &last),
+            "openrouter" => build_client!(OpenRouter, OpenRouterModel, options, &last),
+            "sambanova" => build_client!(SambaNova, SambaNovaModel, options, &last),
+            "mock" => build_client!(Mock, MockModel, options, &last),
+            provider => return Err(CliError::new(ErrorKind::Usage, format!("Unknown provider {}", provider))),
+        };
+        Ok(Self { llm, last })
+    }
+
+    // Error of a failed reply, with its kind taken from how the last request ended
+    pub fn error(&self, message: String) -> CliError {
+        let kind = match *self.last.lock() {
+            Some(Outcome::Status(status)) if !status.is_success() => ErrorKind::from_status(status),
+            Some(Outcome::Failed) => ErrorKind::Network,
+            _ => ErrorKind::Other,
+        };
+        CliError::new(kind, message)
+    }
+
+    pub fn model_id(&self) -> &'static str {
+        with_client!(&self.llm, |client| client.get_model_id())
+    }
+
+    pub fn temperature(&self) -> f32 {
+        with_client!(&self.llm, |client| client.get_temperature())
+    }
+
+    pub fn context_window(&self) -> Option<u32> {
+        with_client!(&self.llm, |client| client.get_model().context_window())
+    }
+
+    pub async fn stream_chat<'a>(&'a self, conversation: &'a mut Conversation, content: String) -> ReplyStream<'a> {
+        with_client!(&self.llm, |client| conversation.stream_chat(client, content).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::collect_stream;
+    use babel::MockScript;
+
+    fn client(script: MockScript) -> Client {
+        let last = Arc::new(Mutex::new(None));
+        let builder = LLMBuilder::<Mock>::new().model(MockModel::Scripted);
+        let llm = observe(builder, Arc::new(script), &last).build().unwrap();
+        Client {
+            llm: Llm::Mock(llm),
+            last,
+        }
+    }
+
+    async fn ask(client: &Client) -> Result<String, CliError> {
+        let mut conversation = Conversation::new();
+        let stream = client
+            .stream_chat(&mut conversation, "Hi".to_string())
+            .await;
+        let (reply, _) = collect_stream(stream).await.map_err(|e| client.error(e))?;
+        Ok(reply)
+    }
+
+    #[tokio::test]
+    async fn failed_replies_are_classified_by_how_the_request_ended() {
+        let script = MockScript::new()
+            .status(401, "invalid key")
+            .status(403, "forbidden")
+            .status(429, "slow down")
+            .status(503, "overloaded")
+            .error("connection reset")
+            .status(400, "bad request")
+            .reply("Hello");
+        let client = client(script);
+
+        let mut kinds = Vec::new();
+        for _ in 0..6 {
+            kinds.push(ask(&client).await.unwrap_err().kind);
+        }
+
+        assert_eq!(
+            kinds,
+            [
+                ErrorKind::Auth,
+                ErrorKind::Auth,
+                ErrorKind::RateLimit,
+                ErrorKind::Network,
+                ErrorKind::Network,
+                ErrorKind::Other
+            ]
+        );
+        assert_eq!(ask(&client).await.unwrap(), "Hello");
+    }
+
+    #[tokio::test]
+    async fn errors_keep_the_message_of_the_request() {
+        let client = client(MockScript::new().status(429, "slow down"));
+
+        let error = ask(&client).await.unwrap_err();
+
+        assert_eq!(error.message, "HTTP 429 Too Many Requests: slow down");
+        assert_eq!(error.kind.exit_code(), 4);
+    }
+
+    #[test]
+    fn unknown_models_are_rejected() {
+        let options = Options {
+            provider: "mock".to_string(),
+            model: Some("no-such-model".to_string()),
+            ..Options::default()
+        };
+
+        let error = Client::build(&options).err().unwrap();
+
+        assert_eq!(error.kind, ErrorKind::Other);
+        assert!(error
+            .message
+            .starts_with("Unknown mock model no-such-model"));
+    }
+}
@@ -0,0 +1,99 @@
+use reqwest::StatusCode;
+use std::fmt;
+
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_AUTH: i32 = 3;
+pub const EXIT_RATE_LIMIT: i32 = 4;
+pub const EXIT_NETWORK: i32 = 5;
+
+// What went wrong, as far as the exit code is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Other,
+    // Invalid arguments
+    Usage,
+    // Missing or rejected API key
+    Auth,
+    RateLimit,
+    // No response, or the provider is unavailable
+    Network,
+}
+
+impl ErrorKind {
+    // Kind of a failed HTTP response
+    pub fn from_status(status: StatusCode) -> Self {
+        match status.as_u16() {
+            401 | 403 => ErrorKind::Auth,
+            429 => ErrorKind::RateLimit,
+            500..=599 => ErrorKind::Network,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => EXIT_ERROR,
+            ErrorKind::Usage => EXIT_USAGE,
+            ErrorKind::Auth => EXIT_AUTH,
+            ErrorKind::RateLimit => EXIT_RATE_LIMIT,
+            ErrorKind::Network => EXIT_NETWORK,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CliError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl CliError {
+    pub fn new(kind: ErrorKind, message: String) -> Self {
+        Self { kind, message }
+    }
+}
+
+impl From<String> for CliError {
+    fn from(message: String) -> Self {
+        Self::new(ErrorKind::Other, message)
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_map_to_kinds() {
+        let kind = |status: u16| ErrorKind::from_status(StatusCode::from_u16(status).unwrap());
+        assert_eq!(kind(401), ErrorKind::Auth);
+        assert_eq!(kind(403), ErrorKind::Auth);
+        assert_eq!(kind(429), ErrorKind::RateLimit);
+        assert_eq!(kind(500), ErrorKind::Network);
+        assert_eq!(kind(503), ErrorKind::Network);
+        assert_eq!(kind(400), ErrorKind::Other);
+        assert_eq!(kind(404), ErrorKind::Other);
+    }
+
+    #[test]
+    fn kinds_map_to_the_documented_exit_codes() {
+        let codes: Vec<i32> = [
+            ErrorKind::Other,
+            ErrorKind::Usage,
+            ErrorKind::Auth,
+            ErrorKind::RateLimit,
+            ErrorKind::Network,
+        ]
+        .into_iter()
+        .map(ErrorKind::exit_code)
+        .collect();
+        assert_eq!(codes, [1, 2, 3, 4, 5]);
+    }
+}
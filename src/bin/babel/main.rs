@@ -0,0 +1,49 @@
+mod args;
+mod ask;
+mod client;
+mod error;
+mod output;
+mod repl;
+
+use args::{Command, USAGE};
+use client::{default_model, model_ids, PROVIDERS};
+use error::{CliError, ErrorKind};
+use repl::Repl;
+
+#[tokio::main]
+async fn main() {
+    let command = match args::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(ErrorKind::Usage.exit_code());
+        }
+    };
+
+    let result = match command {
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Command::Models(provider) => {
+            for name in PROVIDERS.iter().filter(|name| provider.as_deref().is_none_or(|p| p == **name)) {
+                println!("{}:", name);
+                for id in model_ids(name) {
+                    let marker = if id == default_model(name) { " (default)" } else { "" };
+                    println!("  {}{}", id, marker);
+                }
+            }
+            Ok(())
+        }
+        Command::Ask { options, prompt, json } => ask::run(options, prompt, json).await,
+        Command::Chat(options) => match Repl::new(options) {
+            Ok(mut repl) => repl.run().await.map_err(CliError::from),
+            Err(e) => Err(e),
+        },
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(e.kind.exit_code());
+    }
+}
@@ -0,0 +1,54 @@
+use babel::{MarkdownStreamRenderer, Usage};
+use futures::StreamExt;
+use std::io::{self, Write};
+
+use crate::client::ReplyStream;
+
+// Print a reply as it streams in, rendering markdown when `render` is set.
+// The output always ends with a newline.
+pub async fn print_stream(mut stream: ReplyStream<'_>, render: bool) -> Result<(), String> {
+    let mut renderer = MarkdownStreamRenderer::new();
+    let mut result = Ok(());
+    let mut at_line_start = true;
+    while let Some(item) = stream.next().await {
+        match item {
+            Ok(response) => {
+                if let Some(content) = response.get_content() {
+                    if !render || renderer.render_markdown(&content).is_err() {
+                        print!("{}", content);
+                        at_line_start = content.ends_with('\n');
+                    }
+                    let _ = io::stdout().flush();
+                }
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    // Let the stream finish updating the conversation
+    drop(stream);
+
+    if render {
+        // The renderer ends the last line itself
+        let _ = renderer.finish();
+    } else if !at_line_start {
+        println!();
+    }
+    result
+}
+
+// Reply collected without printing, e.g. for --json
+pub async fn collect_stream(mut stream: ReplyStream<'_>) -> Result<(String, Option<Usage>), String> {
+    let mut reply = String::new();
+    let mut usage = None;
+    while let Some(item) = stream.next().await {
+        let response = item?;
+        if let Some(content) = response.get_content() {
+            reply.push_str(&content);
+        }
+        usage = response.get_usage().or(usage);
+    }
+    Ok((reply, usage))
+}
@@ -0,0 +1,232 @@
+use babel::Conversation;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::args::Options;
+use crate::client::{model_ids, Client, PROVIDERS};
+use crate::error::CliError;
+use crate::output::print_stream;
+
+const HELP: &str = "Commands:
+  /model [ID]             Show or switch the model
+  /model PROVIDER ID      Switch provider and model
+  /models                 List the models of the current provider
+  /system [PROMPT]        Show or set the system prompt
+  /temp [TEMP]            Show or set the temperature
+  /save PATH              Save the conversation as JSON
+  /load PATH              Load a conversation saved with /save
+  /clear                  Forget the conversation so far
+  /usage                  Show token usage of this conversation
+  /help                   Show this help
+  /quit                   Leave (or press Ctrl-D)
+
+End a line with \\ to continue on the next one, or wrap several lines in \"\"\".";
+
+pub struct Repl {
+    options: Options,
+    client: Client,
+    conversation: Conversation,
+    render: bool,
+}
+
+enum Input {
+    Message(String),
+    Command(String, String),
+    Eof,
+}
+
+impl Repl {
+    pub fn new(options: Options) -> Result<Self, CliError> {
+        let client = Client::build(&options)?;
+        let mut conversation = Conversation::new();
+        if let Some(system) = &options.system {
+            conversation = conversation.system_prompt(system.clone());
+        }
+        let render = !options.plain && io::stdout().is_terminal();
+        Ok(Self {
+            options,
+            client,
+            conversation,
+            render,
+        })
+    }
+
+    pub async fn run(&mut self) -> Result<(), String> {
+        println!(
+            "babel · {} {} · /help for commands",
+            self.options.provider,
+            self.client.model_id()
+        );
+
+        loop {
+            match read_input()? {
+                Input::Eof => break,
+                Input::Message(message) if message.trim().is_empty() => continue,
+                Input::Message(message) => self.send(message).await,
+                Input::Command(command, argument) => {
+                    if matches!(command.as_str(), "quit" | "exit" | "q") {
+                        break;
+                    }
+                    if let Err(e) = self.command(&command, argument.trim()) {
+                        eprintln!("{}", e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, message: String) {
+        let stream = self.client.stream_chat(&mut self.conversation, message).await;
+        match print_stream(stream, self.render).await {
+            Ok(()) => println!(),
+            Err(e) => eprintln!("\nError: {}", e),
+        }
+    }
+
+    fn rebuild(&mut self, options: Options) -> Result<(), String> {
+        self.client = Client::build(&options).map_err(|e| e.to_string())?;
+        self.options = options;
+        Ok(())
+    }
+
+    fn command(&mut self, command: &str, argument: &str) -> Result<(), String> {
+        match command {
+            "help" | "?" => println!("{}", HELP),
+            "model" if argument.is_empty() => {
+                println!("{} {}", self.options.provider, self.client.model_id());
+            }
+            "model" => {
+                let mut options = self.options.clone();
+                match argument.split_once(char::is_whitespace) {
+                    Some((provider, model)) if PROVIDERS.contains(&provider) => {
+                        options.provider = provider.to_string();
+                        options.model = Some(model.trim().to_string());
+                    }
+                    _ if PROVIDERS.contains(&argument) => {
+                        options.provider = argument.to_string();
+                        options.model = None;
+                    }
+                    _ => options.model = Some(argument.to_string()),
+                }
+                self.rebuild(options)?;
+                println!("Using {} {}", self.options.provider, self.client.model_id());
+            }
+            "models" => {
+                for id in model_ids(&self.options.provider) {
+                    println!("{}", id);
+                }
+            }
+            "system" if argument.is_empty() => {
+                println!("{}", self.conversation.get_system_prompt().unwrap_or("(none)"));
+            }
+            "system" => {
+                self.conversation.set_system_prompt(Some(argument.to_string()));
+                self.options.system = Some(argument.to_string());
+                println!("System prompt updated");
+            }
+            "temp" if argument.is_empty() => println!("{}", self.client.temperature()),
+            "temp" => {
+                let temperature: f32 = argument
+                    .parse()
+                    .map_err(|_| format!("Invalid temperature: {}", argument))?;
+                let mut options = self.options.clone();
+                options.temperature = Some(temperature);
+                self.rebuild(options)?;
+                println!("Temperature set to {}", temperature);
+            }
+            "save" if argument.is_empty() => return Err("Usage: /save PATH".to_string()),
+            "save" => {
+                self.conversation.save_json(argument)?;
+                println!("Saved {} messages to {}", self.conversation.len(), argument);
+            }
+            "load" if argument.is_empty() => return Err("Usage: /load PATH".to_string()),
+            "load" => {
+                self.conversation = Conversation::load_json(argument)?;
+                println!("Loaded {} messages from {}", self.conversation.len(), argument);
+            }
+            "clear" => {
+                self.conversation.clear();
+                println!("Conversation cleared");
+            }
+            "usage" => self.print_usage(),
+            _ => return Err(format!("Unknown command /{} (try /help)", command)),
+        }
+        Ok(())
+    }
+
+    fn print_usage(&self) {
+        let (mut prompt, mut completion, mut replies) = (0u64, 0u64, 0);
+        for usage in self.conversation.turns().iter().filter_map(|turn| turn.usage.as_ref()) {
+            prompt += usage.prompt_tokens.unwrap_or(0) as u64;
+            completion += usage.completion_tokens.unwrap_or(0) as u64;
+            replies += 1;
+        }
+        println!(
+            "{} replies: {} prompt + {} completion = {} tokens",
+            replies,
+            prompt,
+            completion,
+            prompt + completion
+        );
+
+        let context = self.conversation.estimated_tokens();
+        match self.client.context_window() {
+            Some(window) => println!("History: ~{} of {} context tokens", context, window),
+            None => println!("History: ~{} tokens", context),
+        }
+    }
+}
+
+fn read_line(prompt: &str) -> Result<Option<String>, String> {
+    print!("{}", prompt);
+    io::stdout().flush().map_err(|e| e.to_string())?;
+
+    let mut line = String::new();
+    let read = io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .map_err(|e| format!("Failed to read input: {}", e))?;
+    if read == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\n', '\r']).to_string()))
+}
+
+// One message or command, which may span several lines
+fn read_input() -> Result<Input, String> {
+    let Some(first) = read_line("> ")? else {
+        println!();
+        return Ok(Input::Eof);
+    };
+
+    if let Some(command) = first.trim_start().strip_prefix('/') {
+        let (name, argument) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        return Ok(Input::Command(name.to_lowercase(), argument.to_string()));
+    }
+
+    // """ opens a block that runs until the next """
+    if first.trim() == "\"\"\"" {
+        let mut lines = Vec::new();
+        while let Some(line) = read_line(". ")? {
+            if line.trim() == "\"\"\"" {
+                break;
+            }
+            lines.push(line);
+        }
+        return Ok(Input::Message(lines.join("\n")));
+    }
+
+    // A trailing backslash continues the message on the next line
+    let mut message = first;
+    while message.ends_with('\\') {
+        message.pop();
+        match read_line(". ")? {
+            Some(line) => {
+                message.push('\n');
+                message.push_str(&line);
+            }
+            None => break,
+        }
+    }
+    Ok(Input::Message(message))
+}
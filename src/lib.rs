@@ -1,5 +1,21 @@
 pub mod utils;
 pub use utils::*;
 
+pub mod protocol;
+pub use protocol::*;
+
+pub mod tools;
+pub use tools::*;
+
+pub mod render;
+pub use render::*;
+
 pub mod model;
-pub use model::*;
\ No newline at end of file
+pub use model::*;
+
+pub mod tokens;
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
+
+pub mod store;
+pub use store::*;
@@ -0,0 +1,396 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+
+use crate::model::ChatMessage;
+use crate::tokens::{count_messages_for, count_text_for, ModelFamily};
+
+// What the server answers to a chat completion
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockServerReply {
+    // The last user message
+    Echo,
+    // The same text every time
+    Canned(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct MockServerConfig {
+    reply: MockServerReply,
+    models: Vec<String>,
+    latency: Duration,
+    chunk_delay: Duration,
+    error_rate: f64,
+    error_status: u16,
+    fail_first: usize,
+}
+
+impl Default for MockServerConfig {
+    fn default() -> Self {
+        Self {
+            reply: MockServerReply::Echo,
+            models: vec!["mock-echo".to_string()],
+            latency: Duration::ZERO,
+            chunk_delay: Duration::ZERO,
+            error_rate: 0.0,
+            error_status: 500,
+            fail_first: 0,
+        }
+    }
+}
+
+impl MockServerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reply(mut self, text: &str) -> Self {
+        self.reply = MockServerReply::Canned(text.to_string());
+        self
+    }
+
+    pub fn echo(mut self) -> Self {
+        self.reply = MockServerReply::Echo;
+        self
+    }
+
+    // Model ids listed by /v1/models; completions accept any model
+    pub fn models(mut self, models: Vec<String>) -> Self {
+        self.models = models;
+        self
+    }
+
+    // Delay before each response starts
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    // Delay before each streamed chunk
+    pub fn chunk_delay(mut self, delay: Duration) -> Self {
+        self.chunk_delay = delay;
+        self
+    }
+
+    // Share of completions (0.0 to 1.0) answered with `error_status`
+    pub fn error_rate(mut self, rate: f64) -> Self {
+        self.error_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn error_status(mut self, status: u16) -> Self {
+        self.error_status = status;
+        self
+    }
+
+    // Fail the first `count` completions, e.g. to exercise retries deterministically
+    pub fn fail_first(mut self, count: usize) -> Self {
+        self.fail_first = count;
+        self
+    }
+}
+
+#[derive(Debug)]
+struct ServerState {
+    config: MockServerConfig,
+    completions: AtomicUsize,
+}
+
+// OpenAI-compatible server on localhost for end-to-end tests.
+// Point a client at it with `LLMBuilder::base_url(server.base_url())`.
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<ServerState>,
+    handle: JoinHandle<()>,
+}
+
+impl MockServer {
+    // Listen on a free port of 127.0.0.1
+    pub async fn start(config: MockServerConfig) -> Result<Self, String> {
+        Self::bind("127.0.0.1:0", config).await
+    }
+
+    pub async fn bind(addr: &str, config: MockServerConfig) -> Result<Self, String> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
+        let addr = listener.local_addr().map_err(|e| e.to_string())?;
+        let state = Arc::new(ServerState {
+            config,
+            completions: AtomicUsize::new(0),
+        });
+
+        let server_state = state.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                let (stream, _) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        error!("Mock server failed to accept a connection: {}", e);
+                        continue;
+                    }
+                };
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, state).await {
+                        debug!("Mock server connection ended: {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(Self { addr, state, handle })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // API root including the version, e.g. "http://127.0.0.1:41234/v1"
+    pub fn base_url(&self) -> String {
+        format!("http://{}/v1", self.addr)
+    }
+
+    // Chat completions received so far, including failed ones
+    pub fn completions(&self) -> usize {
+        self.state.completions.load(Ordering::SeqCst)
+    }
+
+    // Serve until the task is cancelled
+    pub async fn wait(mut self) {
+        let _ = (&mut self.handle).await;
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+// Largest request body the server reads; Content-Length is not trusted beyond this
+const MAX_BODY: usize = 8 * 1024 * 1024;
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    if content_length > MAX_BODY {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Request body of {} bytes exceeds the {} byte limit", content_length, MAX_BODY),
+        ));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    Ok(Request { method, path, body })
+}
+
+async fn write_head<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    status: u16,
+    headers: &[(&str, String)],
+) -> std::io::Result<()> {
+    let reason = reqwest::StatusCode::from_u16(status)
+        .ok()
+        .and_then(|status| status.canonical_reason())
+        .unwrap_or("Unknown");
+    let mut head = format!("HTTP/1.1 {} {}\r\nConnection: close\r\n", status, reason);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    writer.write_all(head.as_bytes()).await
+}
+
+async fn write_json<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    status: u16,
+    extra_headers: &[(&str, String)],
+    body: &Value,
+) -> std::io::Result<()> {
+    let body = body.to_string();
+    let mut headers = vec![
+        ("Content-Type", "application/json".to_string()),
+        ("Content-Length", body.len().to_string()),
+    ];
+    headers.extend(extra_headers.iter().cloned());
+    write_head(writer, status, &headers).await?;
+    writer.write_all(body.as_bytes()).await?;
+    writer.flush().await
+}
+
+fn error_body(message: &str, kind: &str) -> Value {
+    json!({ "error": { "message": message, "type": kind } })
+}
+
+async fn handle_connection(mut stream: TcpStream, state: Arc<ServerState>) -> std::io::Result<()> {
+    let request = read_request(&mut stream).await?;
+    debug!("Mock server: {} {}", request.method, request.path);
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/v1/models") => {
+            let data: Vec<Value> = state
+                .config
+                .models
+                .iter()
+                .map(|id| json!({ "id": id, "object": "model", "owned_by": "babel-mock-server" }))
+                .collect();
+            write_json(&mut stream, 200, &[], &json!({ "object": "list", "data": data })).await
+        }
+        ("POST", "/v1/chat/completions") => chat_completion(&mut stream, &state, &request.body).await,
+        _ => {
+            let message = format!("No route for {} {}", request.method, request.path);
+            write_json(&mut stream, 404, &[], &error_body(&message, "not_found")).await
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CompletionRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    #[serde(default)]
+    stream: bool,
+}
+
+async fn chat_completion(stream: &mut TcpStream, state: &ServerState, body: &[u8]) -> std::io::Result<()> {
+    let config = &state.config;
+    let index = state.completions.fetch_add(1, Ordering::SeqCst);
+
+    let request: CompletionRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => {
+            let message = format!("Invalid request body: {}", e);
+            return write_json(stream, 400, &[], &error_body(&message, "invalid_request_error")).await;
+        }
+    };
+
+    if !config.latency.is_zero() {
+        tokio::time::sleep(config.latency).await;
+    }
+
+    if index < config.fail_first || rand::random::<f64>() < config.error_rate {
+        let retry_after = match config.error_status {
+            429 => vec![("Retry-After", "1".to_string())],
+            _ => Vec::new(),
+        };
+        let body = error_body("Injected error from babel-mock-server", "mock_error");
+        return write_json(stream, config.error_status, &retry_after, &body).await;
+    }
+
+    let content = match &config.reply {
+        MockServerReply::Canned(text) => text.clone(),
+        MockServerReply::Echo => request
+            .messages
+            .iter()
+            .rev()
+            .find(|message| message.role == "user")
+            .map(|message| message.content.clone())
+            .unwrap_or_default(),
+    };
+
+    let prompt_tokens = count_messages_for(&request.messages, ModelFamily::Other);
+    let completion_tokens = count_text_for(&content, ModelFamily::Other);
+    let usage = json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": prompt_tokens + completion_tokens,
+    });
+    let id = format!("chatcmpl-mock-{}", index);
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+
+    if !request.stream {
+        let body = json!({
+            "id": id,
+            "object": "chat.completion",
+            "created": created,
+            "model": request.model,
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": content },
+                "finish_reason": "stop",
+            }],
+            "usage": usage,
+        });
+        return write_json(stream, 200, &[], &body).await;
+    }
+
+    // The body ends when the connection closes
+    write_head(
+        stream,
+        200,
+        &[
+            ("Content-Type", "text/event-stream".to_string()),
+            ("Cache-Control", "no-cache".to_string()),
+        ],
+    )
+    .await?;
+
+    let chunk = |delta: Value, finish_reason: Value, usage: Value| {
+        let mut chunk = json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": request.model,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        });
+        if !usage.is_null() {
+            chunk["usage"] = usage;
+        }
+        format!("data: {}\n\n", chunk)
+    };
+
+    stream
+        .write_all(chunk(json!({ "role": "assistant", "content": "" }), Value::Null, Value::Null).as_bytes())
+        .await?;
+    for piece in content.split_inclusive(' ') {
+        if !config.chunk_delay.is_zero() {
+            tokio::time::sleep(config.chunk_delay).await;
+        }
+        stream
+            .write_all(chunk(json!({ "content": piece }), Value::Null, Value::Null).as_bytes())
+            .await?;
+        stream.flush().await?;
+    }
+    stream
+        .write_all(chunk(json!({}), json!("stop"), usage).as_bytes())
+        .await?;
+    stream.write_all(b"data: [DONE]\n\n").await?;
+    stream.flush().await
+}
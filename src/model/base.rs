@@ -1,10 +1,84 @@
+use std::sync::Arc;
+
+use super::ratelimit::RequestWindow;
+use super::transport::{ReqwestTransport, Transport};
+
 pub trait Model {
     fn model_id(&self) -> &'static str;
+
+    // Context window in tokens, if known
+    fn context_window(&self) -> Option<u32> {
+        context_window_for(self.model_id())
+    }
+}
+
+// Known context windows, matched in order against the lowercased model id
+const CONTEXT_WINDOWS: &[(&str, u32)] = &[
+    ("-8192", 8192),
+    ("-32768", 32768),
+    ("llama-guard", 8192),
+    ("gemma2", 8192),
+    ("gemma-3", 131072),
+    ("llama-3.1", 131072),
+    ("llama-3.2", 131072),
+    ("llama-3.3", 131072),
+    ("llama-3-", 8192),
+    ("mixtral-8x7b", 32768),
+    ("mistral-nemo", 131072),
+    ("mistral", 32768),
+    ("qwen", 131072),
+    ("qwq", 131072),
+    ("deepseek-r1-distill", 131072),
+    ("deepseek", 65536),
+    ("gemini", 1048576),
+    ("claude", 200000),
+    ("gpt-4o", 128000),
+    ("gpt-4-turbo", 128000),
+    ("command-r", 128000),
+    ("minimax-01", 1000192),
+    ("wizardlm-2", 65536),
+    ("hermes-3", 131072),
+    ("mythomax", 4096),
+    ("lumimaid", 24576),
+    ("lfm-7b", 32768),
+];
+
+pub fn context_window_for(model_id: &str) -> Option<u32> {
+    let id = model_id.to_lowercase();
+    CONTEXT_WINDOWS
+        .iter()
+        .find(|(pattern, _)| id.contains(pattern))
+        .map(|(_, window)| *window)
 }
 
 pub trait Provider {
     type ModelType: Model;
     fn provider_name() -> &'static str;
+
+    // Whether build() needs an API key (from the builder or `{PROVIDER}_API_KEY`)
+    fn requires_api_key() -> bool {
+        true
+    }
+
+    // API root used when neither the builder nor `{PROVIDER}_BASE_URL` sets one
+    fn default_base_url() -> Option<&'static str> {
+        None
+    }
+
+    // Whether streaming requests may ask for usage with `stream_options.include_usage`
+    fn supports_stream_usage() -> bool {
+        false
+    }
+
+    // What the x-ratelimit-*-requests response headers count, for the RateLimiter
+    fn request_window() -> RequestWindow {
+        RequestWindow::Minute
+    }
+
+    // Transport used when the builder is given none
+    fn default_transport() -> Arc<dyn Transport> {
+        Arc::new(ReqwestTransport::default())
+    }
 }
 
 macro_rules! define_provider_models {
@@ -12,7 +86,7 @@ macro_rules! define_provider_models {
         $(($variant:ident, $value:expr)),*
     }) => {
         // Define model enum with Debug derive
-        #[derive(Debug)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
         pub enum $enum_name {
             $($variant),*
         }
@@ -25,6 +99,15 @@ macro_rules! define_provider_models {
                 }
             }
         }
+
+        impl $enum_name {
+            // Every model of the provider, in declaration order
+            pub const ALL: &'static [$enum_name] = &[$(Self::$variant),*];
+
+            pub fn from_model_id(model_id: &str) -> Option<Self> {
+                Self::ALL.iter().copied().find(|model| model.model_id() == model_id)
+            }
+        }
     }
 }
 
@@ -0,0 +1,341 @@
+use async_stream::stream;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{self, Stream, StreamExt};
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::base::Provider;
+use super::chat::{ChatMessage, LLMClient};
+
+// Stream of (input index, result) pairs in completion order
+pub type BatchStream = Pin<Box<dyn Stream<Item = (usize, Result<String, String>)> + Send>>;
+
+// Snapshot passed to the progress callback after every finished item
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchProgress {
+    pub completed: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+// Options for LLMClient::chat_batch_with
+#[derive(Clone)]
+pub struct BatchOptions {
+    concurrency: usize,
+    max_retries: u32,
+    retry_delay: Duration,
+    fail_fast: bool,
+    on_progress: Option<Arc<dyn Fn(BatchProgress) + Send + Sync>>,
+}
+
+impl fmt::Debug for BatchOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchOptions")
+            .field("concurrency", &self.concurrency)
+            .field("max_retries", &self.max_retries)
+            .field("retry_delay", &self.retry_delay)
+            .field("fail_fast", &self.fail_fast)
+            .field("on_progress", &self.on_progress.is_some())
+            .finish()
+    }
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
+
+impl BatchOptions {
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            concurrency: concurrency.max(1),
+            max_retries: 2,
+            retry_delay: Duration::from_millis(500),
+            fail_fast: false,
+            on_progress: None,
+        }
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    // Retries for transient failures (network errors and 5xx responses). 429s are retried
+    // by the client's RateLimiter, if it has one.
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    // Delay before the first retry, doubled on every further attempt
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    // End the stream after the first failed item instead of returning partial results
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(BatchProgress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(callback));
+        self
+    }
+}
+
+fn chat_with_retry<P>(
+    client: LLMClient<P>,
+    messages: Vec<ChatMessage>,
+    max_retries: u32,
+    retry_delay: Duration,
+) -> BoxFuture<'static, Result<String, String>>
+where
+    P: Provider + Send + Sync + 'static,
+    P::ModelType: Clone + Send + Sync + 'static,
+{
+    async move {
+        let mut attempt = 0;
+        loop {
+            match client.try_chat(messages.clone()).await {
+                Err(e) if attempt < max_retries && e.is_transient() => {
+                    tokio::time::sleep(retry_delay * 2u32.pow(attempt.min(10))).await;
+                    attempt += 1;
+                }
+                result => return result.map_err(|e| e.to_string()),
+            }
+        }
+    }
+    .boxed()
+}
+
+impl<P> LLMClient<P>
+where
+    P: Provider + Send + Sync + 'static,
+    P::ModelType: Clone + Send + Sync + 'static,
+{
+    // Run many conversations through chat(), yielding (input index, result) as each completes
+    pub fn chat_batch(
+        &self,
+        inputs: Vec<Vec<ChatMessage>>,
+        concurrency: usize,
+    ) -> BatchStream {
+        self.chat_batch_with(inputs, BatchOptions::new(concurrency))
+    }
+
+    pub fn chat_batch_with(
+        &self,
+        inputs: Vec<Vec<ChatMessage>>,
+        options: BatchOptions,
+    ) -> BatchStream {
+        let client = self.clone();
+        let total = inputs.len();
+
+        Box::pin(stream! {
+            let mut pending = stream::iter(inputs.into_iter().enumerate())
+                .map(|(index, messages)| {
+                    chat_with_retry(client.clone(), messages, options.max_retries, options.retry_delay)
+                        .map(move |result| (index, result))
+                })
+                .buffer_unordered(options.concurrency);
+
+            let mut progress = BatchProgress { completed: 0, failed: 0, total };
+            while let Some((index, result)) = pending.next().await {
+                progress.completed += 1;
+                if result.is_err() {
+                    progress.failed += 1;
+                }
+                if let Some(callback) = &options.on_progress {
+                    callback(progress);
+                }
+
+                let stop = result.is_err() && options.fail_fast;
+                yield (index, result);
+                if stop {
+                    // Dropping the remaining futures cancels the in-flight requests
+                    break;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{LLMBuilder, Mock, MockModel, MockScript};
+    use parking_lot::Mutex;
+
+    fn client(model: MockModel, script: MockScript) -> LLMClient<Mock> {
+        LLMBuilder::<Mock>::new()
+            .model(model)
+            .script(script)
+            .build()
+            .unwrap()
+    }
+
+    fn prompts(texts: &[&str]) -> Vec<Vec<ChatMessage>> {
+        texts
+            .iter()
+            .map(|text| {
+                vec![ChatMessage {
+                    role: "user".to_string(),
+                    content: text.to_string(),
+                }]
+            })
+            .collect()
+    }
+
+    fn options() -> BatchOptions {
+        BatchOptions::new(1).retry_delay(Duration::from_millis(1))
+    }
+
+    async fn run(
+        client: &LLMClient<Mock>,
+        inputs: &[&str],
+        options: BatchOptions,
+    ) -> Vec<(usize, Result<String, String>)> {
+        client
+            .chat_batch_with(prompts(inputs), options)
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn results_carry_the_index_of_their_input() {
+        let client = client(MockModel::Echo, MockScript::new());
+        let inputs = ["a", "b", "c", "d", "e", "f", "g"];
+
+        let mut results = client
+            .chat_batch(prompts(&inputs), 3)
+            .collect::<Vec<_>>()
+            .await;
+
+        results.sort_by_key(|(index, _)| *index);
+        let expected: Vec<_> = inputs
+            .iter()
+            .enumerate()
+            .map(|(index, text)| (index, Ok(text.to_string())))
+            .collect();
+        assert_eq!(results, expected);
+    }
+
+    #[tokio::test]
+    async fn results_arrive_in_completion_order() {
+        let script = MockScript::new()
+            .stream(&["slow"], Duration::from_millis(200))
+            .reply("fast");
+        let client = client(MockModel::Scripted, script);
+
+        let results = run(&client, &["first", "second"], options().concurrency(2)).await;
+
+        assert_eq!(
+            results,
+            [(1, Ok("fast".to_string())), (0, Ok("slow".to_string()))]
+        );
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried() {
+        let script = MockScript::new()
+            .status(503, "overloaded")
+            .error("connection reset")
+            .reply("done");
+        let client = client(MockModel::Scripted, script.clone());
+
+        let results = run(&client, &["hi"], options()).await;
+
+        assert_eq!(results, [(0, Ok("done".to_string()))]);
+        assert_eq!(script.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn retries_give_up_after_max_retries() {
+        let script = MockScript::new()
+            .status(500, "down")
+            .status(500, "still down")
+            .reply("too late");
+        let client = client(MockModel::Scripted, script.clone());
+
+        let results = run(&client, &["hi"], options().max_retries(1)).await;
+
+        assert_eq!(
+            results,
+            [(
+                0,
+                Err("HTTP 500 Internal Server Error: still down".to_string())
+            )]
+        );
+        assert_eq!(script.remaining(), 1);
+    }
+
+    #[tokio::test]
+    async fn rate_limits_and_client_errors_are_not_retried() {
+        let script = MockScript::new()
+            .status(429, "slow down")
+            .status(400, "bad request")
+            .reply("unused")
+            .reply("unused");
+        let client = client(MockModel::Scripted, script.clone());
+
+        let results = run(&client, &["one", "two"], options()).await;
+
+        assert_eq!(
+            results,
+            [
+                (0, Err("HTTP 429 Too Many Requests: slow down".to_string())),
+                (1, Err("HTTP 400 Bad Request: bad request".to_string())),
+            ]
+        );
+        assert_eq!(script.remaining(), 2);
+    }
+
+    #[tokio::test]
+    async fn fail_fast_stops_after_the_first_failure() {
+        let script = MockScript::new()
+            .reply("one")
+            .status(400, "bad request")
+            .reply("three");
+        let client = client(MockModel::Scripted, script.clone());
+
+        let results = run(&client, &["1", "2", "3"], options().fail_fast(true)).await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0], (0, Ok("one".to_string())));
+        assert!(results[1].1.is_err());
+        assert_eq!(script.remaining(), 1);
+    }
+
+    #[tokio::test]
+    async fn progress_is_reported_after_every_item() {
+        let script = MockScript::new()
+            .reply("one")
+            .status(400, "bad request")
+            .reply("three");
+        let client = client(MockModel::Scripted, script);
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = reports.clone();
+        let options = options().on_progress(move |progress| seen.lock().push(progress));
+
+        let results = run(&client, &["1", "2", "3"], options).await;
+
+        assert_eq!(results.len(), 3);
+        let progress = |completed, failed| BatchProgress {
+            completed,
+            failed,
+            total: 3,
+        };
+        assert_eq!(
+            *reports.lock(),
+            [progress(1, 0), progress(2, 1), progress(3, 1)]
+        );
+    }
+}
@@ -0,0 +1,511 @@
+use async_stream::stream;
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use parking_lot::Mutex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::chat::LLMBuilder;
+use super::base::Provider;
+use super::transport::{HttpRequest, HttpResponse, Transport};
+
+pub const CASSETTE_VERSION: u32 = 1;
+
+// Header values never written to a cassette
+const REDACTED_HEADERS: &[&str] = &["authorization", "x-api-key", "api-key", "cookie", "set-cookie"];
+const REDACTED: &str = "[REDACTED]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    // Send requests to the provider and write every exchange to the file
+    Record,
+    // Answer from the file without touching the network
+    Replay,
+}
+
+// One chunk of a response body; kept as text when it is valid UTF-8 on its own
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Chunk {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Chunk {
+    fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => Chunk::Text(text),
+            Err(e) => Chunk::Bytes(e.into_bytes()),
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Chunk::Text(text) => text.into_bytes(),
+            Chunk::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RecordedRequest {
+    url: String,
+    headers: Vec<(String, String)>,
+    // The JSON request body, or the raw text if it was not JSON
+    body: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    chunks: Vec<Chunk>,
+    // Set when the body stream failed after `chunks`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    // None when the request itself failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<RecordedResponse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CassetteFile {
+    version: u32,
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Serialize)]
+struct CassetteFileRef<'a> {
+    version: u32,
+    interactions: &'a [Interaction],
+}
+
+#[derive(Debug, Default)]
+struct CassetteState {
+    interactions: Vec<Interaction>,
+    // Interactions already handed out during replay
+    used: Vec<bool>,
+}
+
+// A fixture file of recorded HTTP exchanges; clones share the same recording
+#[derive(Debug, Clone)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    state: Arc<Mutex<CassetteState>>,
+}
+
+impl Cassette {
+    // Start a new recording; the file is only written by save()
+    pub fn record<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            mode: CassetteMode::Record,
+            state: Arc::new(Mutex::new(CassetteState::default())),
+        }
+    }
+
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read cassette {}: {}", path.display(), e))?;
+        let file: CassetteFile = serde_json::from_str(&data)
+            .map_err(|e| format!("Invalid cassette {}: {}", path.display(), e))?;
+        if file.version > CASSETTE_VERSION {
+            return Err(format!(
+                "Cassette version {} is newer than the supported version {}",
+                file.version, CASSETTE_VERSION
+            ));
+        }
+
+        let used = vec![false; file.interactions.len()];
+        Ok(Self {
+            path: path.to_path_buf(),
+            mode: CassetteMode::Replay,
+            state: Arc::new(Mutex::new(CassetteState {
+                interactions: file.interactions,
+                used,
+            })),
+        })
+    }
+
+    // Replay the file if it exists, otherwise record it until save()
+    pub fn auto<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        if path.as_ref().exists() {
+            Self::replay(path)
+        } else {
+            Ok(Self::record(path))
+        }
+    }
+
+    pub fn get_mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().interactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Recorded exchanges not yet replayed
+    pub fn remaining(&self) -> usize {
+        self.state.lock().used.iter().filter(|used| !**used).count()
+    }
+
+    // Write the recorded exchanges to the file, replacing it; does nothing while replaying.
+    // A response still being streamed is saved with the chunks received so far.
+    pub fn save(&self) -> Result<(), String> {
+        if self.mode == CassetteMode::Replay {
+            return Ok(());
+        }
+
+        let data = {
+            let state = self.state.lock();
+            let file = CassetteFileRef {
+                version: CASSETTE_VERSION,
+                interactions: &state.interactions,
+            };
+            serde_json::to_string_pretty(&file).map_err(|e| format!("Failed to serialize cassette: {}", e))?
+        };
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        fs::write(&self.path, data)
+            .map_err(|e| format!("Failed to write cassette {}: {}", self.path.display(), e))
+    }
+
+    // Returns the index of the new interaction
+    fn push(&self, interaction: Interaction) -> usize {
+        let mut state = self.state.lock();
+        state.interactions.push(interaction);
+        state.used.push(true);
+        state.interactions.len() - 1
+    }
+
+    // Update a recorded response while its body streams in
+    fn update_response<F: FnOnce(&mut RecordedResponse)>(&self, index: usize, f: F) {
+        if let Some(response) = self.state.lock().interactions[index].response.as_mut() {
+            f(response);
+        }
+    }
+
+    // First unused recording of the same request; JSON bodies match regardless of key order
+    fn take(&self, request: &RecordedRequest) -> Option<Interaction> {
+        let mut state = self.state.lock();
+        let index = (0..state.interactions.len()).find(|&index| {
+            let recorded = &state.interactions[index].request;
+            !state.used[index] && recorded.url == request.url && recorded.body == request.body
+        })?;
+        state.used[index] = true;
+        Some(state.interactions[index].clone())
+    }
+}
+
+impl<P: Provider> LLMBuilder<P> {
+    // Record to or replay from `cassette` instead of talking to the provider directly.
+    // No API key is needed while replaying.
+    pub fn cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+}
+
+fn redact(headers: impl Iterator<Item = (String, String)>) -> Vec<(String, String)> {
+    headers
+        .map(|(name, value)| {
+            if REDACTED_HEADERS.contains(&name.to_lowercase().as_str()) {
+                (name, REDACTED.to_string())
+            } else {
+                (name, value)
+            }
+        })
+        .collect()
+}
+
+fn record_request(request: &HttpRequest) -> RecordedRequest {
+    let body = serde_json::from_slice(&request.body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&request.body).into_owned()));
+    RecordedRequest {
+        url: request.url.clone(),
+        headers: redact(request.headers.iter().cloned()),
+        body,
+    }
+}
+
+fn header_map(headers: &[(String, String)]) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name.as_str()), HeaderValue::from_str(value)) {
+            map.append(name, value);
+        }
+    }
+    map
+}
+
+// Response being streamed to the client while it is recorded. Chunks go into the cassette
+// as they arrive, so a body the client stops reading early is still kept.
+struct Recording {
+    cassette: Cassette,
+    index: usize,
+}
+
+impl Recording {
+    fn chunk(&self, bytes: &[u8]) {
+        let chunk = Chunk::from_bytes(bytes.to_vec());
+        self.cassette.update_response(self.index, |response| response.chunks.push(chunk));
+    }
+
+    fn fail(&self, error: &str) {
+        self.cassette.update_response(self.index, |response| response.error = Some(error.to_string()));
+    }
+}
+
+// Transport that records the exchanges of `inner`, or replays them from the cassette
+#[derive(Debug)]
+pub(crate) struct CassetteTransport {
+    cassette: Cassette,
+    inner: Arc<dyn Transport>,
+}
+
+impl CassetteTransport {
+    pub(crate) fn new(cassette: Cassette, inner: Arc<dyn Transport>) -> Self {
+        Self { cassette, inner }
+    }
+
+    fn replay(&self, request: RecordedRequest) -> Result<HttpResponse, String> {
+        let interaction = self.cassette.take(&request).ok_or_else(|| {
+            format!(
+                "No recorded interaction in {} matches the request to {}",
+                self.cassette.path.display(),
+                request.url
+            )
+        })?;
+
+        let response = match (interaction.response, interaction.error) {
+            (Some(response), _) => response,
+            (None, error) => return Err(error.unwrap_or_else(|| "Recorded request failed".to_string())),
+        };
+        let status = StatusCode::from_u16(response.status).map_err(|e| e.to_string())?;
+        let chunks = response.chunks;
+        let error = response.error;
+
+        Ok(HttpResponse {
+            status,
+            headers: header_map(&response.headers),
+            body: Box::pin(stream! {
+                for chunk in chunks {
+                    yield Ok(chunk.into_bytes());
+                }
+                if let Some(error) = error {
+                    yield Err(error);
+                }
+            }),
+        })
+    }
+
+    async fn record(&self, http_request: HttpRequest, request: RecordedRequest) -> Result<HttpResponse, String> {
+        let response = match self.inner.send(http_request).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.cassette.push(Interaction {
+                    request,
+                    response: None,
+                    error: Some(e.clone()),
+                });
+                return Err(e);
+            }
+        };
+
+        let headers = response
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())));
+        let index = self.cassette.push(Interaction {
+            request,
+            response: Some(RecordedResponse {
+                status: response.status.as_u16(),
+                headers: redact(headers),
+                chunks: Vec::new(),
+                error: None,
+            }),
+            error: None,
+        });
+        let recording = Recording {
+            cassette: self.cassette.clone(),
+            index,
+        };
+
+        let mut body = response.body;
+        Ok(HttpResponse {
+            status: response.status,
+            headers: response.headers,
+            body: Box::pin(stream! {
+                while let Some(item) = body.next().await {
+                    match item {
+                        Ok(bytes) => {
+                            recording.chunk(&bytes);
+                            yield Ok(bytes);
+                        }
+                        Err(e) => {
+                            recording.fail(&e);
+                            yield Err(e);
+                            break;
+                        }
+                    }
+                }
+            }),
+        })
+    }
+}
+
+#[async_trait]
+impl Transport for CassetteTransport {
+    async fn send(&self, http_request: HttpRequest) -> Result<HttpResponse, String> {
+        let request = record_request(&http_request);
+        match self.cassette.mode {
+            CassetteMode::Replay => self.replay(request),
+            CassetteMode::Record => self.record(http_request, request).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Answers every request with the same chunks, and counts the requests
+    #[derive(Debug, Default)]
+    struct Fixed {
+        chunks: Vec<Vec<u8>>,
+        sent: Mutex<usize>,
+    }
+
+    #[async_trait]
+    impl Transport for Fixed {
+        async fn send(&self, _request: HttpRequest) -> Result<HttpResponse, String> {
+            *self.sent.lock() += 1;
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "set-cookie",
+                HeaderValue::from_static("session=secret-session"),
+            );
+            headers.insert("x-request-id", HeaderValue::from_static("req-1"));
+            let chunks = self.chunks.clone();
+            Ok(HttpResponse {
+                status: StatusCode::OK,
+                headers,
+                body: Box::pin(futures::stream::iter(chunks.into_iter().map(Ok))),
+            })
+        }
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "babel-cassette-{:016x}.json",
+            rand::random::<u64>()
+        ))
+    }
+
+    fn request() -> HttpRequest {
+        HttpRequest {
+            url: "https://api.example.com/v1/chat/completions".to_string(),
+            headers: vec![
+                (
+                    "Authorization".to_string(),
+                    "Bearer secret-token".to_string(),
+                ),
+                ("x-api-key".to_string(), "secret-key".to_string()),
+                ("api-key".to_string(), "secret-azure-key".to_string()),
+                ("Cookie".to_string(), "session=secret-cookie".to_string()),
+                ("Content-Type".to_string(), "application/json".to_string()),
+            ],
+            body: br#"{"model":"m","messages":[]}"#.to_vec(),
+        }
+    }
+
+    async fn chunks(transport: &CassetteTransport) -> Vec<Vec<u8>> {
+        let response = transport.send(request()).await.unwrap();
+        response.body.map(Result::unwrap).collect().await
+    }
+
+    #[tokio::test]
+    async fn recordings_redact_secrets_and_replay_the_same_chunks() {
+        // "é" is split between two chunks, so neither of them is valid UTF-8
+        let sent = vec![
+            b"data: caf\xc3".to_vec(),
+            b"\xa9\n\n".to_vec(),
+            b"data: [DONE]\n\n".to_vec(),
+        ];
+        let inner = Arc::new(Fixed {
+            chunks: sent.clone(),
+            ..Fixed::default()
+        });
+        let path = temp_path();
+
+        let cassette = Cassette::record(&path);
+        let recorder = CassetteTransport::new(cassette.clone(), inner.clone());
+        assert_eq!(chunks(&recorder).await, sent);
+        assert!(!path.exists());
+        cassette.save().unwrap();
+
+        let data = fs::read_to_string(&path).unwrap();
+        let replayer = CassetteTransport::new(Cassette::replay(&path).unwrap(), inner.clone());
+        let replayed = chunks(&replayer).await;
+        let second = replayer.send(request()).await;
+        fs::remove_file(&path).unwrap();
+
+        assert!(!data.contains("secret"), "{}", data);
+        assert_eq!(data.matches(REDACTED).count(), 5);
+        assert!(data.contains("application/json") && data.contains("req-1"));
+        let file: Value = serde_json::from_str(&data).unwrap();
+        let recorded = &file["interactions"][0]["response"]["chunks"];
+        assert!(recorded[0].is_array() && recorded[1].is_array() && recorded[2].is_string());
+
+        assert_eq!(replayed, sent);
+        assert_eq!(*inner.sent.lock(), 1);
+        assert!(second.is_err());
+    }
+
+    #[tokio::test]
+    async fn bodies_read_only_partly_are_saved_as_far_as_they_were_read() {
+        let inner = Arc::new(Fixed {
+            chunks: vec![b"one".to_vec(), b"two".to_vec()],
+            ..Fixed::default()
+        });
+        let path = temp_path();
+        let cassette = Cassette::record(&path);
+        let recorder = CassetteTransport::new(cassette.clone(), inner);
+
+        let mut body = recorder.send(request()).await.unwrap().body;
+        assert_eq!(body.next().await, Some(Ok(b"one".to_vec())));
+        drop(body);
+        cassette.save().unwrap();
+
+        let replayer =
+            CassetteTransport::new(Cassette::replay(&path).unwrap(), Arc::new(Fixed::default()));
+        let replayed = chunks(&replayer).await;
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(replayed, [b"one".to_vec()]);
+    }
+}
@@ -1,15 +1,22 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::pin::Pin;
 use futures::stream::Stream;
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use tokio_stream::StreamExt;
 use tracing::error;
 use dotenv::dotenv;
 use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
 use async_stream::stream;
 
 use super::base::Provider;
 use super::base::Model;
+use super::ratelimit::{parse_reset, RateLimiter};
+use super::transport::{HttpRequest, ReqwestTransport, Transport};
+use super::cassette::{Cassette, CassetteMode, CassetteTransport};
+use crate::tokens::count_messages;
 
 // Chat message structure
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -18,6 +25,44 @@ pub struct ChatMessage {
     pub content: String,
 }
 
+// Why a completion failed; callers of the public API get its message
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ChatError {
+    // The request could not be built
+    Invalid(String),
+    // No response arrived, e.g. a connection error
+    Transport(String),
+    // The provider answered with an error status
+    Status(StatusCode, String),
+    // The response body broke off
+    Stream(String),
+    // A chunk of the response was not valid
+    Parse(String),
+}
+
+impl ChatError {
+    // Worth another attempt: transport failures and server errors. 429s are left to the
+    // RateLimiter, which already waits them out.
+    pub(crate) fn is_transient(&self) -> bool {
+        match self {
+            ChatError::Transport(_) | ChatError::Stream(_) => true,
+            ChatError::Status(status, _) => status.is_server_error(),
+            ChatError::Invalid(_) | ChatError::Parse(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for ChatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChatError::Invalid(message) | ChatError::Parse(message) => write!(f, "{}", message),
+            ChatError::Transport(e) => write!(f, "Request error: {}", e),
+            ChatError::Status(status, body) => write!(f, "HTTP {}: {}", status, body),
+            ChatError::Stream(e) => write!(f, "Error reading stream: {}", e),
+        }
+    }
+}
+
 // Chat request structure
 #[derive(Serialize, Debug)]
 struct ChatRequest {
@@ -26,31 +71,30 @@ struct ChatRequest {
     stream: bool,
     temperature: f32,
     max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+// Ask for token usage in the final chunk of the stream
+#[derive(Serialize, Debug)]
+struct StreamOptions {
+    include_usage: bool,
 }
 
 // Response structures
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct Usage {
     pub prompt_tokens: Option<u32>,
     pub completion_tokens: Option<u32>,
     pub total_tokens: Option<u32>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct Delta {
     // pub role: Option<String>,
     pub content: Option<String>,
 }
 
-impl Default for Delta {
-    fn default() -> Self {
-        Self {
-            // role: None,
-            content: None,
-        }
-    }
-}
-
 #[derive(Debug, Deserialize)]
 pub struct Choice {
     // pub index: Option<u32>,
@@ -61,6 +105,12 @@ pub struct Choice {
     // pub native_finish_reason: Option<String>,
 }
 
+// Groq reports usage in its own extension field
+#[derive(Debug, Deserialize)]
+struct GroqExtension {
+    usage: Option<Usage>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct StreamResponse {
     // id: String,
@@ -68,6 +118,7 @@ pub struct StreamResponse {
     // model: String,
     choices: Vec<Choice>,
     usage: Option<Usage>,
+    x_groq: Option<GroqExtension>,
 }
 
 impl StreamResponse {
@@ -79,7 +130,9 @@ impl StreamResponse {
     }
 
     pub fn get_usage(&self) -> Option<Usage> {
-        self.usage.clone()
+        self.usage
+            .clone()
+            .or_else(|| self.x_groq.as_ref().and_then(|groq| groq.usage.clone()))
     }
 
     pub fn is_finished(&self) -> bool {
@@ -98,6 +151,16 @@ pub struct LLMBuilder<P: Provider> {
     max_tokens: Option<u32>,
     temperature: Option<f32>,
     system_prompt: Option<String>,
+    rate_limiter: Option<RateLimiter>,
+    base_url: Option<String>,
+    pub(crate) transport: Option<Arc<dyn Transport>>,
+    pub(crate) cassette: Option<Cassette>,
+}
+
+impl<P: Provider> Default for LLMBuilder<P> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<P: Provider> LLMBuilder<P> {
@@ -108,6 +171,10 @@ impl<P: Provider> LLMBuilder<P> {
             max_tokens: None,
             temperature: None,
             system_prompt: Some("You are a helpful AI assistant.".to_string()),
+            rate_limiter: None,
+            base_url: None,
+            transport: None,
+            cassette: None,
         }
     }
     
@@ -135,6 +202,28 @@ impl<P: Provider> LLMBuilder<P> {
         self.system_prompt = Some(prompt);
         self
     }
+
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    // API root to send requests to instead of the provider's, e.g. "http://127.0.0.1:8080/v1".
+    // Defaults to `{PROVIDER}_BASE_URL` when that is set.
+    pub fn base_url(mut self, url: String) -> Self {
+        self.base_url = Some(url);
+        self
+    }
+
+    // Send requests through an existing reqwest Client and share its connection pool
+    pub fn http_client(self, client: Client) -> Self {
+        self.transport(ReqwestTransport::new(client))
+    }
+
+    pub fn transport<T: Transport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
     
     pub fn build(self) -> Result<LLMClient<P>, String> {
         // Load environment variables
@@ -142,9 +231,15 @@ impl<P: Provider> LLMBuilder<P> {
         
         let model = self.model.ok_or("Model is required".to_string())?;
         
+        let replaying = self
+            .cassette
+            .as_ref()
+            .is_some_and(|cassette| cassette.get_mode() == CassetteMode::Replay);
+        
         // Try to get API key from environment if not provided
         let api_key = match self.api_key {
             Some(key) => key,
+            None if !P::requires_api_key() || replaying => String::new(),
             None => {
                 let env_var = format!("{}_API_KEY", P::provider_name().to_uppercase());
                 std::env::var(&env_var)
@@ -152,13 +247,28 @@ impl<P: Provider> LLMBuilder<P> {
             }
         };
         
+        let base_url = self.base_url.or_else(|| {
+            std::env::var(format!("{}_BASE_URL", P::provider_name().to_uppercase())).ok()
+        });
+        
+        let transport = match self.transport {
+            Some(transport) => transport,
+            None => P::default_transport(),
+        };
+        let transport: Arc<dyn Transport> = match self.cassette {
+            Some(cassette) => Arc::new(CassetteTransport::new(cassette, transport)),
+            None => transport,
+        };
+        
         Ok(LLMClient {
             model,
             api_key,
             max_tokens: self.max_tokens.unwrap_or(1024),
             temperature: self.temperature.unwrap_or(0.7),
             system_prompt: self.system_prompt,
-            client: Client::new(),
+            base_url,
+            transport,
+            rate_limiter: self.rate_limiter,
             _provider: PhantomData,
         })
     }
@@ -172,10 +282,32 @@ pub struct LLMClient<P: Provider> {
     max_tokens: u32,
     temperature: f32,
     system_prompt: Option<String>,
-    client: Client,
+    base_url: Option<String>,
+    transport: Arc<dyn Transport>,
+    rate_limiter: Option<RateLimiter>,
     _provider: PhantomData<P>,
 }
 
+// Clones share the transport (and its connection pool) and the rate limiter
+impl<P: Provider> Clone for LLMClient<P>
+where
+    P::ModelType: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            model: self.model.clone(),
+            api_key: self.api_key.clone(),
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
+            system_prompt: self.system_prompt.clone(),
+            base_url: self.base_url.clone(),
+            transport: self.transport.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            _provider: PhantomData,
+        }
+    }
+}
+
 impl<P: Provider> LLMClient<P> {
     pub fn get_model_id(&self) -> &'static str {
         self.model.model_id()
@@ -188,12 +320,29 @@ impl<P: Provider> LLMClient<P> {
     pub fn get_system_prompt(&self) -> Option<String> {
         self.system_prompt.clone()
     }
+
+    pub fn get_rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
     
-    // Stream chat implementation
-    pub async fn stream_chat(
-        &self,
-        history: Vec<ChatMessage>,
-    ) -> Pin<Box<dyn Stream<Item = Result<StreamResponse, String>> + Send>> {
+    pub fn get_model(&self) -> &P::ModelType {
+        &self.model
+    }
+    
+    pub fn get_max_tokens(&self) -> u32 {
+        self.max_tokens
+    }
+    
+    pub fn get_temperature(&self) -> f32 {
+        self.temperature
+    }
+    
+    pub fn get_base_url(&self) -> Option<&str> {
+        self.base_url.as_deref()
+    }
+    
+    // The history with the system prompt in front, if there is one
+    fn with_system_prompt(&self, history: Vec<ChatMessage>) -> Vec<ChatMessage> {
         let mut messages = Vec::new();
         
         // Add system prompt if available
@@ -206,20 +355,46 @@ impl<P: Provider> LLMClient<P> {
         
         // Add chat history
         messages.extend(history);
-        
-        let client = self.client.clone();
+        messages
+    }
+    
+    // Stream chat implementation
+    pub async fn stream_chat(
+        &self,
+        history: Vec<ChatMessage>,
+    ) -> Pin<Box<dyn Stream<Item = Result<StreamResponse, String>> + Send>> {
+        self.stream_messages(self.with_system_prompt(history)).await
+    }
+    
+    // Stream a completion for exactly these messages, without adding the system prompt
+    pub async fn stream_messages(
+        &self,
+        messages: Vec<ChatMessage>,
+    ) -> Pin<Box<dyn Stream<Item = Result<StreamResponse, String>> + Send>> {
+        let stream = self.completion_stream(messages);
+        Box::pin(stream.map(|result| result.map_err(|e| e.to_string())))
+    }
+    
+    // stream_messages with typed errors
+    fn completion_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+    ) -> Pin<Box<dyn Stream<Item = Result<StreamResponse, ChatError>> + Send>> {
+        let transport = self.transport.clone();
         let model_id = self.model.model_id().to_string();
         let api_key = self.api_key.clone();
         let temperature = self.temperature;
         let max_tokens = Some(self.max_tokens);
+        // Budget for the prompt plus the largest completion we may get back
+        let estimated_tokens = count_messages(&messages, &self.model) as u32 + self.max_tokens;
+        let rate_limiter = self.rate_limiter.clone();
+        let stream_usage = P::supports_stream_usage();
         
         // Build base URL based on provider
-        let base_url = match P::provider_name() {
-            "groq" => "https://api.groq.com/openai/v1/chat/completions".to_string(),
-            "openrouter" => "https://openrouter.ai/api/v1/chat/completions".to_string(),
-            "sambanova" => "https://api.sambanova.ai/v1/chat/completions".to_string(),
-            _ => return Box::pin(stream! {
-                yield Err("Unsupported provider".to_string());
+        let base_url = match self.base_url.as_deref().or(P::default_base_url()) {
+            Some(root) => format!("{}/chat/completions", root.trim_end_matches('/')),
+            None => return Box::pin(stream! {
+                yield Err(ChatError::Invalid("Unsupported provider".to_string()));
             }),
         };
         
@@ -231,20 +406,60 @@ impl<P: Provider> LLMClient<P> {
                 stream: true,
                 temperature,
                 max_tokens,
+                // Only valid on streaming requests, and only sent to providers that accept it
+                stream_options: stream_usage.then_some(StreamOptions { include_usage: true }),
+            };
+            let http_request = match serde_json::to_vec(&request) {
+                Ok(body) => HttpRequest {
+                    url: base_url,
+                    headers: vec![
+                        ("Authorization".to_string(), format!("Bearer {}", api_key)),
+                        ("Content-Type".to_string(), "application/json".to_string()),
+                    ],
+                    body,
+                },
+                Err(e) => {
+                    yield Err(ChatError::Invalid(format!("Failed to serialize request: {}", e)));
+                    return;
+                }
             };
             
-            // Send request
-            let response = client
-                .post(&base_url)
-                .header("Authorization", format!("Bearer {}", api_key))
-                .header("Content-Type", "application/json")
-                .json(&request)
-                .send()
-                .await;
+            let mut attempt = 0;
+            let response = loop {
+                if let Some(limiter) = &rate_limiter {
+                    limiter.acquire(&request.model, estimated_tokens).await;
+                }
+                
+                // Send request
+                let response = transport.send(http_request.clone()).await;
+                
+                // On 429, wait out the provider's limit instead of failing
+                if let (Some(limiter), Ok(res)) = (&rate_limiter, &response) {
+                    limiter.update_from_headers(&request.model, P::request_window(), &res.headers);
+                    if res.status == StatusCode::TOO_MANY_REQUESTS && attempt < limiter.get_max_retries() {
+                        let retry_after = res
+                            .headers
+                            .get("retry-after")
+                            .and_then(|value| value.to_str().ok())
+                            .and_then(parse_reset)
+                            .unwrap_or(Duration::from_secs(1 << attempt.min(5)));
+                        limiter.defer(&request.model, retry_after);
+                        attempt += 1;
+                        continue;
+                    }
+                }
+                break response;
+            };
                 
             match response {
+                Ok(res) if !res.status.is_success() => {
+                    let status = res.status;
+                    let body = res.text().await.unwrap_or_default();
+                    error!("Request failed with {}: {}", status, body);
+                    yield Err(ChatError::Status(status, body));
+                }
                 Ok(res) => {
-                    let mut stream = res.bytes_stream();
+                    let mut stream = res.body;
                     let mut buffer = String::new();
                     
                     while let Some(item) = stream.next().await {
@@ -262,14 +477,14 @@ impl<P: Provider> LLMClient<P> {
                                         let data = line.replacen("data: ", "", 1);
                                         match serde_json::from_str::<StreamResponse>(&data) {
                                             Ok(response) => yield Ok(response),
-                                            Err(e) => yield Err(format!("Failed to parse response: {}", e)),
+                                            Err(e) => yield Err(ChatError::Parse(format!("Failed to parse response: {}", e))),
                                         }
                                     }
                                 }
                             }
                             Err(e) => {
                                 error!("Error reading stream: {}", e);
-                                yield Err(format!("Error reading stream: {}", e));
+                                yield Err(ChatError::Stream(e));
                                 break;
                             }
                         }
@@ -280,13 +495,13 @@ impl<P: Provider> LLMClient<P> {
                         let data = buffer.replacen("data: ", "", 1);
                         match serde_json::from_str::<StreamResponse>(&data) {
                             Ok(response) => yield Ok(response),
-                            Err(e) => yield Err(format!("Failed to parse final response: {}", e)),
+                            Err(e) => yield Err(ChatError::Parse(format!("Failed to parse final response: {}", e))),
                         }
                     }
                 }
                 Err(e) => {
                     error!("Request error: {}", e);
-                    yield Err(format!("Request error: {}", e));
+                    yield Err(ChatError::Transport(e));
                 }
             }
         })
@@ -294,20 +509,94 @@ impl<P: Provider> LLMClient<P> {
     
     // Non-streaming chat implementation
     pub async fn chat(&self, history: Vec<ChatMessage>) -> Result<String, String> {
-        let mut response_text = String::new();
-        let mut stream = self.stream_chat(history).await;
-        
-        while let Some(result) = stream.next().await {
-            match result {
-                Ok(response) => {
-                    if let Some(content) = response.get_content() {
-                        response_text.push_str(&content);
-                    }
+        collect_response(self.stream_chat(history).await).await
+    }
+    
+    // chat with typed errors, e.g. to decide whether to retry
+    pub(crate) async fn try_chat(&self, history: Vec<ChatMessage>) -> Result<String, ChatError> {
+        collect_response(self.completion_stream(self.with_system_prompt(history))).await
+    }
+    
+    // Non-streaming counterpart of stream_messages
+    pub async fn chat_messages(&self, messages: Vec<ChatMessage>) -> Result<String, String> {
+        collect_response(self.stream_messages(messages).await).await
+    }
+}
+
+// Concatenate the content of every chunk, stopping at the first error
+async fn collect_response<E>(
+    mut stream: Pin<Box<dyn Stream<Item = Result<StreamResponse, E>> + Send>>,
+) -> Result<String, E> {
+    let mut response_text = String::new();
+    
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(response) => {
+                if let Some(content) = response.get_content() {
+                    response_text.push_str(&content);
                 }
-                Err(e) => return Err(e),
             }
+            Err(e) => return Err(e),
         }
-        
-        Ok(response_text)
     }
-}
\ No newline at end of file
+    
+    Ok(response_text)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::HttpResponse;
+    use crate::model::{Groq, GroqModel, OpenRouter, OpenRouterModel};
+    use async_trait::async_trait;
+    use parking_lot::Mutex;
+    use serde_json::Value;
+
+    // Keeps the body of the last request and fails it
+    #[derive(Debug, Clone, Default)]
+    struct Capture {
+        body: Arc<Mutex<Option<Value>>>,
+    }
+
+    #[async_trait]
+    impl Transport for Capture {
+        async fn send(&self, request: HttpRequest) -> Result<HttpResponse, String> {
+            *self.body.lock() = serde_json::from_slice(&request.body).ok();
+            Err("captured".to_string())
+        }
+    }
+
+    async fn request_body<P: Provider>(builder: LLMBuilder<P>) -> Value {
+        let capture = Capture::default();
+        let client = builder
+            .api_key("key".to_string())
+            .transport(capture.clone())
+            .build()
+            .unwrap();
+        let message = ChatMessage {
+            role: "user".to_string(),
+            content: "Hi".to_string(),
+        };
+        let _ = collect_response(client.stream_messages(vec![message]).await).await;
+        let body = capture.body.lock().take();
+        body.expect("no request was sent")
+    }
+
+    #[tokio::test]
+    async fn stream_usage_is_requested_from_providers_that_support_it() {
+        let builder = LLMBuilder::<OpenRouter>::new().model(OpenRouterModel::MetaLlama3370BInstruct);
+        let body = request_body(builder).await;
+
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["stream_options"]["include_usage"], true);
+    }
+
+    #[tokio::test]
+    async fn stream_options_are_not_sent_to_other_providers() {
+        let builder = LLMBuilder::<Groq>::new().model(GroqModel::Llama33_70bVersatile);
+        let body = request_body(builder).await;
+
+        assert_eq!(body["stream"], true);
+        assert!(body.get("stream_options").is_none());
+    }
+
+}
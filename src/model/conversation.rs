@@ -0,0 +1,977 @@
+use async_stream::stream;
+use futures::stream::Stream;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio_stream::StreamExt;
+use tracing::error;
+
+use super::base::{Model, Provider};
+use super::chat::{ChatMessage, LLMClient, StreamResponse, Usage};
+use super::memory::{Memory, SlidingWindow};
+use crate::store::{now_millis, ConversationStore};
+use crate::tokens::{count_messages_for, ModelFamily};
+
+// Version of the JSON and JSONL formats written by this crate
+pub const TRANSCRIPT_VERSION: u32 = 1;
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+// A message in the history; pinned messages survive context trimming
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Turn {
+    #[serde(flatten)]
+    pub message: ChatMessage,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pinned: bool,
+    // Written by a summarizing memory in place of older turns
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub summary: bool,
+    // Milliseconds since the Unix epoch
+    #[serde(default)]
+    pub timestamp: u64,
+    // Model that produced an assistant reply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    // Already appended to the JSONL transcript
+    #[serde(skip)]
+    pub(crate) recorded: bool,
+    // Already appended to the conversation store
+    #[serde(skip)]
+    pub(crate) stored: bool,
+}
+
+impl Turn {
+    pub fn new(message: ChatMessage) -> Self {
+        Self {
+            message,
+            pinned: false,
+            summary: false,
+            timestamp: now_millis(),
+            model: None,
+            usage: None,
+            recorded: false,
+            stored: false,
+        }
+    }
+}
+
+// JSONL transcript the conversation appends new turns to
+#[derive(Debug, Clone)]
+struct Transcript {
+    path: PathBuf,
+    system_prompt: Option<String>,
+}
+
+// Chat history with its system prompt, kept within the model's context window
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    system_prompt: Option<String>,
+    turns: Vec<Turn>,
+    context_window: Option<u32>,
+    // Tokenizer family of the last model the conversation was sent to
+    family: ModelFamily,
+    memory: Arc<dyn Memory>,
+    transcript: Option<Transcript>,
+    // Id in the conversation store, once created there
+    id: Option<String>,
+    metadata: BTreeMap<String, String>,
+    store: Option<Arc<dyn ConversationStore>>,
+    // System prompt or metadata changed since the last save to the store
+    header_changed: bool,
+    // History cleared since the last save, so the stored turns must go too
+    turns_cleared: bool,
+}
+
+impl Default for Conversation {
+    fn default() -> Self {
+        Self {
+            system_prompt: None,
+            turns: Vec::new(),
+            context_window: None,
+            family: ModelFamily::Other,
+            memory: Arc::new(SlidingWindow),
+            transcript: None,
+            id: None,
+            metadata: BTreeMap::new(),
+            store: None,
+            header_changed: false,
+            turns_cleared: false,
+        }
+    }
+}
+
+impl Conversation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // How the history is shrunk when it outgrows the context window
+    pub fn memory<M: Memory + 'static>(mut self, memory: M) -> Self {
+        self.memory = Arc::new(memory);
+        self
+    }
+
+    pub fn system_prompt(mut self, prompt: String) -> Self {
+        self.system_prompt = Some(prompt);
+        self
+    }
+
+    // Override the context window, otherwise taken from the client's model
+    pub fn context_window(mut self, tokens: u32) -> Self {
+        self.context_window = Some(tokens);
+        self
+    }
+
+    // Free-form metadata, e.g. the user a conversation belongs to; searchable in stores
+    pub fn metadata(mut self, key: String, value: String) -> Self {
+        self.metadata.insert(key, value);
+        self
+    }
+
+    pub fn get_system_prompt(&self) -> Option<&str> {
+        self.system_prompt.as_deref()
+    }
+
+    pub fn set_system_prompt(&mut self, prompt: Option<String>) {
+        self.system_prompt = prompt;
+        self.header_changed = true;
+    }
+
+    pub fn get_context_window(&self) -> Option<u32> {
+        self.context_window
+    }
+
+    pub fn get_metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
+    pub fn set_metadata(&mut self, key: String, value: String) {
+        self.metadata.insert(key, value);
+        self.header_changed = true;
+    }
+
+    pub fn remove_metadata(&mut self, key: &str) -> Option<String> {
+        self.header_changed = true;
+        self.metadata.remove(key)
+    }
+
+    pub fn get_id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn turns(&self) -> &[Turn] {
+        &self.turns
+    }
+
+    pub fn turns_mut(&mut self) -> &mut Vec<Turn> {
+        &mut self.turns
+    }
+
+    pub fn len(&self) -> usize {
+        self.turns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+
+    // Remove the history, keeping the system prompt; stored turns are removed on the next save
+    pub fn clear(&mut self) {
+        self.turns.clear();
+        self.turns_cleared = self.id.is_some();
+    }
+
+    pub fn push(&mut self, message: ChatMessage) {
+        self.turns.push(Turn::new(message));
+    }
+
+    pub fn push_pinned(&mut self, message: ChatMessage) {
+        self.turns.push(Turn {
+            pinned: true,
+            ..Turn::new(message)
+        });
+    }
+
+    pub fn push_turn(&mut self, turn: Turn) {
+        self.turns.push(turn);
+    }
+
+    pub fn push_user(&mut self, content: String) {
+        self.push(ChatMessage {
+            role: "user".to_string(),
+            content,
+        });
+    }
+
+    pub fn push_assistant(&mut self, content: String) {
+        self.push(ChatMessage {
+            role: "assistant".to_string(),
+            content,
+        });
+    }
+
+    pub fn pin(&mut self, index: usize) {
+        if let Some(turn) = self.turns.get_mut(index) {
+            turn.pinned = true;
+        }
+    }
+
+    pub fn unpin(&mut self, index: usize) {
+        if let Some(turn) = self.turns.get_mut(index) {
+            turn.pinned = false;
+        }
+    }
+
+    // The system prompt followed by the history, as sent to the provider
+    pub fn messages(&self) -> Vec<ChatMessage> {
+        let mut messages = Vec::with_capacity(self.turns.len() + 1);
+        if let Some(system_prompt) = &self.system_prompt {
+            messages.push(ChatMessage {
+                role: "system".to_string(),
+                content: system_prompt.clone(),
+            });
+        }
+        messages.extend(self.turns.iter().map(|turn| turn.message.clone()));
+        messages
+    }
+
+    pub fn estimated_tokens(&self) -> u32 {
+        count_messages_for(&self.messages(), self.family) as u32
+    }
+
+    // Drop the oldest unpinned turns until the estimate fits the budget.
+    // The latest message is always kept. Returns the number of messages removed.
+    pub fn trim_to(&mut self, max_tokens: u32) -> usize {
+        let mut removed = 0;
+
+        while self.estimated_tokens() > max_tokens {
+            let last = self.turns.len().saturating_sub(1);
+            let Some(index) = self.turns[..last].iter().position(|turn| !turn.pinned) else {
+                break;
+            };
+
+            // A user message goes together with the assistant reply that answered it
+            let pair = self.turns[index].message.role == "user"
+                && index + 1 < last
+                && !self.turns[index + 1].pinned
+                && self.turns[index + 1].message.role == "assistant";
+
+            self.turns.remove(index);
+            removed += 1;
+            if pair {
+                self.turns.remove(index);
+                removed += 1;
+            }
+        }
+
+        removed
+    }
+
+    // Let the memory shrink the history so the prompt plus the client's max_tokens fit
+    async fn fit_to<P: Provider>(&mut self, client: &LLMClient<P>) -> Result<(), String> {
+        self.family = ModelFamily::from_model_id(client.get_model_id());
+        let window = self
+            .context_window
+            .or_else(|| client.get_model().context_window());
+        if let Some(window) = window {
+            let memory = self.memory.clone();
+            memory
+                .compact(self, window.saturating_sub(client.get_max_tokens()))
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn system_prompt_or<P: Provider>(&mut self, client: &LLMClient<P>) {
+        if self.system_prompt.is_none() && client.get_system_prompt().is_some() {
+            self.set_system_prompt(client.get_system_prompt());
+        }
+    }
+
+    // Send a user message and append the reply to the history. If the request fails the
+    // user message is removed again.
+    pub async fn chat<P: Provider>(
+        &mut self,
+        client: &LLMClient<P>,
+        content: String,
+    ) -> Result<String, String> {
+        self.push_user(content);
+        let result = self.respond(client).await;
+        if result.is_err() {
+            self.turns.pop();
+        }
+        result
+    }
+
+    // Complete the current history and append the reply to it
+    pub async fn respond<P: Provider>(&mut self, client: &LLMClient<P>) -> Result<String, String> {
+        self.system_prompt_or(client);
+        self.fit_to(client).await?;
+
+        let mut reply = String::new();
+        let mut usage = None;
+        let mut stream = client.stream_messages(self.messages()).await;
+        while let Some(result) = stream.next().await {
+            let response = result?;
+            if let Some(content) = response.get_content() {
+                reply.push_str(&content);
+            }
+            usage = response.get_usage().or(usage);
+        }
+
+        self.push_reply(client.get_model_id(), reply.clone(), usage);
+        // The request succeeded: keep the reply, unsaved turns are retried on the next save
+        if let Err(e) = self.save().await {
+            error!("Failed to save conversation: {}", e);
+        }
+        Ok(reply)
+    }
+
+    fn push_reply(&mut self, model: &str, content: String, usage: Option<Usage>) {
+        self.push_turn(Turn {
+            model: Some(model.to_string()),
+            usage,
+            ..Turn::new(ChatMessage {
+                role: "assistant".to_string(),
+                content,
+            })
+        });
+        if let Err(e) = self.flush_transcript() {
+            error!("Failed to write transcript: {}", e);
+        }
+    }
+
+    // Streaming version of chat(); the reply is appended once the stream is exhausted
+    pub async fn stream_chat<'a, P>(
+        &'a mut self,
+        client: &'a LLMClient<P>,
+        content: String,
+    ) -> Pin<Box<dyn Stream<Item = Result<StreamResponse, String>> + Send + 'a>>
+    where
+        P: Provider + Sync,
+        P::ModelType: Sync,
+    {
+        self.push_user(content);
+        self.system_prompt_or(client);
+        if let Err(e) = self.fit_to(client).await {
+            self.turns.pop();
+            return Box::pin(stream! {
+                yield Err(e);
+            });
+        }
+
+        let mut stream = client.stream_messages(self.messages()).await;
+        let model = client.get_model_id();
+        Box::pin(stream! {
+            let mut reply = String::new();
+            let mut usage = None;
+            while let Some(result) = stream.next().await {
+                match result {
+                    Ok(response) => {
+                        if let Some(content) = response.get_content() {
+                            reply.push_str(&content);
+                        }
+                        usage = response.get_usage().or(usage);
+                        yield Ok(response);
+                    }
+                    Err(e) => {
+                        // Leave the history as it was before the failed request
+                        self.turns.pop();
+                        yield Err(e);
+                        return;
+                    }
+                }
+            }
+            self.push_reply(model, reply, usage);
+            // As in respond(), a failed save keeps the reply and is retried on the next save
+            if let Err(e) = self.save().await {
+                error!("Failed to save conversation: {}", e);
+            }
+        })
+    }
+
+    // Save every turn to the store and keep saving after each reply.
+    // Creates the conversation in the store unless it was loaded from there; returns its id.
+    pub async fn autosave(&mut self, store: Arc<dyn ConversationStore>) -> Result<String, String> {
+        let id = match &self.id {
+            Some(id) => {
+                store.update(id, self).await?;
+                id.clone()
+            }
+            None => {
+                let id = store.create(self).await?;
+                for turn in &mut self.turns {
+                    turn.stored = false;
+                }
+                id
+            }
+        };
+        self.id = Some(id.clone());
+        self.header_changed = false;
+        self.store = Some(store);
+        self.save().await?;
+        Ok(id)
+    }
+
+    pub fn stop_autosave(&mut self) {
+        self.store = None;
+    }
+
+    // Write pending turns and header changes to the store, if autosave is enabled
+    pub async fn save(&mut self) -> Result<(), String> {
+        let (Some(store), Some(id)) = (self.store.clone(), self.id.clone()) else {
+            return Ok(());
+        };
+
+        if self.header_changed {
+            store.update(&id, self).await?;
+            self.header_changed = false;
+        }
+        if self.turns_cleared {
+            store.clear_turns(&id).await?;
+            self.turns_cleared = false;
+        }
+
+        // Summaries stand in for turns that are already stored
+        let pending: Vec<usize> = (0..self.turns.len())
+            .filter(|&index| !self.turns[index].stored && !self.turns[index].summary)
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let turns: Vec<Turn> = pending.iter().map(|&index| self.turns[index].clone()).collect();
+        store.append(&id, &turns).await?;
+        for index in pending {
+            self.turns[index].stored = true;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn from_store(
+        id: String,
+        system_prompt: Option<String>,
+        context_window: Option<u32>,
+        metadata: BTreeMap<String, String>,
+        mut turns: Vec<Turn>,
+    ) -> Self {
+        for turn in &mut turns {
+            turn.stored = true;
+        }
+        Self {
+            system_prompt,
+            turns,
+            context_window,
+            id: Some(id),
+            metadata,
+            ..Self::default()
+        }
+    }
+
+    // Save the whole conversation as a versioned JSON document
+    pub fn save_json<Q: AsRef<Path>>(&self, path: Q) -> Result<(), String> {
+        let path = path.as_ref();
+        fs::write(path, self.to_json()?)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn load_json<Q: AsRef<Path>>(path: Q) -> Result<Self, String> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::from_json(&json)
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| format!("Failed to serialize conversation: {}", e))
+    }
+
+    // Also accepts a plain array of messages, as saved from a Vec<ChatMessage>
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Failed to parse conversation: {}", e))
+    }
+
+    // Append every new turn to a JSONL transcript from now on.
+    // A new file starts with a header line; an existing one is continued.
+    pub fn record_jsonl<Q: AsRef<Path>>(&mut self, path: Q) -> Result<(), String> {
+        let path = path.as_ref().to_path_buf();
+        let is_new = fs::metadata(&path).map(|meta| meta.len() == 0).unwrap_or(true);
+
+        if is_new {
+            let header = TranscriptRecord::Header {
+                version: TRANSCRIPT_VERSION,
+                system_prompt: self.system_prompt.clone(),
+                context_window: self.context_window,
+                metadata: self.metadata.clone(),
+            };
+            append_records(&path, &[header])?;
+            for turn in &mut self.turns {
+                turn.recorded = false;
+            }
+        }
+
+        self.transcript = Some(Transcript {
+            path,
+            system_prompt: self.system_prompt.clone(),
+        });
+        self.flush_transcript()
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.transcript = None;
+    }
+
+    // Write turns (and system prompt changes) not yet in the transcript
+    pub fn flush_transcript(&mut self) -> Result<(), String> {
+        let Some(transcript) = &mut self.transcript else {
+            return Ok(());
+        };
+
+        let mut records = Vec::new();
+        if transcript.system_prompt != self.system_prompt {
+            records.push(TranscriptRecord::SystemPrompt {
+                system_prompt: self.system_prompt.clone(),
+            });
+        }
+        // Summaries stand in for turns that are already in the transcript
+        let pending: Vec<usize> = (0..self.turns.len())
+            .filter(|&index| !self.turns[index].recorded && !self.turns[index].summary)
+            .collect();
+        records.extend(pending.iter().map(|&index| TranscriptRecord::Turn(self.turns[index].clone())));
+
+        if records.is_empty() {
+            return Ok(());
+        }
+        append_records(&transcript.path, &records)?;
+
+        transcript.system_prompt = self.system_prompt.clone();
+        for index in pending {
+            self.turns[index].recorded = true;
+        }
+        Ok(())
+    }
+
+    // Rebuild a conversation from a JSONL transcript
+    pub fn load_jsonl<Q: AsRef<Path>>(path: Q) -> Result<Self, String> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+        let mut conversation = Conversation::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = |e: serde_json::Error| format!("Invalid transcript line {}: {}", number + 1, e);
+            let value: Value = serde_json::from_str(&line).map_err(invalid)?;
+
+            // Lines without a type are bare messages from older transcripts
+            if value.get("type").is_none() {
+                let mut turn: Turn = serde_json::from_value(value).map_err(invalid)?;
+                turn.recorded = true;
+                conversation.turns.push(turn);
+                continue;
+            }
+
+            match serde_json::from_value(value).map_err(invalid)? {
+                TranscriptRecord::Header { version, system_prompt, context_window, metadata } => {
+                    check_version(version)?;
+                    conversation.system_prompt = system_prompt;
+                    conversation.context_window = context_window;
+                    conversation.metadata = metadata;
+                }
+                TranscriptRecord::SystemPrompt { system_prompt } => {
+                    conversation.system_prompt = system_prompt;
+                }
+                TranscriptRecord::Turn(mut turn) => {
+                    turn.recorded = true;
+                    conversation.turns.push(turn);
+                }
+            }
+        }
+        Ok(conversation)
+    }
+}
+
+fn check_version(version: u32) -> Result<(), String> {
+    if version > TRANSCRIPT_VERSION {
+        return Err(format!(
+            "Transcript version {} is newer than the supported version {}",
+            version, TRANSCRIPT_VERSION
+        ));
+    }
+    Ok(())
+}
+
+fn append_records(path: &Path, records: &[TranscriptRecord]) -> Result<(), String> {
+    let mut lines = String::new();
+    for record in records {
+        let line = serde_json::to_string(record)
+            .map_err(|e| format!("Failed to serialize transcript record: {}", e))?;
+        lines.push_str(&line);
+        lines.push('\n');
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    file.write_all(lines.as_bytes())
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// One line of a JSONL transcript
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TranscriptRecord {
+    Header {
+        version: u32,
+        system_prompt: Option<String>,
+        context_window: Option<u32>,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        metadata: BTreeMap<String, String>,
+    },
+    SystemPrompt {
+        system_prompt: Option<String>,
+    },
+    Turn(Turn),
+}
+
+// JSON document layout, see TRANSCRIPT_VERSION
+#[derive(Serialize)]
+struct ConversationFileRef<'a> {
+    version: u32,
+    system_prompt: &'a Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context_window: Option<u32>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: &'a BTreeMap<String, String>,
+    turns: &'a [Turn],
+}
+
+#[derive(Deserialize)]
+struct ConversationFile {
+    // Version 0 documents predate the field
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    system_prompt: Option<String>,
+    #[serde(default)]
+    context_window: Option<u32>,
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+    #[serde(default, alias = "messages")]
+    turns: Vec<Turn>,
+}
+
+impl Serialize for Conversation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ConversationFileRef {
+            version: TRANSCRIPT_VERSION,
+            system_prompt: &self.system_prompt,
+            context_window: self.context_window,
+            metadata: &self.metadata,
+            turns: &self.turns,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Conversation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let file = match Value::deserialize(deserializer)? {
+            // Version 0: a bare list of messages
+            Value::Array(messages) => ConversationFile {
+                version: 0,
+                system_prompt: None,
+                context_window: None,
+                metadata: BTreeMap::new(),
+                turns: serde_json::from_value(Value::Array(messages)).map_err(D::Error::custom)?,
+            },
+            value => serde_json::from_value(value).map_err(D::Error::custom)?,
+        };
+        check_version(file.version).map_err(D::Error::custom)?;
+
+        let mut conversation = Conversation {
+            system_prompt: file.system_prompt,
+            context_window: file.context_window,
+            metadata: file.metadata,
+            turns: file.turns,
+            ..Conversation::default()
+        };
+        // Older documents kept the system prompt as the first message
+        if conversation.system_prompt.is_none()
+            && conversation.turns.first().is_some_and(|turn| turn.message.role == "system" && !turn.summary)
+        {
+            conversation.system_prompt = Some(conversation.turns.remove(0).message.content);
+        }
+        Ok(conversation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{LLMBuilder, Mock, MockModel, MockScript};
+    use crate::store::InMemoryStore;
+
+    fn client(script: MockScript) -> LLMClient<Mock> {
+        LLMBuilder::<Mock>::new()
+            .model(MockModel::Scripted)
+            .script(script)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn chat_keeps_the_reply_when_saving_fails() {
+        let client = client(MockScript::new().reply("Hello!"));
+        let store = Arc::new(InMemoryStore::new());
+        let mut conversation = Conversation::new();
+        let id = conversation.autosave(store.clone()).await.unwrap();
+        // Appending to a conversation the store no longer has fails
+        store.delete(&id).await.unwrap();
+
+        let reply = conversation.chat(&client, "Hi".to_string()).await;
+
+        assert_eq!(reply, Ok("Hello!".to_string()));
+        let roles: Vec<&str> = conversation
+            .turns()
+            .iter()
+            .map(|turn| turn.message.role.as_str())
+            .collect();
+        assert_eq!(roles, ["user", "assistant"]);
+        assert!(conversation.turns().iter().all(|turn| !turn.stored));
+    }
+
+    #[tokio::test]
+    async fn chat_removes_the_user_turn_when_the_request_fails() {
+        let client = client(MockScript::new().reply("First").error("connection reset"));
+        let mut conversation = Conversation::new();
+        conversation.chat(&client, "One".to_string()).await.unwrap();
+
+        let reply = conversation.chat(&client, "Two".to_string()).await;
+
+        assert!(reply.is_err());
+        assert_eq!(conversation.len(), 2);
+        assert_eq!(conversation.turns()[1].message.content, "First");
+    }
+
+    fn temp_path(extension: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "babel-conversation-{:016x}.{}",
+            rand::random::<u64>(),
+            extension
+        ))
+    }
+
+    fn contents(conversation: &Conversation) -> Vec<(&str, &str, bool)> {
+        conversation
+            .turns()
+            .iter()
+            .map(|turn| {
+                (
+                    turn.message.role.as_str(),
+                    turn.message.content.as_str(),
+                    turn.pinned,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn json_documents_round_trip_with_their_version() {
+        let mut conversation = Conversation::new()
+            .system_prompt("Be brief".to_string())
+            .context_window(4096)
+            .metadata("user".to_string(), "ada".to_string());
+        conversation.push_pinned(ChatMessage {
+            role: "user".to_string(),
+            content: "Remember this".to_string(),
+        });
+        conversation.push_assistant("Noted".to_string());
+        let path = temp_path("json");
+
+        conversation.save_json(&path).unwrap();
+        let document: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let loaded = Conversation::load_json(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(document["version"], TRANSCRIPT_VERSION);
+        assert_eq!(loaded.get_system_prompt(), Some("Be brief"));
+        assert_eq!(loaded.get_context_window(), Some(4096));
+        assert_eq!(
+            loaded.get_metadata().get("user").map(String::as_str),
+            Some("ada")
+        );
+        assert_eq!(
+            contents(&loaded),
+            [
+                ("user", "Remember this", true),
+                ("assistant", "Noted", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn version_0_documents_are_bare_message_arrays() {
+        let json = r#"[
+            {"role": "system", "content": "Be brief"},
+            {"role": "user", "content": "Hi"},
+            {"role": "assistant", "content": "Hello"}
+        ]"#;
+
+        let conversation = Conversation::from_json(json).unwrap();
+
+        assert_eq!(conversation.get_system_prompt(), Some("Be brief"));
+        assert_eq!(
+            contents(&conversation),
+            [("user", "Hi", false), ("assistant", "Hello", false)]
+        );
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let json = format!(r#"{{"version": {}, "turns": []}}"#, TRANSCRIPT_VERSION + 1);
+        let error = Conversation::from_json(&json).unwrap_err();
+        assert!(
+            error.contains("is newer than the supported version"),
+            "{}",
+            error
+        );
+
+        let path = temp_path("jsonl");
+        fs::write(&path, format!("{{\"type\":\"header\",\"version\":{},\"system_prompt\":null,\"context_window\":null}}\n", TRANSCRIPT_VERSION + 1)).unwrap();
+        let error = Conversation::load_jsonl(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(
+            error.contains("is newer than the supported version"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn jsonl_transcripts_start_with_a_header_line() {
+        let mut conversation = Conversation::new().system_prompt("Be brief".to_string());
+        conversation.push_user("Hi".to_string());
+        let path = temp_path("jsonl");
+
+        conversation.record_jsonl(&path).unwrap();
+        let transcript = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let lines: Vec<Value> = transcript
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["type"], "header");
+        assert_eq!(lines[0]["version"], TRANSCRIPT_VERSION);
+        assert_eq!(lines[0]["system_prompt"], "Be brief");
+        assert_eq!(lines[1]["type"], "turn");
+        assert_eq!(lines[1]["content"], "Hi");
+    }
+
+    #[tokio::test]
+    async fn appended_transcripts_reload_to_the_same_conversation() {
+        let client = client(MockScript::new().reply("First").reply("Second"));
+        let path = temp_path("jsonl");
+        let mut conversation = Conversation::new().system_prompt("Be brief".to_string());
+        conversation.record_jsonl(&path).unwrap();
+        conversation.chat(&client, "One".to_string()).await.unwrap();
+
+        // Continue the same file from a reloaded conversation
+        let mut reloaded = Conversation::load_jsonl(&path).unwrap();
+        reloaded.record_jsonl(&path).unwrap();
+        reloaded.set_system_prompt(Some("Be kind".to_string()));
+        reloaded.chat(&client, "Two".to_string()).await.unwrap();
+
+        let loaded = Conversation::load_jsonl(&path).unwrap();
+        let transcript = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(transcript.matches(r#""type":"header""#).count(), 1);
+        assert_eq!(loaded.get_system_prompt(), Some("Be kind"));
+        assert_eq!(
+            contents(&loaded),
+            [
+                ("user", "One", false),
+                ("assistant", "First", false),
+                ("user", "Two", false),
+                ("assistant", "Second", false)
+            ]
+        );
+        assert_eq!(loaded.turns()[1].model.as_deref(), Some("mock-scripted"));
+    }
+
+    #[test]
+    fn jsonl_lines_without_a_type_are_read_as_messages() {
+        let path = temp_path("jsonl");
+        fs::write(&path, "{\"role\":\"user\",\"content\":\"Hi\"}\n\n{\"role\":\"assistant\",\"content\":\"Hello\"}\n").unwrap();
+
+        let conversation = Conversation::load_jsonl(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            contents(&conversation),
+            [("user", "Hi", false), ("assistant", "Hello", false)]
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_chat_keeps_the_reply_when_saving_fails() {
+        let client = client(MockScript::new().stream(&["Hel", "lo"], std::time::Duration::ZERO));
+        let store = Arc::new(InMemoryStore::new());
+        let mut conversation = Conversation::new();
+        let id = conversation.autosave(store.clone()).await.unwrap();
+        store.delete(&id).await.unwrap();
+
+        let results: Vec<_> = conversation
+            .stream_chat(&client, "Hi".to_string())
+            .await
+            .collect()
+            .await;
+
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(
+            contents(&conversation),
+            [("user", "Hi", false), ("assistant", "Hello", false)]
+        );
+        assert!(conversation.turns().iter().all(|turn| !turn.stored));
+    }
+
+    #[tokio::test]
+    async fn clear_removes_the_stored_turns_on_the_next_save() {
+        let client = client(MockScript::new().reply("First").reply("Second"));
+        let store = Arc::new(InMemoryStore::new());
+        let mut conversation = Conversation::new().system_prompt("Be brief".to_string());
+        let id = conversation.autosave(store.clone()).await.unwrap();
+        conversation.chat(&client, "One".to_string()).await.unwrap();
+
+        conversation.clear();
+        conversation.save().await.unwrap();
+        let cleared = store.load(&id).await.unwrap();
+        conversation.chat(&client, "Two".to_string()).await.unwrap();
+        let loaded = store.load(&id).await.unwrap();
+
+        assert!(cleared.is_empty());
+        assert_eq!(cleared.get_system_prompt(), Some("Be brief"));
+        assert_eq!(
+            contents(&loaded),
+            [("user", "Two", false), ("assistant", "Second", false)]
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use super::base::{define_provider_models, Model, Provider};
+use super::ratelimit::RequestWindow;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Groq;
 
 impl Provider for Groq {
@@ -9,6 +10,15 @@ impl Provider for Groq {
     fn provider_name() -> &'static str {
         "groq"
     }
+
+    fn default_base_url() -> Option<&'static str> {
+        Some("https://api.groq.com/openai/v1")
+    }
+
+    // Groq's request headers report the daily budget; tokens are per minute
+    fn request_window() -> RequestWindow {
+        RequestWindow::Day
+    }
 }
 
 define_provider_models!(Groq, GroqModel, {
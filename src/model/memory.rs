@@ -0,0 +1,298 @@
+use async_trait::async_trait;
+use std::fmt;
+
+use super::base::Provider;
+use super::chat::{ChatMessage, LLMClient};
+use super::conversation::{Conversation, Turn};
+
+// Strategy a Conversation uses to keep its prompt within the context window
+#[async_trait]
+pub trait Memory: Send + Sync + fmt::Debug {
+    // Shrink the history so the prompt fits within `budget` tokens
+    async fn compact(&self, conversation: &mut Conversation, budget: u32) -> Result<(), String>;
+}
+
+// Drop the oldest unpinned turns (the default)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SlidingWindow;
+
+#[async_trait]
+impl Memory for SlidingWindow {
+    async fn compact(&self, conversation: &mut Conversation, budget: u32) -> Result<(), String> {
+        conversation.trim_to(budget);
+        Ok(())
+    }
+}
+
+const DEFAULT_SUMMARY_PROMPT: &str = "Summarize the following conversation between a user and an assistant. \
+Keep every fact, decision, name and open question needed to continue it. \
+Reply with the summary only.";
+
+// Condense older turns into a pinned summary message using a (possibly cheaper) client
+pub struct SummaryMemory<P: Provider> {
+    client: LLMClient<P>,
+    threshold: f32,
+    keep_recent: usize,
+    prompt: String,
+}
+
+impl<P: Provider> fmt::Debug for SummaryMemory<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SummaryMemory")
+            .field("model", &self.client.get_model_id())
+            .field("threshold", &self.threshold)
+            .field("keep_recent", &self.keep_recent)
+            .finish()
+    }
+}
+
+impl<P: Provider> SummaryMemory<P> {
+    pub fn new(client: LLMClient<P>) -> Self {
+        Self {
+            client,
+            threshold: 0.8,
+            keep_recent: 4,
+            prompt: DEFAULT_SUMMARY_PROMPT.to_string(),
+        }
+    }
+
+    // Fraction of the budget at which summarizing starts
+    pub fn threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold.clamp(0.0, 1.0);
+        self
+    }
+
+    // Number of most recent messages that are never summarized
+    pub fn keep_recent(mut self, messages: usize) -> Self {
+        self.keep_recent = messages;
+        self
+    }
+
+    pub fn prompt(mut self, prompt: String) -> Self {
+        self.prompt = prompt;
+        self
+    }
+}
+
+#[async_trait]
+impl<P> Memory for SummaryMemory<P>
+where
+    P: Provider + Send + Sync,
+    P::ModelType: Send + Sync,
+{
+    async fn compact(&self, conversation: &mut Conversation, budget: u32) -> Result<(), String> {
+        if conversation.estimated_tokens() as f32 <= budget as f32 * self.threshold {
+            return Ok(());
+        }
+
+        // Everything unpinned except the most recent messages, plus any earlier summary
+        let turns = conversation.turns_mut();
+        let recent_start = turns.len().saturating_sub(self.keep_recent.max(1));
+        let selected: Vec<usize> = (0..recent_start)
+            .filter(|&index| !turns[index].pinned || turns[index].summary)
+            .collect();
+
+        if selected.iter().any(|&index| !turns[index].summary) {
+            let transcript = selected
+                .iter()
+                .map(|&index| {
+                    let message = &turns[index].message;
+                    format!("{}: {}", message.role, message.content)
+                })
+                .collect::<Vec<_>>()
+                .join("\n\n");
+
+            let summary = self
+                .client
+                .chat_messages(vec![
+                    ChatMessage {
+                        role: "system".to_string(),
+                        content: self.prompt.clone(),
+                    },
+                    ChatMessage {
+                        role: "user".to_string(),
+                        content: transcript,
+                    },
+                ])
+                .await?;
+
+            for index in selected.into_iter().rev() {
+                turns.remove(index);
+            }
+            turns.insert(
+                0,
+                Turn {
+                    pinned: true,
+                    summary: true,
+                    model: Some(self.client.get_model_id().to_string()),
+                    ..Turn::new(ChatMessage {
+                        role: "system".to_string(),
+                        content: format!("Summary of the earlier conversation:\n{}", summary.trim()),
+                    })
+                },
+            );
+        }
+
+        // The summary may still be too large, fall back to dropping turns
+        conversation.trim_to(budget);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{LLMBuilder, Mock, MockModel, MockScript};
+
+    fn message(role: &str, content: &str) -> ChatMessage {
+        ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    // Six turns of small talk after a pinned instruction
+    fn conversation() -> Conversation {
+        let mut conversation = Conversation::new();
+        conversation.push_pinned(message("user", "Always answer in French."));
+        for i in 1..=3 {
+            conversation.push_user(format!("Question number {} about the weather in Paris", i));
+            conversation.push_assistant(format!("Answer number {}: it is sunny and warm today", i));
+        }
+        conversation
+    }
+
+    fn summarizer(script: MockScript) -> SummaryMemory<Mock> {
+        let client = LLMBuilder::<Mock>::new()
+            .model(MockModel::Scripted)
+            .script(script)
+            .build()
+            .unwrap();
+        SummaryMemory::new(client).keep_recent(2)
+    }
+
+    fn contents(conversation: &Conversation) -> Vec<&str> {
+        conversation
+            .turns()
+            .iter()
+            .map(|turn| turn.message.content.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn summary_memory_waits_for_the_threshold() {
+        let script = MockScript::new().reply("unused");
+        let memory = summarizer(script.clone()).threshold(0.8);
+        let mut conversation = conversation();
+        let before = contents(&conversation).join("|");
+        // The prompt uses 70% of this budget
+        let budget = conversation.estimated_tokens() * 10 / 7;
+
+        memory.compact(&mut conversation, budget).await.unwrap();
+
+        assert!(script.requests().is_empty());
+        assert_eq!(contents(&conversation).join("|"), before);
+    }
+
+    #[tokio::test]
+    async fn summary_memory_replaces_older_turns_with_a_pinned_summary() {
+        let script = MockScript::new().reply("  The user asked about the weather.  ");
+        let memory = summarizer(script.clone());
+        let mut conversation = conversation();
+        let budget = conversation.estimated_tokens();
+
+        memory.compact(&mut conversation, budget).await.unwrap();
+
+        assert_eq!(
+            contents(&conversation),
+            [
+                "Summary of the earlier conversation:\nThe user asked about the weather.",
+                "Always answer in French.",
+                "Question number 3 about the weather in Paris",
+                "Answer number 3: it is sunny and warm today",
+            ]
+        );
+        let summary = &conversation.turns()[0];
+        assert!(summary.pinned && summary.summary);
+        assert_eq!(summary.message.role, "system");
+        assert_eq!(summary.model.as_deref(), Some("mock-scripted"));
+
+        // The summarizer saw the older unpinned turns, not the pinned or recent ones
+        let request = script.last_request().unwrap();
+        assert_eq!(request.messages[0].content, DEFAULT_SUMMARY_PROMPT);
+        let transcript = request.last_user_message().unwrap();
+        assert!(transcript.starts_with("user: Question number 1 about the weather in Paris\n\n"));
+        assert!(transcript.ends_with("assistant: Answer number 2: it is sunny and warm today"));
+        assert!(!transcript.contains("French"));
+        assert!(!transcript.contains("number 3"));
+    }
+
+    #[tokio::test]
+    async fn summary_memory_folds_the_previous_summary_into_the_next() {
+        let script = MockScript::new()
+            .reply("First summary")
+            .reply("Second summary");
+        let memory = summarizer(script.clone());
+        let mut conversation = conversation();
+        let budget = conversation.estimated_tokens();
+        memory.compact(&mut conversation, budget).await.unwrap();
+
+        conversation
+            .push_user("Question number 4 about the weather in Paris, and in Lyon".to_string());
+        conversation
+            .push_assistant("Answer number 4: it is sunny and warm in both cities".to_string());
+        memory.compact(&mut conversation, budget).await.unwrap();
+
+        let transcript = script
+            .last_request()
+            .unwrap()
+            .last_user_message()
+            .unwrap()
+            .to_string();
+        assert!(
+            transcript.starts_with("system: Summary of the earlier conversation:\nFirst summary")
+        );
+        let summaries = conversation
+            .turns()
+            .iter()
+            .filter(|turn| turn.summary)
+            .count();
+        assert_eq!(summaries, 1);
+        assert_eq!(
+            contents(&conversation)[0],
+            "Summary of the earlier conversation:\nSecond summary"
+        );
+    }
+
+    #[tokio::test]
+    async fn summary_memory_keeps_the_history_when_summarizing_fails() {
+        let memory = summarizer(MockScript::new().error("connection reset"));
+        let mut conversation = conversation();
+        let before = conversation.len();
+        let budget = conversation.estimated_tokens();
+
+        let result = memory.compact(&mut conversation, budget).await;
+
+        assert_eq!(result, Err("Request error: connection reset".to_string()));
+        assert_eq!(conversation.len(), before);
+    }
+
+    #[tokio::test]
+    async fn sliding_window_drops_the_oldest_unpinned_turns() {
+        let mut conversation = conversation();
+        let mut expected = conversation.clone();
+        expected.turns_mut().drain(1..3);
+        let budget = expected.estimated_tokens();
+
+        SlidingWindow
+            .compact(&mut conversation, budget)
+            .await
+            .unwrap();
+
+        assert_eq!(contents(&conversation), contents(&expected));
+        assert_eq!(
+            conversation.turns()[0].message.content,
+            "Always answer in French."
+        );
+    }
+}
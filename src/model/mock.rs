@@ -0,0 +1,364 @@
+use async_stream::stream;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::base::{define_provider_models, Model, Provider};
+use super::chat::{ChatMessage, LLMBuilder};
+use super::transport::{HttpRequest, HttpResponse, Transport};
+use crate::tokens::{count_messages_for, count_text_for, ModelFamily};
+
+// Offline provider that answers from a MockScript, for tests
+#[derive(Debug, Clone, Copy)]
+pub struct Mock;
+
+impl Provider for Mock {
+    type ModelType = MockModel;
+
+    fn provider_name() -> &'static str {
+        "mock"
+    }
+
+    fn requires_api_key() -> bool {
+        false
+    }
+
+    fn default_base_url() -> Option<&'static str> {
+        Some("mock://v1")
+    }
+
+    fn supports_stream_usage() -> bool {
+        true
+    }
+
+    // Answers from an (empty) script instead of the network
+    fn default_transport() -> Arc<dyn Transport> {
+        Arc::new(MockScript::new())
+    }
+}
+
+define_provider_models!(Mock, MockModel, {
+    // Fails once the script is exhausted
+    (Scripted, "mock-scripted"),
+    // Echoes the last user message once the script is exhausted
+    (Echo, "mock-echo")
+});
+
+// One scripted answer
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockReply {
+    // The whole reply in a single chunk
+    Text(String),
+    // Streamed chunk by chunk, waiting `delay` before each one
+    Chunks { chunks: Vec<String>, delay: Duration },
+    // A reply in the {"response", "tool", "finished"} envelope asking to run a tool
+    ToolCall { name: String, content: String },
+    // The request fails before any response arrives
+    Error(String),
+    // The provider answers with an HTTP error
+    Status { status: u16, body: String },
+}
+
+// A request the mock received, for assertions
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecordedRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    pub temperature: f32,
+    pub max_tokens: Option<u32>,
+    pub stream: bool,
+}
+
+impl RecordedRequest {
+    pub fn last_user_message(&self) -> Option<&str> {
+        self.messages
+            .iter()
+            .rev()
+            .find(|message| message.role == "user")
+            .map(|message| message.content.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    replies: VecDeque<MockReply>,
+    requests: Vec<RecordedRequest>,
+}
+
+// Scripted replies, handed out in order; clones share the script and the recorded requests
+#[derive(Debug, Clone, Default)]
+pub struct MockScript {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reply(self, text: &str) -> Self {
+        self.push(MockReply::Text(text.to_string()));
+        self
+    }
+
+    pub fn stream(self, chunks: &[&str], delay: Duration) -> Self {
+        self.push(MockReply::Chunks {
+            chunks: chunks.iter().map(|chunk| chunk.to_string()).collect(),
+            delay,
+        });
+        self
+    }
+
+    pub fn tool_call(self, name: &str, content: &str) -> Self {
+        self.push(MockReply::ToolCall {
+            name: name.to_string(),
+            content: content.to_string(),
+        });
+        self
+    }
+
+    pub fn error(self, message: &str) -> Self {
+        self.push(MockReply::Error(message.to_string()));
+        self
+    }
+
+    pub fn status(self, status: u16, body: &str) -> Self {
+        self.push(MockReply::Status {
+            status,
+            body: body.to_string(),
+        });
+        self
+    }
+
+    // Queue a reply on a script that is already in use
+    pub fn push(&self, reply: MockReply) {
+        self.state.lock().replies.push_back(reply);
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.state.lock().replies.len()
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().requests.clone()
+    }
+
+    pub fn last_request(&self) -> Option<RecordedRequest> {
+        self.state.lock().requests.last().cloned()
+    }
+}
+
+impl LLMBuilder<Mock> {
+    pub fn script(mut self, script: MockScript) -> Self {
+        self.transport = Some(Arc::new(script));
+        self
+    }
+}
+
+// OpenAI-style SSE event carrying a content delta
+fn sse_chunk(content: &str) -> Vec<u8> {
+    let chunk = json!({
+        "choices": [{ "index": 0, "delta": { "content": content }, "finish_reason": null }]
+    });
+    format!("data: {}\n\n", chunk).into_bytes()
+}
+
+fn sse_finish(request: &RecordedRequest, completion: &str) -> Vec<u8> {
+    let prompt_tokens = count_messages_for(&request.messages, ModelFamily::Other);
+    let completion_tokens = count_text_for(completion, ModelFamily::Other);
+    let chunk = json!({
+        "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }],
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens,
+        }
+    });
+    format!("data: {}\n\ndata: [DONE]\n\n", chunk).into_bytes()
+}
+
+#[async_trait]
+impl Transport for MockScript {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, String> {
+        let recorded: RecordedRequest = serde_json::from_slice(&request.body)
+            .map_err(|e| format!("Mock received an invalid request: {}", e))?;
+
+        let reply = {
+            let mut state = self.state.lock();
+            state.requests.push(recorded.clone());
+            state.replies.pop_front()
+        };
+
+        let (chunks, delay) = match reply {
+            Some(MockReply::Text(text)) => (vec![text], Duration::ZERO),
+            Some(MockReply::Chunks { chunks, delay }) => (chunks, delay),
+            Some(MockReply::ToolCall { name, content }) => {
+                let envelope = json!({
+                    "response": "",
+                    "tool": { "name": name, "content": content },
+                    "finished": false,
+                });
+                (vec![envelope.to_string()], Duration::ZERO)
+            }
+            Some(MockReply::Error(message)) => return Err(message),
+            Some(MockReply::Status { status, body }) => {
+                let status = StatusCode::from_u16(status).map_err(|e| e.to_string())?;
+                return Ok(HttpResponse {
+                    status,
+                    headers: HeaderMap::new(),
+                    body: Box::pin(futures::stream::once(async move { Ok(body.into_bytes()) })),
+                });
+            }
+            None if recorded.model == MockModel::Echo.model_id() => {
+                let echo = recorded.last_user_message().unwrap_or_default().to_string();
+                (vec![echo], Duration::ZERO)
+            }
+            None => return Err("Mock script exhausted".to_string()),
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+
+        Ok(HttpResponse {
+            status: StatusCode::OK,
+            headers,
+            body: Box::pin(stream! {
+                let completion = chunks.concat();
+                for chunk in chunks {
+                    if !delay.is_zero() {
+                        tokio::time::sleep(delay).await;
+                    }
+                    yield Ok(sse_chunk(&chunk));
+                }
+                yield Ok(sse_finish(&recorded, &completion));
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::LLMClient;
+    use futures::StreamExt;
+    use std::time::Instant;
+
+    fn client(model: MockModel, script: MockScript) -> LLMClient<Mock> {
+        LLMBuilder::<Mock>::new()
+            .model(model)
+            .script(script)
+            .build()
+            .unwrap()
+    }
+
+    fn user(content: &str) -> Vec<ChatMessage> {
+        vec![ChatMessage {
+            role: "user".to_string(),
+            content: content.to_string(),
+        }]
+    }
+
+    #[tokio::test]
+    async fn streamed_replies_arrive_chunk_by_chunk() {
+        let script = MockScript::new().stream(&["Hel", "lo", "!"], Duration::from_millis(20));
+        let client = client(MockModel::Scripted, script);
+
+        let started = Instant::now();
+        let responses: Vec<_> = client.stream_chat(user("hi")).await.collect().await;
+
+        assert!(started.elapsed() >= Duration::from_millis(60));
+        let chunks: Vec<_> = responses
+            .iter()
+            .filter_map(|r| r.as_ref().unwrap().get_content())
+            .collect();
+        assert_eq!(chunks, ["Hel", "lo", "!"]);
+
+        let last = responses.last().unwrap().as_ref().unwrap();
+        assert!(last.is_finished());
+        let usage = last.get_usage().unwrap();
+        assert_eq!(
+            usage.completion_tokens,
+            Some(count_text_for("Hello!", ModelFamily::Other) as u32)
+        );
+    }
+
+    #[tokio::test]
+    async fn replies_are_handed_out_in_order() {
+        let script = MockScript::new()
+            .reply("first")
+            .tool_call("read_file", "notes.txt");
+        let client = client(MockModel::Scripted, script.clone());
+
+        assert_eq!(client.chat(user("one")).await.unwrap(), "first");
+        let envelope: serde_json::Value =
+            serde_json::from_str(&client.chat(user("two")).await.unwrap()).unwrap();
+        assert_eq!(envelope["tool"]["name"], "read_file");
+        assert_eq!(envelope["tool"]["content"], "notes.txt");
+        assert_eq!(envelope["finished"], false);
+
+        assert_eq!(script.remaining(), 0);
+        assert_eq!(
+            client.chat(user("three")).await.unwrap_err(),
+            "Request error: Mock script exhausted"
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_and_statuses_fail_the_request() {
+        let script = MockScript::new()
+            .error("connection reset")
+            .status(503, "overloaded");
+        let client = client(MockModel::Scripted, script);
+
+        assert_eq!(
+            client.chat(user("one")).await.unwrap_err(),
+            "Request error: connection reset"
+        );
+        assert_eq!(
+            client.chat(user("two")).await.unwrap_err(),
+            "HTTP 503 Service Unavailable: overloaded"
+        );
+    }
+
+    #[tokio::test]
+    async fn echo_repeats_the_last_user_message_once_the_script_runs_out() {
+        let client = client(MockModel::Echo, MockScript::new().reply("scripted"));
+
+        assert_eq!(client.chat(user("one")).await.unwrap(), "scripted");
+        assert_eq!(client.chat(user("two")).await.unwrap(), "two");
+    }
+
+    #[tokio::test]
+    async fn requests_are_recorded() {
+        let script = MockScript::new();
+        let client = LLMBuilder::<Mock>::new()
+            .model(MockModel::Echo)
+            .script(script.clone())
+            .system_prompt("Be brief".to_string())
+            .temperature(0.2)
+            .max_tokens(64)
+            .build()
+            .unwrap();
+
+        client.chat(user("first")).await.unwrap();
+        client.chat(user("second")).await.unwrap();
+
+        let requests = script.requests();
+        assert_eq!(requests.len(), 2);
+        let last = script.last_request().unwrap();
+        assert_eq!(last.model, "mock-echo");
+        assert_eq!(last.temperature, 0.2);
+        assert_eq!(last.max_tokens, Some(64));
+        assert!(last.stream);
+        assert_eq!(last.messages[0].role, "system");
+        assert_eq!(last.messages[0].content, "Be brief");
+        assert_eq!(last.last_user_message(), Some("second"));
+        assert_eq!(requests[0].last_user_message(), Some("first"));
+    }
+}
@@ -3,13 +3,27 @@ mod groq;
 mod openrouter;
 mod chat;
 mod sambanova;
+mod ratelimit;
+mod batch;
+mod conversation;
+mod memory;
+mod transport;
+mod mock;
+mod cassette;
 
 // Re-export the main components
-pub use base::{Model, Provider};
+pub use base::{context_window_for, Model, Provider};
 pub use groq::{Groq, GroqModel};
 pub use sambanova::{SambaNova, SambaNovaModel};
 pub use openrouter::{OpenRouter, OpenRouterModel};
 pub use chat::{ChatMessage, LLMClient, LLMBuilder, StreamResponse, Usage};
+pub use ratelimit::{RateLimit, RateLimiter, RequestWindow};
+pub use batch::{BatchOptions, BatchProgress, BatchStream};
+pub use conversation::{Conversation, Turn};
+pub use memory::{Memory, SlidingWindow, SummaryMemory};
+pub use transport::{ByteStream, HttpRequest, HttpResponse, ReqwestTransport, Transport};
+pub use cassette::{Cassette, CassetteMode, CASSETTE_VERSION};
+pub use mock::{Mock, MockModel, MockReply, MockScript, RecordedRequest};
 
 // Example usage:
 /*
@@ -1,6 +1,6 @@
 use super::base::{define_provider_models, Model, Provider};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct OpenRouter;
 
 impl Provider for OpenRouter {
@@ -9,6 +9,14 @@ impl Provider for OpenRouter {
     fn provider_name() -> &'static str {
         "openrouter"
     }
+
+    fn default_base_url() -> Option<&'static str> {
+        Some("https://openrouter.ai/api/v1")
+    }
+
+    fn supports_stream_usage() -> bool {
+        true
+    }
 }
 
 define_provider_models!(OpenRouter, OpenRouterModel, {
@@ -0,0 +1,429 @@
+use parking_lot::Mutex;
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Requests and tokens a model may use per minute
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimit {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u32>,
+}
+
+impl RateLimit {
+    pub fn new(requests_per_minute: u32, tokens_per_minute: u32) -> Self {
+        Self {
+            requests_per_minute: Some(requests_per_minute),
+            tokens_per_minute: Some(tokens_per_minute),
+        }
+    }
+
+    pub fn requests_per_minute(requests: u32) -> Self {
+        Self {
+            requests_per_minute: Some(requests),
+            tokens_per_minute: None,
+        }
+    }
+
+    pub fn tokens_per_minute(tokens: u32) -> Self {
+        Self {
+            requests_per_minute: None,
+            tokens_per_minute: Some(tokens),
+        }
+    }
+}
+
+// What a provider's x-ratelimit-*-requests headers count
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RequestWindow {
+    // Requests per minute, the budget the limiter tracks (OpenAI style)
+    #[default]
+    Minute,
+    // Requests per day (Groq); only an exhausted budget holds requests back
+    Day,
+}
+
+// Remaining budget for a single model, refilled continuously
+#[derive(Debug)]
+struct Budget {
+    limit: RateLimit,
+    requests: f64,
+    tokens: f64,
+    updated: Instant,
+    blocked_until: Option<Instant>,
+}
+
+impl Budget {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            requests: limit.requests_per_minute.unwrap_or(0) as f64,
+            tokens: limit.tokens_per_minute.unwrap_or(0) as f64,
+            updated: Instant::now(),
+            blocked_until: None,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.updated = now;
+
+        if let Some(rpm) = self.limit.requests_per_minute {
+            self.requests = (self.requests + elapsed * rpm as f64 / 60.0).min(rpm as f64);
+        }
+        if let Some(tpm) = self.limit.tokens_per_minute {
+            self.tokens = (self.tokens + elapsed * tpm as f64 / 60.0).min(tpm as f64);
+        }
+    }
+
+    // Take budget for one request, or return how long to wait before trying again
+    fn try_take(&mut self, estimated_tokens: u32) -> Option<Duration> {
+        let now = Instant::now();
+        self.refill(now);
+
+        if let Some(until) = self.blocked_until {
+            if until > now {
+                return Some(until - now);
+            }
+            self.blocked_until = None;
+        }
+
+        let mut wait: f64 = 0.0;
+
+        if let Some(rpm) = self.limit.requests_per_minute {
+            if self.requests < 1.0 {
+                wait = wait.max((1.0 - self.requests) * 60.0 / rpm.max(1) as f64);
+            }
+        }
+
+        // A single request larger than the whole budget would never fit, so cap it
+        let needed = match self.limit.tokens_per_minute {
+            Some(tpm) => estimated_tokens.min(tpm) as f64,
+            None => 0.0,
+        };
+        if let Some(tpm) = self.limit.tokens_per_minute {
+            if self.tokens < needed {
+                wait = wait.max((needed - self.tokens) * 60.0 / tpm.max(1) as f64);
+            }
+        }
+
+        if wait > 0.0 {
+            return Some(Duration::from_secs_f64(wait));
+        }
+
+        if self.limit.requests_per_minute.is_some() {
+            self.requests -= 1.0;
+        }
+        self.tokens -= needed;
+        None
+    }
+
+    fn block_for(&mut self, wait: Duration) {
+        let until = Instant::now() + wait;
+        if self.blocked_until.is_none_or(|current| current < until) {
+            self.blocked_until = Some(until);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    default_limit: RateLimit,
+    model_limits: HashMap<String, RateLimit>,
+    budgets: HashMap<String, Budget>,
+}
+
+impl LimiterState {
+    fn budget(&mut self, model_id: &str) -> &mut Budget {
+        if !self.budgets.contains_key(model_id) {
+            let limit = self
+                .model_limits
+                .get(model_id)
+                .copied()
+                .unwrap_or(self.default_limit);
+            self.budgets.insert(model_id.to_string(), Budget::new(limit));
+        }
+        self.budgets.get_mut(model_id).unwrap()
+    }
+}
+
+// Client-side rate limiter, shared by every clone of the LLMClient it is attached to
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    state: Arc<Mutex<LimiterState>>,
+    max_retries: u32,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimit::default())
+    }
+}
+
+impl RateLimiter {
+    // Limits applied to every model without its own entry
+    pub fn new(default_limit: RateLimit) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(LimiterState {
+                default_limit,
+                model_limits: HashMap::new(),
+                budgets: HashMap::new(),
+            })),
+            max_retries: 5,
+        }
+    }
+
+    pub fn model_limit(self, model_id: &str, limit: RateLimit) -> Self {
+        {
+            let mut state = self.state.lock();
+            state.model_limits.insert(model_id.to_string(), limit);
+            state.budgets.remove(model_id);
+        }
+        self
+    }
+
+    // How many times a request rejected with 429 is retried before giving up
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    pub fn get_max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    // Wait until the model has budget for one request of the estimated size
+    pub async fn acquire(&self, model_id: &str, estimated_tokens: u32) {
+        loop {
+            let wait = self.state.lock().budget(model_id).try_take(estimated_tokens);
+            match wait {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => return,
+            }
+        }
+    }
+
+    // Hold back every request for the model, e.g. after a 429 with Retry-After
+    pub fn defer(&self, model_id: &str, wait: Duration) {
+        self.state.lock().budget(model_id).block_for(wait);
+    }
+
+    // Sync the budget with the provider's x-ratelimit-* response headers; `window` is what
+    // the provider's request headers count, see Provider::request_window()
+    pub fn update_from_headers(&self, model_id: &str, window: RequestWindow, headers: &HeaderMap) {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+        let mut state = self.state.lock();
+        let budget = state.budget(model_id);
+        let now = Instant::now();
+        budget.refill(now);
+
+        if budget.limit.tokens_per_minute.is_none() {
+            if let Some(limit) = header("x-ratelimit-limit-tokens").and_then(|v| v.parse().ok()) {
+                budget.limit.tokens_per_minute = Some(limit);
+            }
+        }
+
+        if let Some(remaining) = header("x-ratelimit-remaining-tokens").and_then(|v| v.parse::<f64>().ok()) {
+            budget.tokens = remaining;
+            if remaining <= 0.0 {
+                if let Some(reset) = header("x-ratelimit-reset-tokens").and_then(parse_reset) {
+                    budget.block_for(reset);
+                }
+            }
+        }
+
+        if let Some(remaining) = header("x-ratelimit-remaining-requests").and_then(|v| v.parse::<f64>().ok()) {
+            if window == RequestWindow::Minute && budget.limit.requests_per_minute.is_some() {
+                budget.requests = budget.requests.min(remaining);
+            }
+            if remaining <= 0.0 {
+                if let Some(reset) = header("x-ratelimit-reset-requests").and_then(parse_reset) {
+                    budget.block_for(reset);
+                }
+            }
+        }
+
+        if let Some(retry_after) = header("retry-after").and_then(parse_reset) {
+            budget.block_for(retry_after);
+        }
+    }
+}
+
+// Parse reset durations such as "7.66s", "2m59.56s", "120ms" or a plain number of seconds
+pub(crate) fn parse_reset(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Duration::try_from_secs_f64(seconds).ok();
+    }
+
+    let mut total = 0.0;
+    let mut number = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let amount: f64 = number.parse().ok()?;
+        number.clear();
+        total += match c {
+            'h' => amount * 3600.0,
+            'm' if chars.peek() == Some(&'s') => {
+                chars.next();
+                amount / 1000.0
+            }
+            'm' => amount * 60.0,
+            's' => amount,
+            _ => return None,
+        };
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Duration::try_from_secs_f64(total).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn remaining(limiter: &RateLimiter, model_id: &str) -> (f64, f64) {
+        let mut state = limiter.state.lock();
+        let budget = state.budget(model_id);
+        (budget.requests, budget.tokens)
+    }
+
+    #[test]
+    fn parse_reset_reads_provider_durations() {
+        assert_eq!(parse_reset("7.66s"), Some(Duration::from_millis(7660)));
+        assert_eq!(parse_reset("2m59.56s"), Some(Duration::from_millis(179_560)));
+        assert_eq!(parse_reset("120ms"), Some(Duration::from_millis(120)));
+        assert_eq!(parse_reset("1h2m"), Some(Duration::from_secs(3720)));
+        assert_eq!(parse_reset(" 30 "), Some(Duration::from_secs(30)));
+        assert_eq!(parse_reset("1.5"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_reset("soon"), None);
+        assert_eq!(parse_reset("5d"), None);
+        assert_eq!(parse_reset("12"), Some(Duration::from_secs(12)));
+        assert_eq!(parse_reset("3m12"), None);
+        assert_eq!(parse_reset("-1"), None);
+    }
+
+    #[test]
+    fn try_take_spends_requests_then_waits_for_the_refill() {
+        let mut budget = Budget::new(RateLimit::requests_per_minute(2));
+
+        assert_eq!(budget.try_take(0), None);
+        assert_eq!(budget.try_take(0), None);
+        let wait = budget.try_take(0).unwrap();
+        // One request comes back every 30 seconds
+        assert!(wait > Duration::from_secs(29) && wait <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn try_take_caps_requests_larger_than_the_token_budget() {
+        let mut budget = Budget::new(RateLimit::tokens_per_minute(1000));
+
+        assert_eq!(budget.try_take(600), None);
+        let wait = budget.try_take(600).unwrap();
+        assert!(wait > Duration::from_secs(11) && wait <= Duration::from_secs(12));
+
+        // Larger than the whole budget: waits for a full budget instead of forever
+        let mut budget = Budget::new(RateLimit::tokens_per_minute(1000));
+        assert_eq!(budget.try_take(5000), None);
+        assert!(budget.try_take(1).is_some());
+    }
+
+    #[test]
+    fn try_take_without_limits_never_waits() {
+        let mut budget = Budget::new(RateLimit::default());
+
+        for _ in 0..1000 {
+            assert_eq!(budget.try_take(100_000), None);
+        }
+    }
+
+    #[test]
+    fn blocked_budgets_wait_until_the_block_ends() {
+        let mut budget = Budget::new(RateLimit::default());
+        budget.block_for(Duration::from_secs(5));
+        // A shorter block does not shorten the current one
+        budget.block_for(Duration::from_secs(1));
+
+        let wait = budget.try_take(0).unwrap();
+        assert!(wait > Duration::from_secs(4) && wait <= Duration::from_secs(5));
+    }
+
+    #[test]
+    fn headers_sync_the_per_minute_budget() {
+        let limiter = RateLimiter::new(RateLimit::requests_per_minute(30));
+
+        limiter.update_from_headers(
+            "m",
+            RequestWindow::Minute,
+            &headers(&[
+                ("x-ratelimit-limit-tokens", "6000"),
+                ("x-ratelimit-remaining-tokens", "1500"),
+                ("x-ratelimit-remaining-requests", "4"),
+            ]),
+        );
+
+        let (requests, tokens) = remaining(&limiter, "m");
+        assert!((4.0..4.1).contains(&requests));
+        assert!((1500.0..1510.0).contains(&tokens));
+        assert_eq!(
+            limiter.state.lock().budget("m").limit.tokens_per_minute,
+            Some(6000)
+        );
+    }
+
+    #[test]
+    fn daily_request_headers_leave_the_per_minute_budget_alone() {
+        let limiter = RateLimiter::new(RateLimit::requests_per_minute(30));
+
+        limiter.update_from_headers(
+            "m",
+            RequestWindow::Day,
+            &headers(&[("x-ratelimit-remaining-requests", "4")]),
+        );
+
+        let (requests, _) = remaining(&limiter, "m");
+        assert!(requests >= 29.9);
+        assert_eq!(limiter.state.lock().budget("m").try_take(0), None);
+    }
+
+    #[test]
+    fn exhausted_budgets_block_until_the_reset() {
+        for window in [RequestWindow::Minute, RequestWindow::Day] {
+            let limiter = RateLimiter::default();
+
+            limiter.update_from_headers(
+                "m",
+                window,
+                &headers(&[
+                    ("x-ratelimit-remaining-requests", "0"),
+                    ("x-ratelimit-reset-requests", "2m59.56s"),
+                ]),
+            );
+
+            let wait = limiter.state.lock().budget("m").try_take(0).unwrap();
+            assert!(wait > Duration::from_secs(179) && wait <= Duration::from_millis(179_560));
+        }
+
+        let limiter = RateLimiter::default();
+        limiter.update_from_headers("m", RequestWindow::Minute, &headers(&[("retry-after", "7")]));
+        assert!(limiter.state.lock().budget("m").try_take(0).is_some());
+        // Other models keep their own budget
+        assert_eq!(limiter.state.lock().budget("other").try_take(0), None);
+    }
+}
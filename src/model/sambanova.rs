@@ -1,6 +1,6 @@
 use super::base::{define_provider_models, Model, Provider};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct SambaNova;
 
 impl Provider for SambaNova {
@@ -9,6 +9,14 @@ impl Provider for SambaNova {
     fn provider_name() -> &'static str {
         "sambanova"
     }
+
+    fn default_base_url() -> Option<&'static str> {
+        Some("https://api.sambanova.ai/v1")
+    }
+
+    fn supports_stream_usage() -> bool {
+        true
+    }
 }
 
 define_provider_models!(SambaNova, SambaNovaModel, {
@@ -0,0 +1,81 @@
+use async_trait::async_trait;
+use futures::stream::{Stream, StreamExt};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, StatusCode};
+use std::fmt;
+use std::pin::Pin;
+
+// Response body as it arrives from the network
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Vec<u8>, String>> + Send>>;
+
+// A POST request to a chat completions endpoint
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: ByteStream,
+}
+
+impl HttpResponse {
+    // Read the whole body, e.g. for error messages
+    pub async fn text(mut self) -> Result<String, String> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = self.body.next().await {
+            bytes.extend(chunk?);
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+// Sends requests for an LLMClient; implement it to use another HTTP stack or to
+// answer requests in tests without a socket
+#[async_trait]
+pub trait Transport: Send + Sync + fmt::Debug {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, String>;
+}
+
+// Default transport over a reqwest Client
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    client: Client,
+}
+
+impl ReqwestTransport {
+    // Use a preconfigured client, e.g. with a proxy or custom TLS roots
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+impl From<Client> for ReqwestTransport {
+    fn from(client: Client) -> Self {
+        Self::new(client)
+    }
+}
+
+#[async_trait]
+impl Transport for ReqwestTransport {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, String> {
+        let mut builder = self.client.post(&request.url).body(request.body);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+
+        let response = builder.send().await.map_err(|e| e.to_string())?;
+        Ok(HttpResponse {
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: Box::pin(
+                response
+                    .bytes_stream()
+                    .map(|chunk| chunk.map(|bytes| bytes.to_vec()).map_err(|e| e.to_string())),
+            ),
+        })
+    }
+}
@@ -0,0 +1,399 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::utils::extract_all_json;
+
+const REPLY_FIELDS: [&str; 3] = ["response", "tool", "finished"];
+
+// The JSON object an agent model replies with: markdown for the user, an optional
+// tool call, and whether the task is done
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AgentReply {
+    #[serde(default)]
+    pub response: String,
+    #[serde(default)]
+    pub tool: Option<ToolInvocation>,
+    #[serde(default)]
+    pub finished: bool,
+}
+
+// A tool the model asks to run, e.g. {"name": "cli", "content": "ls -la"}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolInvocation {
+    pub name: String,
+    // Arguments given as a JSON object are kept as JSON text
+    pub content: String,
+}
+
+impl AgentReply {
+    // Parse a complete reply: the first JSON object in the content with any of the
+    // reply's fields, wherever it is (prose, fences and trailing commas are tolerated).
+    // Tool names are not checked; see AgentProtocol::parse.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let objects: Vec<Value> = extract_all_json(content)
+            .into_iter()
+            .filter(Value::is_object)
+            .collect();
+        let object = objects
+            .iter()
+            .find(|object| has_reply_field(object))
+            .or(objects.first())
+            .ok_or(
+                "The reply must be a JSON object with \"response\", \"tool\" and \
+                 \"finished\" fields, but none was found",
+            )?;
+        Self::from_value(object)
+    }
+
+    // Validate a parsed JSON object field by field, naming the field that is wrong
+    pub fn from_value(value: &Value) -> Result<Self, String> {
+        let object = value
+            .as_object()
+            .ok_or("The reply must be a JSON object, not an array or a single value")?;
+        if !has_reply_field(value) {
+            return Err(
+                "The reply has none of the \"response\", \"tool\" and \"finished\" fields"
+                    .to_string(),
+            );
+        }
+
+        let response = match object.get("response") {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(response)) => response.clone(),
+            Some(other) => {
+                return Err(format!(
+                    "\"response\" must be a string, got {}",
+                    kind(other)
+                ))
+            }
+        };
+
+        let tool = match object.get("tool") {
+            None | Some(Value::Null) => None,
+            Some(Value::Object(tool)) => {
+                let name = match tool.get("name") {
+                    Some(Value::String(name)) if !name.trim().is_empty() => name.clone(),
+                    Some(Value::String(_)) => {
+                        return Err("\"tool.name\" must not be empty".to_string())
+                    }
+                    Some(other) => {
+                        return Err(format!(
+                            "\"tool.name\" must be a string, got {}",
+                            kind(other)
+                        ))
+                    }
+                    None => return Err("\"tool\" is missing its \"name\"".to_string()),
+                };
+                let content = match tool.get("content") {
+                    Some(Value::String(content)) => content.clone(),
+                    // Arguments of tools with parameters
+                    Some(arguments @ Value::Object(_)) => arguments.to_string(),
+                    Some(other) => {
+                        return Err(format!(
+                            "\"tool.content\" must be a string or an object of arguments, got {}",
+                            kind(other)
+                        ))
+                    }
+                    None => return Err("\"tool\" is missing its \"content\"".to_string()),
+                };
+                Some(ToolInvocation { name, content })
+            }
+            Some(other) => {
+                return Err(format!(
+                    "\"tool\" must be null or an object with \"name\" and \"content\", got {}",
+                    kind(other)
+                ))
+            }
+        };
+
+        let finished = match object.get("finished") {
+            None | Some(Value::Null) => false,
+            Some(Value::Bool(finished)) => *finished,
+            Some(other) => {
+                return Err(format!(
+                    "\"finished\" must be true or false, got {}",
+                    kind(other)
+                ))
+            }
+        };
+
+        Ok(Self {
+            response,
+            tool,
+            finished,
+        })
+    }
+}
+
+fn has_reply_field(object: &Value) -> bool {
+    REPLY_FIELDS.iter().any(|key| object.get(key).is_some())
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+// A tool the model may call, as described to it in the system prompt
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    // JSON schema of the arguments, for tools whose "content" is a JSON object
+    pub parameters: Option<Value>,
+}
+
+// The reply format agreed with the model: which tools it may call, the system prompt
+// explaining the format, and parsing that checks replies against both
+#[derive(Debug, Clone, Default)]
+pub struct AgentProtocol {
+    tools: Vec<ToolSpec>,
+}
+
+impl AgentProtocol {
+    // A protocol without tools; add them with tool()
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The protocol the crate's helpers assume: a single "cli" tool running shell commands
+    pub fn cli() -> Self {
+        Self::new().tool(
+            "cli",
+            "Run a shell command; \"content\" is the command line",
+        )
+    }
+
+    pub fn tool(self, name: &str, description: &str) -> Self {
+        self.tool_spec(ToolSpec {
+            name: name.to_string(),
+            description: description.to_string(),
+            parameters: None,
+        })
+    }
+
+    // Add a tool, e.g. one taking arguments described by a JSON schema
+    pub fn tool_spec(mut self, spec: ToolSpec) -> Self {
+        self.tools.retain(|tool| tool.name != spec.name);
+        self.tools.push(spec);
+        self
+    }
+
+    pub fn get_tools(&self) -> &[ToolSpec] {
+        &self.tools
+    }
+
+    // The single entry point for model replies: AgentReply::parse, then the tool name is
+    // checked against the protocol's tools, and a tool call must not claim to be finished.
+    // Errors are written to be sent back to the model.
+    pub fn parse(&self, content: &str) -> Result<AgentReply, String> {
+        let reply = AgentReply::parse(content)?;
+        if let Some(tool) = &reply.tool {
+            if reply.finished {
+                return Err(format!(
+                    "\"finished\" must be false when calling the tool \"{}\": its output is sent back to you first",
+                    tool.name
+                ));
+            }
+            if !self.tools.iter().any(|spec| spec.name == tool.name) {
+                return Err(if self.tools.is_empty() {
+                    format!("Unknown tool \"{}\": no tools are available", tool.name)
+                } else {
+                    format!(
+                        "Unknown tool \"{}\": available tools are {}",
+                        tool.name,
+                        self.tool_names().join(", ")
+                    )
+                });
+            }
+        }
+        Ok(reply)
+    }
+
+    // Instructions describing the reply format and the tools, to append to a system prompt
+    pub fn system_prompt(&self) -> String {
+        let mut prompt = String::from(
+            "Reply with a single JSON object and nothing else, in this format:\n\
+             {\"response\": \"...\", \"tool\": null, \"finished\": false}\n\n\
+             - \"response\": your message to the user, in markdown.\n",
+        );
+        if self.tools.is_empty() {
+            prompt.push_str("- \"tool\": always null; no tools are available.\n");
+        } else {
+            prompt.push_str(
+                "- \"tool\": null, or {\"name\": \"...\", \"content\": \"...\"} to call one tool. \
+                 Its output is sent back to you in the next message. Available tools:\n",
+            );
+            for tool in &self.tools {
+                prompt.push_str(&format!("  - \"{}\": {}", tool.name, tool.description));
+                if let Some(parameters) = &tool.parameters {
+                    prompt.push_str(&format!(
+                        "\n    \"content\" is a JSON object with this schema: {}",
+                        parameters
+                    ));
+                }
+                prompt.push('\n');
+            }
+        }
+        prompt.push_str(
+            "- \"finished\": true once the task is complete and no tool call is needed, otherwise false.\n",
+        );
+        prompt
+    }
+
+    fn tool_names(&self) -> Vec<String> {
+        self.tools
+            .iter()
+            .map(|tool| format!("\"{}\"", tool.name))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protocol() -> AgentProtocol {
+        AgentProtocol::cli().tool("read_file", "Read a file")
+    }
+
+    fn tool(name: &str, content: &str) -> Option<ToolInvocation> {
+        Some(ToolInvocation {
+            name: name.to_string(),
+            content: content.to_string(),
+        })
+    }
+
+    #[test]
+    fn replies_are_found_in_prose_and_fences() {
+        let content = "Here you go:\n```json\n{\"response\": \"Listing\", \"tool\": {\"name\": \"cli\", \"content\": \"ls\"}, \"finished\": false,}\n```";
+
+        let reply = protocol().parse(content).unwrap();
+
+        assert_eq!(
+            reply,
+            AgentReply {
+                response: "Listing".to_string(),
+                tool: tool("cli", "ls"),
+                finished: false
+            }
+        );
+    }
+
+    #[test]
+    fn missing_fields_take_their_defaults() {
+        assert_eq!(
+            AgentReply::parse(r#"{"response": "Done", "finished": true}"#).unwrap(),
+            AgentReply {
+                response: "Done".to_string(),
+                tool: None,
+                finished: true
+            }
+        );
+        assert_eq!(
+            AgentReply::parse(r#"{"finished": null, "tool": null}"#).unwrap(),
+            AgentReply::default()
+        );
+    }
+
+    #[test]
+    fn argument_objects_are_kept_as_json_text() {
+        let reply = protocol()
+            .parse(r#"{"tool": {"name": "read_file", "content": {"path": "a.txt"}}}"#)
+            .unwrap();
+
+        assert_eq!(reply.tool, tool("read_file", r#"{"path":"a.txt"}"#));
+    }
+
+    #[test]
+    fn unknown_tools_are_rejected_with_the_available_ones() {
+        let content = r#"{"tool": {"name": "rm", "content": "-rf /"}}"#;
+
+        assert_eq!(
+            protocol().parse(content).unwrap_err(),
+            r#"Unknown tool "rm": available tools are "cli", "read_file""#
+        );
+        assert_eq!(
+            AgentProtocol::new().parse(content).unwrap_err(),
+            r#"Unknown tool "rm": no tools are available"#
+        );
+        // AgentReply alone does not know the tools
+        assert!(AgentReply::parse(content).is_ok());
+    }
+
+    #[test]
+    fn incomplete_tool_calls_are_rejected() {
+        let error = |content: &str| protocol().parse(content).unwrap_err();
+
+        assert_eq!(
+            error(r#"{"tool": {"name": "cli"}}"#),
+            r#""tool" is missing its "content""#
+        );
+        assert_eq!(
+            error(r#"{"tool": {"content": "ls"}}"#),
+            r#""tool" is missing its "name""#
+        );
+        assert_eq!(
+            error(r#"{"tool": {"name": " ", "content": "ls"}}"#),
+            r#""tool.name" must not be empty"#
+        );
+        assert_eq!(
+            error(r#"{"tool": {"name": "cli", "content": ["ls"]}}"#),
+            r#""tool.content" must be a string or an object of arguments, got an array"#
+        );
+        assert_eq!(
+            error(r#"{"tool": "cli"}"#),
+            r#""tool" must be null or an object with "name" and "content", got a string"#
+        );
+    }
+
+    #[test]
+    fn fields_of_the_wrong_type_are_named() {
+        let error = |content: &str| AgentReply::parse(content).unwrap_err();
+
+        assert_eq!(
+            error(r#"{"response": 42}"#),
+            r#""response" must be a string, got a number"#
+        );
+        assert_eq!(
+            error(r#"{"finished": "yes"}"#),
+            r#""finished" must be true or false, got a string"#
+        );
+        assert!(error(r#"{"answer": "42"}"#).starts_with("The reply has none of"));
+        assert!(error("no json at all").starts_with("The reply must be a JSON object"));
+    }
+
+    #[test]
+    fn a_tool_call_cannot_also_finish_the_task() {
+        let content =
+            r#"{"response": "", "tool": {"name": "cli", "content": "ls"}, "finished": true}"#;
+
+        assert_eq!(
+            protocol().parse(content).unwrap_err(),
+            r#""finished" must be false when calling the tool "cli": its output is sent back to you first"#
+        );
+    }
+
+    #[test]
+    fn the_system_prompt_lists_the_tools() {
+        let with_schema = protocol().tool_spec(ToolSpec {
+            name: "search".to_string(),
+            description: "Search the web".to_string(),
+            parameters: Some(serde_json::json!({"type": "object"})),
+        });
+
+        let prompt = with_schema.system_prompt();
+
+        assert!(prompt.contains("  - \"cli\": Run a shell command"));
+        assert!(prompt.contains("  - \"search\": Search the web\n    \"content\" is a JSON object with this schema: {\"type\":\"object\"}"));
+        assert!(AgentProtocol::new()
+            .system_prompt()
+            .contains("always null; no tools are available"));
+    }
+}
@@ -0,0 +1,77 @@
+use anyhow::Result;
+
+// Column alignment from a table's separator row, e.g. `|:---:|`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TableAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+// Parts of a markdown document; every Start is matched by an End of the same element
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarkdownElement {
+    Paragraph,
+    Heading(usize), // Level 1-6
+    Blockquote,
+    // Start number of an ordered list, None for bullets
+    List(Option<usize>),
+    Item(Option<usize>),
+    CodeBlock(String), // Language, empty if not given
+    Table,
+    TableRow,
+    TableCell { header: bool, align: TableAlign },
+    Rule,
+    Bold,
+    Italic,
+    BoldItalic,
+    Strikethrough,
+    InlineCode,
+    Link(String), // Url
+}
+
+impl MarkdownElement {
+    pub fn is_block(&self) -> bool {
+        matches!(
+            self,
+            MarkdownElement::Paragraph
+                | MarkdownElement::Heading(_)
+                | MarkdownElement::Blockquote
+                | MarkdownElement::List(_)
+                | MarkdownElement::Item(_)
+                | MarkdownElement::CodeBlock(_)
+                | MarkdownElement::Table
+                | MarkdownElement::Rule
+        )
+    }
+}
+
+// What MarkdownParser produces as markdown streams in. Text is unstyled; a "\n" in it
+// is a line break inside a paragraph or code block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarkdownEvent {
+    Start(MarkdownElement),
+    End(MarkdownElement),
+    Text(String),
+}
+
+// Turns markdown events into output, e.g. a terminal, HTML or plain text
+pub trait MarkdownSink {
+    fn event(&mut self, event: &MarkdownEvent) -> Result<()>;
+
+    // Write out anything buffered so far
+    fn flush(&mut self) -> Result<()>;
+
+    // Called once the stream has ended
+    fn finish(&mut self) -> Result<()> {
+        self.flush()
+    }
+
+    fn render(&mut self, events: &[MarkdownEvent]) -> Result<()> {
+        for event in events {
+            self.event(event)?;
+        }
+        self.flush()
+    }
+}
@@ -0,0 +1,98 @@
+use crossterm::style::Color;
+
+// Language of a fenced code block from its info string,
+// e.g. "rust" for "```rust", "```Rust ignore", "```rust,no_run" or "```{.rust}"
+pub(crate) fn code_language(info: &str) -> Option<String> {
+    let word = info.split_whitespace().next()?;
+    let word = word.trim_start_matches('{').trim_start_matches('.');
+    let language = word
+        .split([',', '}'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    if language.is_empty() {
+        None
+    } else {
+        Some(language)
+    }
+}
+
+// Colors code one line at a time, keeping the parser state between lines
+#[cfg(feature = "syntax-highlighting")]
+pub(crate) struct CodeHighlighter {
+    lines: syntect::easy::HighlightLines<'static>,
+}
+
+#[cfg(feature = "syntax-highlighting")]
+mod assets {
+    use std::sync::OnceLock;
+    use syntect::highlighting::{Theme, ThemeSet};
+    use syntect::parsing::SyntaxSet;
+
+    pub fn syntaxes() -> &'static SyntaxSet {
+        static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+        SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+    }
+
+    // Falls back to base16-ocean.dark for unknown names
+    pub fn theme(name: &str) -> &'static Theme {
+        static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+        let themes = &THEMES.get_or_init(ThemeSet::load_defaults).themes;
+        themes
+            .get(name)
+            .or_else(|| themes.get("base16-ocean.dark"))
+            .expect("syntect ships base16-ocean.dark")
+    }
+}
+
+#[cfg(feature = "syntax-highlighting")]
+impl CodeHighlighter {
+    // None when the language is not known, so the block is printed plain.
+    // `theme` names one of syntect's default themes, e.g. "InspiredGitHub".
+    pub fn for_language(language: &str, theme: &str) -> Option<Self> {
+        let syntaxes = assets::syntaxes();
+        let syntax = syntaxes
+            .find_syntax_by_token(language)
+            .or_else(|| syntaxes.find_syntax_by_extension(language))?;
+        Some(Self {
+            lines: syntect::easy::HighlightLines::new(syntax, assets::theme(theme)),
+        })
+    }
+
+    // `line` includes its trailing newline
+    pub fn highlight_line(&mut self, line: &str) -> Vec<(Color, String)> {
+        match self.lines.highlight_line(line, assets::syntaxes()) {
+            Ok(ranges) => ranges
+                .into_iter()
+                .map(|(style, text)| {
+                    let color = style.foreground;
+                    (
+                        Color::Rgb {
+                            r: color.r,
+                            g: color.g,
+                            b: color.b,
+                        },
+                        text.to_string(),
+                    )
+                })
+                .collect(),
+            Err(_) => vec![(Color::Reset, line.to_string())],
+        }
+    }
+}
+
+// Without the `syntax-highlighting` feature every block is printed plain
+#[cfg(not(feature = "syntax-highlighting"))]
+pub(crate) struct CodeHighlighter;
+
+#[cfg(not(feature = "syntax-highlighting"))]
+impl CodeHighlighter {
+    pub fn for_language(_language: &str, _theme: &str) -> Option<Self> {
+        None
+    }
+
+    pub fn highlight_line(&mut self, line: &str) -> Vec<(Color, String)> {
+        vec![(Color::Reset, line.to_string())]
+    }
+}
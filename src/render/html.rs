@@ -0,0 +1,193 @@
+use anyhow::Result;
+use std::io::Write;
+
+use super::events::{MarkdownElement, MarkdownEvent, MarkdownSink, TableAlign};
+
+// Writes markdown events as HTML fragments; the fragments of a stream concatenate into
+// one well-formed document body. Text and attributes are escaped.
+pub struct HtmlSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> HtmlSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> MarkdownSink for HtmlSink<W> {
+    fn event(&mut self, event: &MarkdownEvent) -> Result<()> {
+        let html = match event {
+            MarkdownEvent::Start(element) => open_tag(element),
+            MarkdownEvent::End(element) => close_tag(element),
+            MarkdownEvent::Text(text) => escape(text),
+        };
+        self.writer.write_all(html.as_bytes())?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn open_tag(element: &MarkdownElement) -> String {
+    match element {
+        MarkdownElement::Paragraph => "<p>".to_string(),
+        MarkdownElement::Heading(level) => format!("<h{}>", level),
+        MarkdownElement::Blockquote => "<blockquote>\n".to_string(),
+        MarkdownElement::List(None) => "<ul>\n".to_string(),
+        MarkdownElement::List(Some(1)) => "<ol>\n".to_string(),
+        MarkdownElement::List(Some(start)) => format!("<ol start=\"{}\">\n", start),
+        MarkdownElement::Item(_) => "<li>".to_string(),
+        MarkdownElement::CodeBlock(language) if language.is_empty() => "<pre><code>".to_string(),
+        MarkdownElement::CodeBlock(language) => {
+            format!("<pre><code class=\"language-{}\">", escape(language))
+        }
+        MarkdownElement::Table => "<table>\n".to_string(),
+        MarkdownElement::TableRow => "<tr>".to_string(),
+        MarkdownElement::TableCell { header, align } => {
+            let tag = if *header { "th" } else { "td" };
+            match align {
+                TableAlign::Left => format!("<{}>", tag),
+                TableAlign::Center => format!("<{} style=\"text-align: center\">", tag),
+                TableAlign::Right => format!("<{} style=\"text-align: right\">", tag),
+            }
+        }
+        MarkdownElement::Rule => "<hr>".to_string(),
+        MarkdownElement::Bold => "<strong>".to_string(),
+        MarkdownElement::Italic => "<em>".to_string(),
+        MarkdownElement::BoldItalic => "<strong><em>".to_string(),
+        MarkdownElement::Strikethrough => "<del>".to_string(),
+        MarkdownElement::InlineCode => "<code>".to_string(),
+        MarkdownElement::Link(url) => format!("<a href=\"{}\">", escape(safe_url(url))),
+    }
+}
+
+fn close_tag(element: &MarkdownElement) -> String {
+    match element {
+        MarkdownElement::Paragraph => "</p>\n".to_string(),
+        MarkdownElement::Heading(level) => format!("</h{}>\n", level),
+        MarkdownElement::Blockquote => "</blockquote>\n".to_string(),
+        MarkdownElement::List(None) => "</ul>\n".to_string(),
+        MarkdownElement::List(Some(_)) => "</ol>\n".to_string(),
+        MarkdownElement::Item(_) => "</li>\n".to_string(),
+        MarkdownElement::CodeBlock(_) => "</code></pre>\n".to_string(),
+        MarkdownElement::Table => "</table>\n".to_string(),
+        MarkdownElement::TableRow => "</tr>\n".to_string(),
+        MarkdownElement::TableCell { header: true, .. } => "</th>".to_string(),
+        MarkdownElement::TableCell { header: false, .. } => "</td>".to_string(),
+        MarkdownElement::Rule => "\n".to_string(),
+        MarkdownElement::Bold => "</strong>".to_string(),
+        MarkdownElement::Italic => "</em>".to_string(),
+        MarkdownElement::BoldItalic => "</em></strong>".to_string(),
+        MarkdownElement::Strikethrough => "</del>".to_string(),
+        MarkdownElement::InlineCode => "</code>".to_string(),
+        MarkdownElement::Link(_) => "</a>".to_string(),
+    }
+}
+
+// Model output is untrusted: only web, mail and relative links are kept
+fn safe_url(url: &str) -> &str {
+    // Browsers skip leading whitespace and tabs or newlines inside the scheme
+    let scheme = url.split_once(':').map(|(scheme, _)| {
+        scheme
+            .chars()
+            .filter(|c| !c.is_whitespace() && !c.is_control())
+            .collect::<String>()
+            .to_lowercase()
+    });
+    match scheme.as_deref() {
+        None | Some("http") | Some("https") | Some("mailto") => url,
+        // A ':' after a path or query is not a scheme, e.g. "/a:b" or "?t=1:2"
+        Some(scheme) if scheme.contains(['/', '?', '#']) => url,
+        Some(_) => "#",
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(url: &str) -> String {
+        open_tag(&MarkdownElement::Link(url.to_string()))
+    }
+
+    #[test]
+    fn web_mail_and_relative_links_are_kept() {
+        assert_eq!(
+            safe_url("https://example.com/a?b=1:2"),
+            "https://example.com/a?b=1:2"
+        );
+        assert_eq!(safe_url("HTTP://example.com"), "HTTP://example.com");
+        assert_eq!(safe_url("mailto:ada@example.com"), "mailto:ada@example.com");
+        assert_eq!(safe_url("  https://example.com"), "  https://example.com");
+        assert_eq!(safe_url("/a:b"), "/a:b");
+        assert_eq!(safe_url("docs/page#part:2"), "docs/page#part:2");
+        assert_eq!(safe_url("?t=1:2"), "?t=1:2");
+        assert_eq!(safe_url("page.html"), "page.html");
+    }
+
+    #[test]
+    fn script_and_data_links_are_dropped() {
+        for url in [
+            "javascript:alert(1)",
+            "JaVaScript:alert(1)",
+            " javascript:alert(1)",
+            "\tjavascript:alert(1)",
+            "java\nscript:alert(1)",
+            "\u{1}javascript:alert(1)",
+            "data:text/html;base64,PHNjcmlwdD4=",
+            "vbscript:msgbox(1)",
+            "file:///etc/passwd",
+        ] {
+            assert_eq!(safe_url(url), "#", "{:?}", url);
+        }
+    }
+
+    #[test]
+    fn attributes_and_text_are_escaped() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+        assert_eq!(
+            link(r#"https://example.com/?a=1&b="><script>"#),
+            "<a href=\"https://example.com/?a=1&amp;b=&quot;&gt;&lt;script&gt;\">"
+        );
+        assert_eq!(link("/search?q='x'"), "<a href=\"/search?q=&#39;x&#39;\">");
+        assert_eq!(link("javascript:alert(\"x\")"), "<a href=\"#\">");
+        assert_eq!(
+            open_tag(&MarkdownElement::CodeBlock("rust\"><img".to_string())),
+            "<pre><code class=\"language-rust&quot;&gt;&lt;img\">"
+        );
+    }
+}
@@ -0,0 +1,583 @@
+// Decodes the body of a JSON string literal that arrives in pieces.
+// Escapes may be split across chunks, including `\uXXXX` surrogate pairs.
+#[derive(Debug, Clone, Default)]
+pub struct JsonStringUnescaper {
+    state: EscapeState,
+    // High half of a surrogate pair waiting for its low half
+    high_surrogate: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum EscapeState {
+    #[default]
+    Normal,
+    Escape,
+    Unicode { value: u16, digits: u8 },
+}
+
+impl JsonStringUnescaper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Whether the last character fed started an escape sequence that is not complete yet
+    pub fn in_escape(&self) -> bool {
+        self.state != EscapeState::Normal
+    }
+
+    pub fn feed(&mut self, text: &str) -> String {
+        let mut output = String::with_capacity(text.len());
+        for c in text.chars() {
+            self.push(c, &mut output);
+        }
+        output
+    }
+
+    pub fn push(&mut self, c: char, output: &mut String) {
+        match self.state {
+            EscapeState::Normal if c == '\\' => self.state = EscapeState::Escape,
+            EscapeState::Normal => {
+                self.flush_surrogate(output);
+                output.push(c);
+            }
+            EscapeState::Escape => {
+                self.state = EscapeState::Normal;
+                let decoded = match c {
+                    'u' => {
+                        self.state = EscapeState::Unicode { value: 0, digits: 0 };
+                        return;
+                    }
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    // `\"`, `\\`, `\/`, and anything unknown stands for itself
+                    other => other,
+                };
+                self.flush_surrogate(output);
+                output.push(decoded);
+            }
+            EscapeState::Unicode { value, digits } => match c.to_digit(16) {
+                Some(digit) => {
+                    let value = value << 4 | digit as u16;
+                    if digits < 3 {
+                        self.state = EscapeState::Unicode { value, digits: digits + 1 };
+                    } else {
+                        self.state = EscapeState::Normal;
+                        self.push_code_unit(value, output);
+                    }
+                }
+                None => {
+                    // Malformed escape: mark it and keep the character
+                    self.state = EscapeState::Normal;
+                    self.flush_surrogate(output);
+                    output.push(char::REPLACEMENT_CHARACTER);
+                    self.push(c, output);
+                }
+            },
+        }
+    }
+
+    // Emit anything still pending, e.g. a lone high surrogate at the end of the string
+    pub fn finish(&mut self) -> String {
+        let mut output = String::new();
+        if self.in_escape() {
+            output.push(char::REPLACEMENT_CHARACTER);
+        }
+        self.state = EscapeState::Normal;
+        self.flush_surrogate(&mut output);
+        output
+    }
+
+    fn push_code_unit(&mut self, unit: u16, output: &mut String) {
+        match unit {
+            0xD800..=0xDBFF => {
+                self.flush_surrogate(output);
+                self.high_surrogate = Some(unit);
+            }
+            0xDC00..=0xDFFF => match self.high_surrogate.take() {
+                Some(high) => {
+                    let code = 0x10000 + ((high as u32 - 0xD800) << 10) + (unit as u32 - 0xDC00);
+                    output.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                None => output.push(char::REPLACEMENT_CHARACTER),
+            },
+            _ => {
+                self.flush_surrogate(output);
+                output.push(char::from_u32(unit as u32).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+        }
+    }
+
+    fn flush_surrogate(&mut self, output: &mut String) {
+        if self.high_surrogate.take().is_some() {
+            output.push(char::REPLACEMENT_CHARACTER);
+        }
+    }
+}
+
+// Where the parser is inside an object
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ObjectState {
+    Key,
+    Colon,
+    Value,
+    Comma,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Frame {
+    Object { key: String, state: ObjectState },
+    Array { index: usize, has_value: bool },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lex {
+    // Between tokens
+    Structure,
+    String { key: bool },
+    // Number, true, false or null
+    Literal(String),
+}
+
+// Incremental JSON parser for documents streamed by a model. It tracks the path of
+// every value, e.g. "tool.content" or "items.0.name", streams the decoded text of one
+// watched string as it arrives, and keeps the scalar values completed so far, so
+// partial documents can be inspected. Text before the first '{' or '[' (prose, a
+// ```json fence) is skipped, as are trailing commas. A new document starts over.
+#[derive(Debug, Clone)]
+pub struct JsonStreamParser {
+    watch: Option<String>,
+    stack: Vec<Frame>,
+    lex: Lex,
+    unescaper: JsonStringUnescaper,
+    // Decoded text of the current string
+    string: String,
+    // Whether the current string is the watched one
+    watching: bool,
+    complete: bool,
+    values: Vec<(String, serde_json::Value)>,
+}
+
+impl Default for JsonStreamParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JsonStreamParser {
+    pub fn new() -> Self {
+        Self {
+            watch: None,
+            stack: Vec::new(),
+            lex: Lex::Structure,
+            unescaper: JsonStringUnescaper::new(),
+            string: String::new(),
+            watching: false,
+            complete: false,
+            values: Vec::new(),
+        }
+    }
+
+    // Stream the string at `path` ("response", "tool.content", "items.0") from feed()
+    pub fn watch(mut self, path: &str) -> Self {
+        self.watch = Some(path.to_string());
+        self
+    }
+
+    pub fn get_watch(&self) -> Option<&str> {
+        self.watch.as_deref()
+    }
+
+    // Feed the next chunk of JSON; returns the new text of the watched string
+    pub fn feed(&mut self, chunk: &str) -> String {
+        let mut output = String::new();
+        for c in chunk.chars() {
+            self.push(c, &mut output);
+        }
+        output
+    }
+
+    // Value at `path` once it is complete. Only scalars and strings are kept;
+    // objects and arrays are reached through their members.
+    pub fn get(&self, path: &str) -> Option<&serde_json::Value> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(key, _)| key == path)
+            .map(|(_, value)| value)
+    }
+
+    // The string at `path` so far, complete or not
+    pub fn get_str(&self, path: &str) -> Option<&str> {
+        if matches!(self.lex, Lex::String { key: false }) && self.path() == path {
+            return Some(&self.string);
+        }
+        self.get(path)?.as_str()
+    }
+
+    // Whether the parser is inside the watched string
+    pub fn in_watched_value(&self) -> bool {
+        self.watching
+    }
+
+    // Whether a whole document has been read
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    // Path of the current position, e.g. "tool.name"
+    pub fn path(&self) -> String {
+        let mut segments = Vec::new();
+        for frame in &self.stack {
+            match frame {
+                Frame::Object { key, state } => {
+                    if matches!(state, ObjectState::Colon | ObjectState::Value | ObjectState::Comma) {
+                        segments.push(key.clone());
+                    }
+                }
+                Frame::Array { index, .. } => segments.push(index.to_string()),
+            }
+        }
+        segments.join(".")
+    }
+
+    fn push(&mut self, c: char, output: &mut String) {
+        match &mut self.lex {
+            Lex::String { key } => {
+                let key = *key;
+                if c == '"' && !self.unescaper.in_escape() {
+                    let rest = self.unescaper.finish();
+                    self.string.push_str(&rest);
+                    if self.watching {
+                        output.push_str(&rest);
+                    }
+                    self.watching = false;
+                    self.lex = Lex::Structure;
+                    let string = std::mem::take(&mut self.string);
+                    if key {
+                        if let Some(Frame::Object { key, state }) = self.stack.last_mut() {
+                            *key = string;
+                            *state = ObjectState::Colon;
+                        }
+                    } else {
+                        self.value(serde_json::Value::String(string));
+                    }
+                    return;
+                }
+                let start = self.string.len();
+                self.unescaper.push(c, &mut self.string);
+                if self.watching {
+                    output.push_str(&self.string[start..]);
+                }
+            }
+            Lex::Literal(literal) => {
+                if c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.') {
+                    literal.push(c);
+                    return;
+                }
+                let literal = std::mem::take(literal);
+                self.lex = Lex::Structure;
+                let value = serde_json::from_str(&literal).unwrap_or(serde_json::Value::String(literal));
+                self.value(value);
+                self.push(c, output);
+            }
+            Lex::Structure => self.structure(c),
+        }
+    }
+
+    fn structure(&mut self, c: char) {
+        if c.is_whitespace() {
+            return;
+        }
+        if self.stack.is_empty() {
+            // Before the document, or after it: wait for the next one
+            match c {
+                '{' | '[' => {
+                    self.values.clear();
+                    self.complete = false;
+                    self.open(c);
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match c {
+            '}' | ']' => {
+                self.stack.pop();
+                self.after_value();
+                if self.stack.is_empty() {
+                    self.complete = true;
+                }
+            }
+            ':' => {
+                if let Some(Frame::Object { state, .. }) = self.stack.last_mut() {
+                    if *state == ObjectState::Colon {
+                        *state = ObjectState::Value;
+                    }
+                }
+            }
+            ',' => match self.stack.last_mut() {
+                Some(Frame::Object { key, state }) => {
+                    key.clear();
+                    *state = ObjectState::Key;
+                }
+                Some(Frame::Array { index, has_value }) => {
+                    if *has_value {
+                        *index += 1;
+                    }
+                    *has_value = false;
+                }
+                None => {}
+            },
+            '"' => {
+                let key = matches!(self.stack.last(), Some(Frame::Object { state: ObjectState::Key, .. }));
+                if !key && !self.expects_value() {
+                    return;
+                }
+                self.unescaper = JsonStringUnescaper::new();
+                self.string.clear();
+                self.watching = !key && self.watch.as_deref() == Some(self.path().as_str());
+                self.lex = Lex::String { key };
+            }
+            '{' | '[' if self.expects_value() => self.open(c),
+            _ if self.expects_value() => self.lex = Lex::Literal(c.to_string()),
+            // Anything else is malformed; skip it
+            _ => {}
+        }
+    }
+
+    fn expects_value(&self) -> bool {
+        match self.stack.last() {
+            Some(Frame::Object { state, .. }) => *state == ObjectState::Value,
+            Some(Frame::Array { has_value, .. }) => !has_value,
+            None => false,
+        }
+    }
+
+    fn open(&mut self, c: char) {
+        self.stack.push(match c {
+            '{' => Frame::Object {
+                key: String::new(),
+                state: ObjectState::Key,
+            },
+            _ => Frame::Array {
+                index: 0,
+                has_value: false,
+            },
+        });
+    }
+
+    fn value(&mut self, value: serde_json::Value) {
+        self.values.push((self.path(), value));
+        self.after_value();
+    }
+
+    fn after_value(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Object { state, .. }) => *state = ObjectState::Comma,
+            Some(Frame::Array { has_value, .. }) => *has_value = true,
+            None => {}
+        }
+    }
+}
+
+// Pulls the string at a field path, e.g. "response" or "tool.content", out of a streamed
+// JSON object, unescaped, as soon as each piece arrives
+#[derive(Debug, Clone)]
+pub struct JsonFieldExtractor {
+    field: String,
+    parser: JsonStreamParser,
+}
+
+impl JsonFieldExtractor {
+    pub fn new(field: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            parser: JsonStreamParser::new().watch(field),
+        }
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    // Whether the extractor is inside the field's value
+    pub fn in_value(&self) -> bool {
+        self.parser.in_watched_value()
+    }
+
+    // The underlying parser, e.g. to check other fields of the document
+    pub fn parser(&self) -> &JsonStreamParser {
+        &self.parser
+    }
+
+    // Feed the next chunk of JSON; returns the new text of the field's value
+    pub fn feed(&mut self, chunk: &str) -> String {
+        self.parser.feed(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // Feed every chunk and concatenate the outputs
+    fn unescape(chunks: &[&str]) -> String {
+        let mut unescaper = JsonStringUnescaper::new();
+        let mut output: String = chunks.iter().map(|chunk| unescaper.feed(chunk)).collect();
+        output.push_str(&unescaper.finish());
+        output
+    }
+
+    fn feed_chars(parser: &mut JsonStreamParser, json: &str) -> String {
+        json.chars().map(|c| parser.feed(&c.to_string())).collect()
+    }
+
+    #[test]
+    fn escapes_split_across_chunks_are_decoded() {
+        let mut unescaper = JsonStringUnescaper::new();
+
+        assert_eq!(unescaper.feed(r"line\"), "line");
+        assert!(unescaper.in_escape());
+        assert_eq!(
+            unescaper.feed(r#"n\"quoted\" \\ \/ \t"#),
+            "\n\"quoted\" \\ / \t"
+        );
+        assert_eq!(unescaper.feed(r"caf\u00"), "caf");
+        assert_eq!(unescaper.feed("e9!"), "é!");
+        assert!(!unescaper.in_escape());
+        assert_eq!(unescaper.finish(), "");
+    }
+
+    #[test]
+    fn surrogate_pairs_are_joined_across_chunks() {
+        assert_eq!(unescape(&[r"😀"]), "😀");
+        assert_eq!(unescape(&[r"\uD83D", r"\uDE00"]), "😀");
+        assert_eq!(unescape(&[r"a\uD8", r"3D\u", r"DE", r"00b"]), "a😀b");
+    }
+
+    #[test]
+    fn lone_surrogates_become_replacement_characters() {
+        assert_eq!(unescape(&[r"\uD83Dx"]), "\u{FFFD}x");
+        assert_eq!(unescape(&[r"\uDE00x"]), "\u{FFFD}x");
+        assert_eq!(unescape(&[r"\uD83D😀"]), "\u{FFFD}😀");
+        assert_eq!(unescape(&[r"end\uD83D"]), "end\u{FFFD}");
+    }
+
+    #[test]
+    fn malformed_escapes_are_marked() {
+        assert_eq!(unescape(&[r"\u12G"]), "\u{FFFD}G");
+        assert_eq!(unescape(&[r"\q"]), "q");
+        assert_eq!(unescape(&[r"cut\u12"]), "cut\u{FFFD}");
+        assert_eq!(unescape(&[r"cut\"]), "cut\u{FFFD}");
+    }
+
+    #[test]
+    fn the_watched_field_streams_by_its_dotted_path() {
+        let json = r#"{"response": "not this", "tool": {"name": "write_file", "content": "a\nb \"c\" 😀"}, "content": "nor this"}"#;
+        let mut parser = JsonStreamParser::new().watch("tool.content");
+
+        let streamed = feed_chars(&mut parser, json);
+
+        assert_eq!(streamed, "a\nb \"c\" 😀");
+        assert!(parser.is_complete());
+        assert_eq!(parser.get("tool.name"), Some(&json!("write_file")));
+        assert_eq!(parser.get("response"), Some(&json!("not this")));
+        assert_eq!(parser.get("content"), Some(&json!("nor this")));
+    }
+
+    #[test]
+    fn array_elements_have_index_paths() {
+        let mut parser = JsonStreamParser::new().watch("items.1.name");
+
+        let streamed = parser.feed(
+            r#"{"items": [{"name": "a"}, {"name": "b", "tags": [1, 2.5, true, null]}], "n": -3}"#,
+        );
+
+        assert_eq!(streamed, "b");
+        assert_eq!(parser.get("items.0.name"), Some(&json!("a")));
+        assert_eq!(parser.get("items.1.tags.1"), Some(&json!(2.5)));
+        assert_eq!(parser.get("items.1.tags.2"), Some(&json!(true)));
+        assert_eq!(parser.get("items.1.tags.3"), Some(&json!(null)));
+        assert_eq!(parser.get("n"), Some(&json!(-3)));
+    }
+
+    #[test]
+    fn values_of_partial_documents_can_be_read() {
+        let mut parser = JsonStreamParser::new();
+
+        parser.feed(r#"{"response": "Hel"#);
+        assert_eq!(parser.path(), "response");
+        assert_eq!(parser.get_str("response"), Some("Hel"));
+        assert_eq!(parser.get("response"), None);
+
+        parser.feed(r#"lo", "count": 4"#);
+        assert_eq!(parser.get_str("response"), Some("Hello"));
+        assert_eq!(parser.get("response"), Some(&json!("Hello")));
+        // A number is only complete once something follows it
+        assert_eq!(parser.get("count"), None);
+
+        parser.feed(r#"2, "tool": {"name": "ls""#);
+        assert_eq!(parser.get("count"), Some(&json!(42)));
+        assert_eq!(parser.get("tool.name"), Some(&json!("ls")));
+        assert!(!parser.is_complete());
+
+        parser.feed("}}");
+        assert!(parser.is_complete());
+    }
+
+    #[test]
+    fn prose_fences_and_trailing_commas_are_skipped() {
+        let mut parser = JsonStreamParser::new().watch("response");
+
+        let streamed =
+            parser.feed("Sure:\n```json\n{\"response\": \"ok\", \"list\": [1, 2,],}\n```");
+
+        assert_eq!(streamed, "ok");
+        assert!(parser.is_complete());
+        assert_eq!(parser.get("list.1"), Some(&json!(2)));
+        assert_eq!(parser.get("list.2"), None);
+    }
+
+    #[test]
+    fn a_new_document_starts_over() {
+        let mut parser = JsonStreamParser::new().watch("response");
+
+        assert_eq!(parser.feed(r#"{"response": "one", "extra": 1}"#), "one");
+        assert_eq!(parser.feed(r#" {"response": "two"}"#), "two");
+
+        assert_eq!(parser.get("response"), Some(&json!("two")));
+        assert_eq!(parser.get("extra"), None);
+    }
+
+    #[test]
+    fn the_field_extractor_streams_its_field() {
+        let mut extractor = JsonFieldExtractor::new("tool.content");
+        let chunks = [
+            r#"{"tool": {"name": "write", "con"#,
+            r#"tent": "fn main() {\n"#,
+            r#"    println!(\"hi\");\n}"#,
+            r#""}}"#,
+        ];
+
+        let mut streamed = Vec::new();
+        let mut inside = Vec::new();
+        for chunk in chunks {
+            streamed.push(extractor.feed(chunk));
+            inside.push(extractor.in_value());
+        }
+
+        assert_eq!(extractor.field(), "tool.content");
+        assert_eq!(
+            streamed,
+            ["", "fn main() {\n", "    println!(\"hi\");\n}", ""]
+        );
+        assert_eq!(inside, [false, true, true, false]);
+        assert_eq!(extractor.parser().get("tool.name"), Some(&json!("write")));
+        assert!(extractor.parser().is_complete());
+    }
+}
@@ -0,0 +1,301 @@
+use anyhow::Result;
+use std::io::{stdout, IsTerminal, Stdout, Write};
+
+use super::events::MarkdownSink;
+use super::json::JsonFieldExtractor;
+use super::parser::MarkdownParser;
+use super::terminal::TerminalSink;
+use super::theme::RenderTheme;
+
+// What process_chunk() receives
+#[derive(Debug, Clone)]
+pub enum RenderMode {
+    // Markdown deltas, e.g. the content of `stream_chat` responses
+    Raw,
+    // A JSON object streamed by the model; the markdown is the value of one string field
+    Envelope(JsonFieldExtractor),
+}
+
+impl RenderMode {
+    pub fn envelope(field: &str) -> Self {
+        RenderMode::Envelope(JsonFieldExtractor::new(field))
+    }
+}
+
+// Renders streamed markdown with terminal colors to any writer (stdout by default)
+pub struct MarkdownStreamRenderer<W: Write = Stdout> {
+    mode: RenderMode,
+    parser: MarkdownParser,
+    sink: TerminalSink<W>,
+}
+
+impl Default for MarkdownStreamRenderer<Stdout> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MarkdownStreamRenderer<Stdout> {
+    // Wraps at the terminal width when stdout is a terminal, and prints plain text when it is not
+    pub fn new() -> Self {
+        let width = if stdout().is_terminal() {
+            crossterm::terminal::size().ok().map(|(columns, _)| columns as usize)
+        } else {
+            None
+        };
+        Self::with_writer(stdout())
+            .width(width)
+            .theme(RenderTheme::detect())
+    }
+}
+
+impl<W: Write> MarkdownStreamRenderer<W> {
+    // Write the styled output to `writer`, e.g. a file, a Vec<u8> or a TUI buffer
+    pub fn with_writer(writer: W) -> Self {
+        Self {
+            mode: RenderMode::envelope("response"),
+            parser: MarkdownParser::new(),
+            sink: TerminalSink::new(writer),
+        }
+    }
+
+    // Defaults to the "response" field of the JSON envelope
+    pub fn mode(mut self, mode: RenderMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    // Color fenced code by language; needs the `syntax-highlighting` feature
+    pub fn syntax_highlighting(mut self, enabled: bool) -> Self {
+        self.sink = self.sink.syntax_highlighting(enabled);
+        self
+    }
+
+    // Word wrap at `width` columns; None (the default for custom writers) never wraps
+    pub fn width(mut self, width: Option<usize>) -> Self {
+        self.sink = self.sink.width(width);
+        self
+    }
+
+    // Defaults to RenderTheme::from_env(), which honors NO_COLOR
+    pub fn theme(mut self, theme: RenderTheme) -> Self {
+        self.sink = self.sink.theme(theme);
+        self
+    }
+
+    // Print links as OSC 8 hyperlinks, or as "text (url)" when disabled
+    pub fn hyperlinks(mut self, enabled: bool) -> Self {
+        self.sink = self.sink.hyperlinks(enabled);
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.sink.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.sink.get_mut()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.sink.into_inner()
+    }
+
+    // Print whatever is still held back and reset the colors once the stream has ended
+    pub fn finish(&mut self) -> Result<()> {
+        let events = self.parser.finish();
+        self.sink.render(&events)?;
+        self.sink.finish()
+    }
+
+    // Render the next chunk of the stream according to the mode. Output goes to the
+    // writer, so the returned string is always empty.
+    pub fn process_chunk(&mut self, chunk: &str) -> String {
+        let text = match &mut self.mode {
+            RenderMode::Raw => chunk.to_string(),
+            RenderMode::Envelope(extractor) => extractor.feed(chunk),
+        };
+        if !text.is_empty() {
+            let _ = self.render_markdown(&text);
+        }
+        String::new()
+    }
+
+    // Render a plain markdown delta, e.g. from `stream_chat`, without looking for a JSON envelope
+    pub fn render_markdown(&mut self, text: &str) -> Result<()> {
+        let events = self.parser.feed(text);
+        self.sink.render(&events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(mode: RenderMode, theme: RenderTheme, chunks: &[&str]) -> String {
+        let mut renderer = MarkdownStreamRenderer::with_writer(Vec::new())
+            .mode(mode)
+            .theme(theme)
+            .width(Some(40))
+            .hyperlinks(false)
+            .syntax_highlighting(false);
+        for chunk in chunks {
+            renderer.process_chunk(chunk);
+        }
+        renderer.finish().unwrap();
+        String::from_utf8(renderer.into_inner()).unwrap()
+    }
+
+    const DOCUMENT: &str = "# Title\n\nSome **bold** text with `code`.\n\n- one\n- two\n  - nested\n\n```rust\nfn main() {}\n```\n\n| a | b |\n|---|---|\n| 1 | 22 |\n";
+
+    #[test]
+    fn headings_and_emphasis() {
+        let input = "# Title\n\nSome **bold** and *italic* text.\n\n## Section\n";
+
+        assert_eq!(
+            render(RenderMode::Raw, RenderTheme::plain(), &[input]),
+            "Title\n\nSome bold and italic text.\n\nSection\n"
+        );
+        assert_eq!(
+            render(RenderMode::Raw, RenderTheme::dark(), &[input]),
+            "\u{1b}[0m\u{1b}[38;5;13m\u{1b}[1mTitle\u{1b}[0m\n\nSome \u{1b}[0m\u{1b}[38;5;11m\u{1b}[1mbold\u{1b}[0m and \u{1b}[0m\u{1b}[38;5;12m\u{1b}[3mitalic\u{1b}[0m text.\n\n\u{1b}[0m\u{1b}[38;5;5m\u{1b}[1mSection\u{1b}[0m\n\u{1b}[0m\u{1b}[0m"
+        );
+    }
+
+    #[test]
+    fn lists() {
+        let input = "- one\n- two\n  - nested\n1. first\n2. second\n";
+
+        assert_eq!(
+            render(RenderMode::Raw, RenderTheme::plain(), &[input]),
+            "• one\n• two\n  ◦ nested\n\n1. first\n2. second\n"
+        );
+        assert_eq!(
+            render(RenderMode::Raw, RenderTheme::dark(), &["- one\n"]),
+            "\u{1b}[0m\u{1b}[38;5;10m• \u{1b}[0mone\n\u{1b}[0m\u{1b}[0m"
+        );
+    }
+
+    #[test]
+    fn inline_code() {
+        let input = "Use `cargo test` here.\n";
+
+        assert_eq!(
+            render(RenderMode::Raw, RenderTheme::plain(), &[input]),
+            "Use cargo test here.\n"
+        );
+        assert_eq!(
+            render(RenderMode::Raw, RenderTheme::dark(), &[input]),
+            "\u{1b}[0mUse \u{1b}[0m\u{1b}[38;5;11mcargo test\u{1b}[0m here.\n\u{1b}[0m\u{1b}[0m"
+        );
+    }
+
+    #[test]
+    fn fenced_code() {
+        let input = "```rust\nfn main() {}\n```\n";
+
+        assert_eq!(
+            render(RenderMode::Raw, RenderTheme::plain(), &[input]),
+            "```rust\nfn main() {}\n```\n"
+        );
+        assert_eq!(
+            render(RenderMode::Raw, RenderTheme::dark(), &[input]),
+            "\u{1b}[0m\u{1b}[38;5;11m```rust\u{1b}[0m\n\u{1b}[0m\u{1b}[38;5;11mfn main() {}\u{1b}[0m\n\u{1b}[0m\u{1b}[38;5;11m```\u{1b}[0m\n\u{1b}[0m\u{1b}[0m"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let input = "| a | b |\n|---|---|\n| 1 | 22 |\n";
+
+        assert_eq!(
+            render(RenderMode::Raw, RenderTheme::plain(), &[input]),
+            "a │ b \n──┼───\n1 │ 22\n"
+        );
+        assert_eq!(
+            render(RenderMode::Raw, RenderTheme::dark(), &[input]),
+            "\u{1b}[0m\u{1b}[1ma\u{1b}[0m\u{1b}[2m │ \u{1b}[0m\u{1b}[1mb\u{1b}[0m \n\u{1b}[0m\u{1b}[2m──┼───\u{1b}[0m\n1\u{1b}[0m\u{1b}[2m │ \u{1b}[0m22\n\u{1b}[0m\u{1b}[0m"
+        );
+    }
+
+    #[test]
+    fn paragraphs_wrap_at_the_width() {
+        let input = "A long paragraph that needs to wrap because it is much wider than forty columns.\n";
+
+        assert_eq!(
+            render(RenderMode::Raw, RenderTheme::plain(), &[input]),
+            "A long paragraph that needs to wrap\nbecause it is much wider than forty\ncolumns.\n"
+        );
+    }
+
+    #[test]
+    fn envelope_mode_renders_only_the_field() {
+        let input = r##"{"response": "# Hi\n\n- a `b`\n", "finished": true}"##;
+
+        assert_eq!(
+            render(RenderMode::envelope("response"), RenderTheme::plain(), &[input]),
+            "Hi\n\n• a b\n"
+        );
+        // Text outside a JSON object is not the envelope
+        assert_eq!(
+            render(RenderMode::envelope("response"), RenderTheme::plain(), &["plain text"]),
+            ""
+        );
+    }
+
+    #[test]
+    fn raw_mode_renders_json_as_text() {
+        let input = r##"{"response": "# Hi"}"##;
+
+        assert_eq!(
+            render(RenderMode::Raw, RenderTheme::plain(), &[input]),
+            "{\"response\": \"# Hi\"}\n"
+        );
+    }
+
+    // Every way of cutting `text` in two, plus one chunk per character
+    fn splits(text: &str) -> Vec<Vec<&str>> {
+        let mut splits: Vec<Vec<&str>> = text
+            .char_indices()
+            .skip(1)
+            .map(|(i, _)| vec![&text[..i], &text[i..]])
+            .collect();
+        splits.push(
+            text.char_indices()
+                .map(|(i, c)| &text[i..i + c.len_utf8()])
+                .collect(),
+        );
+        splits
+    }
+
+    #[test]
+    fn chunk_boundaries_do_not_change_raw_output() {
+        for theme in [RenderTheme::plain(), RenderTheme::dark()] {
+            let whole = render(RenderMode::Raw, theme.clone(), &[DOCUMENT]);
+            for chunks in splits(DOCUMENT) {
+                assert_eq!(
+                    render(RenderMode::Raw, theme.clone(), &chunks),
+                    whole,
+                    "chunks: {:?}",
+                    chunks
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn chunk_boundaries_do_not_change_envelope_output() {
+        let envelope = serde_json::json!({ "response": DOCUMENT, "finished": true }).to_string();
+        let whole = render(RenderMode::envelope("response"), RenderTheme::plain(), &[&envelope]);
+        assert_eq!(whole, render(RenderMode::Raw, RenderTheme::plain(), &[DOCUMENT]));
+
+        for chunks in splits(&envelope) {
+            assert_eq!(
+                render(RenderMode::envelope("response"), RenderTheme::plain(), &chunks),
+                whole,
+                "chunks: {:?}",
+                chunks
+            );
+        }
+    }
+}
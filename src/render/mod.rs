@@ -0,0 +1,19 @@
+mod events;
+mod highlight;
+mod html;
+mod json;
+mod markdown;
+mod parser;
+mod plain;
+mod terminal;
+mod theme;
+
+pub use crossterm::style::Color;
+pub use events::{MarkdownElement, MarkdownEvent, MarkdownSink, TableAlign};
+pub use html::HtmlSink;
+pub use json::{JsonFieldExtractor, JsonStreamParser, JsonStringUnescaper};
+pub use markdown::{MarkdownStreamRenderer, RenderMode};
+pub use parser::MarkdownParser;
+pub use plain::PlainTextSink;
+pub use terminal::TerminalSink;
+pub use theme::{RenderTheme, TextStyle};
@@ -0,0 +1,707 @@
+use super::events::{MarkdownElement, MarkdownEvent, TableAlign};
+use super::highlight::code_language;
+
+// Block that starts a line, known once its leading markers have arrived
+#[derive(Debug, Clone, PartialEq)]
+enum Block {
+    Paragraph,
+    Blank,
+    Heading(usize),
+    Bullet,
+    Ordered(usize),
+    Rule,
+    TableRow,
+    // Info string of an opening code fence
+    Fence(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct LineStart {
+    indent: usize,
+    quote: usize,
+    block: Block,
+    // Text after the markers
+    rest: String,
+}
+
+// The innermost block, which holds the text of the line
+#[derive(Debug, Clone, Default, PartialEq)]
+enum Leaf {
+    #[default]
+    None,
+    Paragraph,
+    Heading(usize),
+    // Text right after a bullet or number
+    ItemText,
+    Table,
+    Code(String),
+}
+
+// A `[text](url)` link while it streams in
+#[derive(Debug, Clone)]
+enum LinkPart {
+    Text(String),
+    Label(String),
+    Url(String, String),
+}
+
+impl LinkPart {
+    // The source text, for when it turns out not to be a link
+    fn literal(&self) -> String {
+        match self {
+            LinkPart::Text(text) => format!("[{}", text),
+            LinkPart::Label(text) => format!("[{}]", text),
+            LinkPart::Url(text, url) => format!("[{}]({}", text, url),
+        }
+    }
+}
+
+// Turns markdown that arrives in pieces into start/end/text events as early as possible.
+// Line markers, emphasis delimiters and links are held back until their meaning is known,
+// and tables until their last row, so the columns can be laid out.
+#[derive(Debug, Clone, Default)]
+pub struct MarkdownParser {
+    events: Vec<MarkdownEvent>,
+    // Open blockquotes, lists and items, outermost first
+    blocks: Vec<MarkdownElement>,
+    leaf: Leaf,
+    // Open emphasis, code and links, outermost first
+    inline: Vec<MarkdownElement>,
+    // Markers at the start of a line, held until the kind of block is known
+    line_start: Option<String>,
+    // Backticks seen but not resolved yet
+    backticks: usize,
+    // Run of `*`, `_` or `~` whose meaning depends on the next character
+    delimiter: Option<(char, usize)>,
+    prev_char: char,
+    link: Option<LinkPart>,
+    table: Vec<String>,
+    table_line: Option<String>,
+    // Current line of a code block, and how much of it was sent
+    code_line: String,
+    code_sent: usize,
+    // Indent of the opening fence, removed from every code line
+    code_indent: usize,
+}
+
+impl MarkdownParser {
+    pub fn new() -> Self {
+        Self {
+            line_start: Some(String::new()),
+            prev_char: ' ',
+            ..Self::default()
+        }
+    }
+
+    // Feed the next piece of markdown; returns the events it completes
+    pub fn feed(&mut self, text: &str) -> Vec<MarkdownEvent> {
+        for c in text.chars() {
+            self.push(c);
+        }
+        std::mem::take(&mut self.events)
+    }
+
+    // Resolve everything held back and close every open element
+    pub fn finish(&mut self) -> Vec<MarkdownEvent> {
+        if matches!(self.leaf, Leaf::Code(_)) {
+            // A code block cut off mid-line
+            let line = std::mem::take(&mut self.code_line);
+            let text = strip_indent(&line, self.code_indent)[self.code_sent..].to_string();
+            self.text(&text);
+        } else if self.line_start.as_ref().is_some_and(|prefix| !prefix.is_empty()) {
+            self.begin_line(true);
+        }
+        if let Some(row) = self.table_line.take() {
+            self.table.push(row);
+        }
+        self.close_leaf();
+        self.close_blocks(0);
+        self.line_start = Some(String::new());
+        std::mem::take(&mut self.events)
+    }
+
+    fn push(&mut self, c: char) {
+        if matches!(self.leaf, Leaf::Code(_)) {
+            self.push_code(c);
+            return;
+        }
+        if let Some(prefix) = &mut self.line_start {
+            if c != '\n' && (is_marker_char(c) || opens_fence(prefix)) {
+                prefix.push(c);
+                return;
+            }
+            self.begin_line(c == '\n');
+            if c == '\n' {
+                return;
+            }
+        }
+        if let Some(row) = &mut self.table_line {
+            if c == '\n' {
+                let row = std::mem::take(row);
+                self.table.push(row);
+                self.table_line = None;
+                self.line_start = Some(String::new());
+            } else {
+                row.push(c);
+            }
+            return;
+        }
+        self.push_inline(c);
+    }
+
+    // Start the line once its markers are known. `complete` is set when the whole
+    // line has arrived, which also ends it.
+    fn begin_line(&mut self, complete: bool) {
+        let prefix = self.line_start.take().unwrap_or_default();
+        let line = classify_line(&prefix, complete);
+        let depth = self.quote_depth();
+
+        // More text of the same paragraph or list item
+        let continues = matches!(self.leaf, Leaf::Paragraph | Leaf::ItemText);
+        if line.block == Block::Paragraph && continues && line.quote == depth {
+            self.text("\n");
+            self.inline_text(&line.rest, complete);
+            return;
+        }
+        if line.block == Block::TableRow && self.leaf == Leaf::Table && line.quote == depth {
+            self.table_row(line.rest, complete);
+            return;
+        }
+
+        self.close_leaf();
+        if line.block == Block::Blank {
+            if line.quote < depth {
+                self.close_quotes(line.quote);
+            }
+            self.line_start = Some(String::new());
+            return;
+        }
+        self.enter_containers(&line);
+
+        match line.block {
+            Block::Paragraph => {
+                self.start(MarkdownElement::Paragraph);
+                self.leaf = Leaf::Paragraph;
+            }
+            Block::Heading(level) => {
+                self.start(MarkdownElement::Heading(level));
+                self.leaf = Leaf::Heading(level);
+            }
+            Block::Bullet | Block::Ordered(_) => self.leaf = Leaf::ItemText,
+            Block::Rule => {
+                self.start(MarkdownElement::Rule);
+                self.end(MarkdownElement::Rule);
+                self.line_start = Some(String::new());
+                return;
+            }
+            Block::TableRow => {
+                self.leaf = Leaf::Table;
+                self.table_row(line.rest, complete);
+                return;
+            }
+            Block::Fence(info) => {
+                let language = code_language(&info).unwrap_or_default();
+                self.start(MarkdownElement::CodeBlock(language.clone()));
+                self.leaf = Leaf::Code(language);
+                self.code_indent = line.indent;
+                self.code_line.clear();
+                self.code_sent = 0;
+                return;
+            }
+            Block::Blank => {}
+        }
+        self.inline_text(&line.rest, complete);
+    }
+
+    fn inline_text(&mut self, text: &str, complete: bool) {
+        self.prev_char = ' ';
+        for c in text.chars() {
+            self.push_inline(c);
+        }
+        if complete {
+            self.push_inline('\n');
+        }
+    }
+
+    fn table_row(&mut self, row: String, complete: bool) {
+        if complete {
+            self.table.push(row);
+            self.line_start = Some(String::new());
+        } else {
+            self.table_line = Some(row);
+        }
+    }
+
+    // Close and open blockquotes, lists and items so the line sits at the right depth
+    fn enter_containers(&mut self, line: &LineStart) {
+        let depth = self.quote_depth();
+        if line.quote < depth {
+            self.close_quotes(line.quote);
+        } else if line.quote > depth {
+            let keep = self.innermost_quote_end();
+            self.close_blocks(keep);
+            for _ in depth..line.quote {
+                self.open_block(MarkdownElement::Blockquote);
+            }
+        }
+
+        let base = self.innermost_quote_end();
+        let lists: Vec<usize> = (base..self.blocks.len())
+            .filter(|index| matches!(self.blocks[*index], MarkdownElement::List(_)))
+            .collect();
+        let number = match line.block {
+            Block::Ordered(number) => Some(number),
+            Block::Bullet => None,
+            _ => {
+                // Text indented under an item stays inside it
+                let keep = match line.indent {
+                    0 | 1 => 0,
+                    indent => (indent - 2) / 2 + 1,
+                };
+                if let Some(index) = lists.get(keep) {
+                    self.close_blocks(*index);
+                }
+                return;
+            }
+        };
+
+        let level = (line.indent / 2).min(lists.len());
+        let same_kind = lists.get(level).is_some_and(|index| {
+            matches!(
+                (&self.blocks[*index], number),
+                (MarkdownElement::List(None), None) | (MarkdownElement::List(Some(_)), Some(_))
+            )
+        });
+        match lists.get(level) {
+            // Next item of an open list: close its previous item and anything nested in it
+            Some(index) if same_kind => self.close_blocks(index + 1),
+            Some(index) => {
+                self.close_blocks(*index);
+                self.open_block(MarkdownElement::List(number));
+            }
+            None => self.open_block(MarkdownElement::List(number)),
+        }
+        self.open_block(MarkdownElement::Item(number));
+    }
+
+    fn quote_depth(&self) -> usize {
+        self.blocks
+            .iter()
+            .filter(|block| **block == MarkdownElement::Blockquote)
+            .count()
+    }
+
+    // Length of `blocks` up to and including the innermost blockquote
+    fn innermost_quote_end(&self) -> usize {
+        self.blocks
+            .iter()
+            .rposition(|block| *block == MarkdownElement::Blockquote)
+            .map_or(0, |index| index + 1)
+    }
+
+    // Keep the outermost `depth` blockquotes
+    fn close_quotes(&mut self, depth: usize) {
+        let position = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| **block == MarkdownElement::Blockquote)
+            .nth(depth)
+            .map(|(index, _)| index);
+        if let Some(index) = position {
+            self.close_blocks(index);
+        }
+    }
+
+    fn open_block(&mut self, element: MarkdownElement) {
+        self.start(element.clone());
+        self.blocks.push(element);
+    }
+
+    fn close_blocks(&mut self, keep: usize) {
+        while self.blocks.len() > keep {
+            if let Some(block) = self.blocks.pop() {
+                self.end(block);
+            }
+        }
+    }
+
+    fn close_leaf(&mut self) {
+        self.close_inline();
+        match std::mem::take(&mut self.leaf) {
+            Leaf::Paragraph => self.end(MarkdownElement::Paragraph),
+            Leaf::Heading(level) => self.end(MarkdownElement::Heading(level)),
+            Leaf::Table => self.emit_table(),
+            Leaf::Code(language) => self.end(MarkdownElement::CodeBlock(language)),
+            Leaf::ItemText | Leaf::None => {}
+        }
+    }
+
+    // Code lines are sent as they arrive, except what may still become the closing fence
+    fn push_code(&mut self, c: char) {
+        self.code_line.push(c);
+        let line = strip_indent(&self.code_line, self.code_indent);
+
+        if c == '\n' {
+            let trimmed = line.trim();
+            if trimmed.len() >= 3 && trimmed.chars().all(|c| c == '`') {
+                self.code_line.clear();
+                self.code_sent = 0;
+                self.close_leaf();
+                self.line_start = Some(String::new());
+                return;
+            }
+            let text = line[self.code_sent..].to_string();
+            self.text(&text);
+            self.code_line.clear();
+            self.code_sent = 0;
+            return;
+        }
+        if "```".starts_with(line.trim_start()) {
+            return;
+        }
+        let text = line[self.code_sent..].to_string();
+        self.code_sent = line.len();
+        self.text(&text);
+    }
+
+    fn push_inline(&mut self, c: char) {
+        if self.backticks > 0 && c != '`' {
+            self.resolve_backticks();
+        }
+        if self.link.is_some() {
+            self.push_link(c);
+            return;
+        }
+        if c == '`' {
+            self.resolve_delimiter(Some(c));
+            self.backticks += 1;
+            return;
+        }
+        if self.inline.last() == Some(&MarkdownElement::InlineCode) {
+            match c {
+                '\n' => self.end_line(),
+                _ => self.text_char(c),
+            }
+            return;
+        }
+        if matches!(c, '*' | '_' | '~') {
+            match &mut self.delimiter {
+                Some((delimiter, count)) if *delimiter == c => *count += 1,
+                _ => {
+                    self.resolve_delimiter(Some(c));
+                    self.delimiter = Some((c, 1));
+                }
+            }
+            return;
+        }
+        self.resolve_delimiter(Some(c));
+        match c {
+            '\n' => self.end_line(),
+            '[' => self.link = Some(LinkPart::Text(String::new())),
+            _ => self.text_char(c),
+        }
+    }
+
+    fn end_line(&mut self) {
+        if matches!(self.leaf, Leaf::Heading(_)) {
+            self.close_leaf();
+        }
+        self.line_start = Some(String::new());
+    }
+
+    fn resolve_backticks(&mut self) {
+        // A run of any length opens or closes inline code
+        self.backticks = 0;
+        match self.inline.iter().rposition(|e| *e == MarkdownElement::InlineCode) {
+            Some(index) => self.close_inline_at(index),
+            None => self.open_inline(MarkdownElement::InlineCode),
+        }
+    }
+
+    // Decide whether a run of `*`, `_` or `~` opens or closes emphasis, now that the
+    // character after it is known
+    fn resolve_delimiter(&mut self, next: Option<char>) {
+        let Some((delimiter, count)) = self.delimiter.take() else {
+            return;
+        };
+        let element = match (delimiter, count) {
+            ('~', 2) => Some(MarkdownElement::Strikethrough),
+            ('~', _) => None,
+            (_, 1) => Some(MarkdownElement::Italic),
+            (_, 2) => Some(MarkdownElement::Bold),
+            (_, 3) => Some(MarkdownElement::BoldItalic),
+            _ => None,
+        };
+        let prev = self.prev_char;
+        // snake_case is not emphasis
+        let intraword = delimiter == '_'
+            && prev.is_alphanumeric()
+            && next.is_some_and(|c| c.is_alphanumeric());
+        let can_open = next.is_some_and(|c| !c.is_whitespace());
+        let can_close = !prev.is_whitespace();
+
+        match element {
+            Some(element) if !intraword => {
+                match self.inline.iter().rposition(|e| *e == element) {
+                    Some(index) if can_close => self.close_inline_at(index),
+                    _ if can_open => self.open_inline(element),
+                    _ => self.text(&delimiter.to_string().repeat(count)),
+                }
+            }
+            _ => self.text(&delimiter.to_string().repeat(count)),
+        }
+    }
+
+    fn push_link(&mut self, c: char) {
+        let Some(part) = self.link.take() else {
+            return;
+        };
+        match (part, c) {
+            (LinkPart::Text(text), ']') => self.link = Some(LinkPart::Label(text)),
+            (LinkPart::Text(mut text), c) if c != '\n' => {
+                text.push(c);
+                self.link = Some(LinkPart::Text(text));
+            }
+            (LinkPart::Label(text), '(') => self.link = Some(LinkPart::Url(text, String::new())),
+            (LinkPart::Url(text, url), ')') => {
+                self.start(MarkdownElement::Link(url.clone()));
+                self.text(&text);
+                self.end(MarkdownElement::Link(url));
+            }
+            (LinkPart::Url(text, mut url), c) if !c.is_whitespace() => {
+                url.push(c);
+                self.link = Some(LinkPart::Url(text, url));
+            }
+            (part, c) => {
+                // Not a link after all
+                self.text(&part.literal());
+                self.push_inline(c);
+            }
+        }
+    }
+
+    fn open_inline(&mut self, element: MarkdownElement) {
+        self.start(element.clone());
+        self.inline.push(element);
+    }
+
+    // Close an inline element, reopening the ones inside it, e.g. for `**bold *both** italic*`
+    fn close_inline_at(&mut self, index: usize) {
+        let inner = self.inline.split_off(index + 1);
+        for element in inner.iter().rev() {
+            self.end(element.clone());
+        }
+        if let Some(element) = self.inline.pop() {
+            self.end(element);
+        }
+        for element in inner {
+            self.open_inline(element);
+        }
+    }
+
+    fn close_inline(&mut self) {
+        if self.backticks > 0 {
+            self.resolve_backticks();
+        }
+        self.resolve_delimiter(None);
+        if let Some(link) = self.link.take() {
+            self.text(&link.literal());
+        }
+        while let Some(element) = self.inline.pop() {
+            self.end(element);
+        }
+    }
+
+    fn emit_table(&mut self) {
+        let mut rows: Vec<Vec<String>> = std::mem::take(&mut self.table)
+            .iter()
+            .map(|row| table_cells(row))
+            .collect();
+        let aligns: Option<Vec<TableAlign>> = match rows.get(1) {
+            Some(row) if row.iter().all(|cell| is_separator_cell(cell)) => {
+                let aligns = row.iter().map(|cell| cell_align(cell)).collect();
+                rows.remove(1);
+                Some(aligns)
+            }
+            _ => None,
+        };
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+
+        self.start(MarkdownElement::Table);
+        for (index, row) in rows.iter().enumerate() {
+            self.start(MarkdownElement::TableRow);
+            for column in 0..columns {
+                let cell = MarkdownElement::TableCell {
+                    header: index == 0 && aligns.is_some(),
+                    align: aligns
+                        .as_ref()
+                        .and_then(|aligns| aligns.get(column))
+                        .copied()
+                        .unwrap_or_default(),
+                };
+                self.start(cell.clone());
+                self.prev_char = ' ';
+                for c in row.get(column).map(String::as_str).unwrap_or_default().chars() {
+                    self.push_inline(c);
+                }
+                self.close_inline();
+                self.end(cell);
+            }
+            self.end(MarkdownElement::TableRow);
+        }
+        self.end(MarkdownElement::Table);
+    }
+
+    fn start(&mut self, element: MarkdownElement) {
+        self.events.push(MarkdownEvent::Start(element));
+    }
+
+    fn end(&mut self, element: MarkdownElement) {
+        self.events.push(MarkdownEvent::End(element));
+    }
+
+    fn text_char(&mut self, c: char) {
+        let mut buffer = [0; 4];
+        self.text(c.encode_utf8(&mut buffer));
+    }
+
+    // Adjacent text is merged into one event
+    fn text(&mut self, text: &str) {
+        let Some(last) = text.chars().last() else {
+            return;
+        };
+        self.prev_char = last;
+        match self.events.last_mut() {
+            Some(MarkdownEvent::Text(previous)) => previous.push_str(text),
+            _ => self.events.push(MarkdownEvent::Text(text.to_string())),
+        }
+    }
+}
+
+// Characters that may still be part of a block marker at the start of a line
+fn is_marker_char(c: char) -> bool {
+    c == ' ' || c == '\t' || c.is_ascii_digit() || "#>-*+_.)|`".contains(c)
+}
+
+// A fence line is held until its info string is complete
+fn opens_fence(prefix: &str) -> bool {
+    prefix
+        .trim_start_matches(|c: char| c.is_whitespace() || c == '>')
+        .starts_with("```")
+}
+
+fn strip_indent(line: &str, indent: usize) -> &str {
+    let spaces = line.chars().take(indent).take_while(|c| *c == ' ').count();
+    &line[spaces..]
+}
+
+// Split the markers off the start of a line, e.g. "  > - item" is a bullet one level
+// deep inside a quote. `complete` is set once the whole line has arrived.
+fn classify_line(prefix: &str, complete: bool) -> LineStart {
+    let indent_of = |text: &str| {
+        text.chars()
+            .take_while(|c| c.is_whitespace())
+            .map(|c| if c == '\t' { 4 } else { 1 })
+            .sum::<usize>()
+    };
+    let mut indent = indent_of(prefix);
+    let mut rest = prefix.trim_start();
+    let mut quote = 0;
+    while let Some(after) = rest.strip_prefix('>') {
+        quote += 1;
+        rest = after.strip_prefix(' ').unwrap_or(after);
+    }
+    if quote > 0 {
+        indent = indent_of(rest);
+        rest = rest.trim_start();
+    }
+    let line = |block, rest: &str| LineStart {
+        indent,
+        quote,
+        block,
+        rest: rest.to_string(),
+    };
+
+    if complete && rest.trim().is_empty() {
+        return line(Block::Blank, "");
+    }
+    if let Some(info) = rest.strip_prefix("```") {
+        let info = info.trim_start_matches('`');
+        if !info.contains('`') {
+            return line(Block::Fence(info.trim().to_string()), "");
+        }
+    }
+    if complete && is_rule(rest) {
+        return line(Block::Rule, "");
+    }
+    if rest.starts_with('|') {
+        return line(Block::TableRow, rest);
+    }
+    let hashes = rest.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes) {
+        let after = &rest[hashes..];
+        if after.starts_with(' ') || (complete && after.is_empty()) {
+            return line(Block::Heading(hashes), after.trim_start());
+        }
+    }
+    if let Some(after) = rest.strip_prefix(['-', '*', '+']) {
+        if let Some(after) = after.strip_prefix(' ') {
+            return line(Block::Bullet, after);
+        }
+    }
+    let digits = rest.chars().take_while(char::is_ascii_digit).count();
+    if (1..=9).contains(&digits) {
+        let after = &rest[digits..];
+        if let Some(after) = after.strip_prefix(". ").or_else(|| after.strip_prefix(") ")) {
+            let number = rest[..digits].parse().unwrap_or(1);
+            return line(Block::Ordered(number), after);
+        }
+    }
+    line(Block::Paragraph, rest)
+}
+
+// "---", "***", "___" or spaced out, e.g. "* * *"
+fn is_rule(line: &str) -> bool {
+    let marks: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3 && matches!(marks[0], '-' | '*' | '_') && marks.iter().all(|c| *c == marks[0])
+}
+
+fn table_cells(row: &str) -> Vec<String> {
+    let row = row.trim();
+    let row = row.strip_prefix('|').unwrap_or(row);
+    let row = match row.strip_suffix('|') {
+        Some(inner) if !inner.ends_with('\\') => inner,
+        _ => row,
+    };
+    let mut cells = vec![String::new()];
+    let mut escaped = false;
+    for c in row.chars() {
+        match c {
+            '|' if !escaped => cells.push(String::new()),
+            _ => {
+                if let Some(cell) = cells.last_mut() {
+                    cell.push(c);
+                }
+            }
+        }
+        escaped = c == '\\';
+    }
+    cells
+        .iter()
+        .map(|cell| cell.trim().replace("\\|", "|"))
+        .collect()
+}
+
+fn is_separator_cell(cell: &str) -> bool {
+    let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+    !dashes.is_empty() && dashes.chars().all(|c| c == '-')
+}
+
+fn cell_align(cell: &str) -> TableAlign {
+    match (cell.starts_with(':'), cell.ends_with(':')) {
+        (true, true) => TableAlign::Center,
+        (false, true) => TableAlign::Right,
+        _ => TableAlign::Left,
+    }
+}
@@ -0,0 +1,55 @@
+use anyhow::Result;
+use std::io::Write;
+
+use super::events::{MarkdownEvent, MarkdownSink};
+use super::terminal::TerminalSink;
+use super::theme::RenderTheme;
+
+// Writes markdown events as plain text for logs: no escape codes, markers and code fences
+// removed, links printed as "text (url)", lists and tables laid out as in the terminal
+pub struct PlainTextSink<W: Write> {
+    inner: TerminalSink<W>,
+}
+
+impl<W: Write> PlainTextSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            inner: TerminalSink::new(writer)
+                .theme(RenderTheme::plain())
+                .hyperlinks(false)
+                .code_fences(false),
+        }
+    }
+
+    // Word wrap at `width` columns; None (the default) never wraps
+    pub fn width(mut self, width: Option<usize>) -> Self {
+        self.inner = self.inner.width(width);
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.get_mut()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner.into_inner()
+    }
+}
+
+impl<W: Write> MarkdownSink for PlainTextSink<W> {
+    fn event(&mut self, event: &MarkdownEvent) -> Result<()> {
+        self.inner.event(event)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
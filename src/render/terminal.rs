@@ -0,0 +1,663 @@
+use anyhow::Result;
+use crossterm::{
+    queue,
+    style::{Attribute, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor},
+};
+use std::io::Write;
+
+use super::events::{MarkdownElement, MarkdownEvent, MarkdownSink, TableAlign};
+use super::highlight::CodeHighlighter;
+use super::theme::{RenderTheme, TextStyle};
+
+const BULLETS: [char; 3] = ['•', '◦', '▪'];
+
+struct Cell {
+    header: bool,
+    align: TableAlign,
+    text: Vec<(TextStyle, String)>,
+}
+
+// Prints markdown events with terminal styles: markers hidden, lists indented, words
+// wrapped at `width` and tables aligned
+pub struct TerminalSink<W: Write> {
+    writer: W,
+    theme: RenderTheme,
+    width: Option<usize>,
+    hyperlinks: bool,
+    highlight: bool,
+    code_fences: bool,
+    // Open elements, outermost first
+    open: Vec<MarkdownElement>,
+    // Width of each open item's bullet or number, the indent of its other lines
+    item_indents: Vec<usize>,
+    // Bullet or number of an item whose first line has not started yet
+    marker: Option<String>,
+    // Whether the quote bars and indent of the current line are printed
+    line_started: bool,
+    column: usize,
+    // A blank line is due before the next block
+    separate: bool,
+    printed: bool,
+    highlighter: Option<CodeHighlighter>,
+    code_line: String,
+    // Text of a link, printed once it is complete
+    link: Option<String>,
+    table: Option<Vec<Vec<Cell>>>,
+    // Word and the whitespace before it, held until it is known whether it fits on the line
+    word: Vec<(TextStyle, String)>,
+    word_width: usize,
+    gap: String,
+    gap_style: TextStyle,
+    last_style: Option<TextStyle>,
+}
+
+impl<W: Write> TerminalSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            theme: RenderTheme::from_env(),
+            width: None,
+            hyperlinks: true,
+            highlight: true,
+            code_fences: true,
+            open: Vec::new(),
+            item_indents: Vec::new(),
+            marker: None,
+            line_started: false,
+            column: 0,
+            separate: false,
+            printed: false,
+            highlighter: None,
+            code_line: String::new(),
+            link: None,
+            table: None,
+            word: Vec::new(),
+            word_width: 0,
+            gap: String::new(),
+            gap_style: TextStyle::new(),
+            last_style: None,
+        }
+    }
+
+    // Defaults to RenderTheme::from_env(), which honors NO_COLOR
+    pub fn theme(mut self, theme: RenderTheme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    // Word wrap at `width` columns; None never wraps
+    pub fn width(mut self, width: Option<usize>) -> Self {
+        self.width = width.filter(|width| *width > 0);
+        self
+    }
+
+    // Print links as OSC 8 hyperlinks, or as "text (url)" when disabled
+    pub fn hyperlinks(mut self, enabled: bool) -> Self {
+        self.hyperlinks = enabled;
+        self
+    }
+
+    // Color fenced code by language; needs the `syntax-highlighting` feature
+    pub fn syntax_highlighting(mut self, enabled: bool) -> Self {
+        self.highlight = enabled;
+        self
+    }
+
+    // Print the ``` lines around code blocks
+    pub(crate) fn code_fences(mut self, enabled: bool) -> Self {
+        self.code_fences = enabled;
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn start(&mut self, element: &MarkdownElement) -> Result<()> {
+        if let Some(rows) = &mut self.table {
+            match element {
+                MarkdownElement::TableRow => rows.push(Vec::new()),
+                MarkdownElement::TableCell { header, align } => {
+                    if let Some(row) = rows.last_mut() {
+                        row.push(Cell {
+                            header: *header,
+                            align: *align,
+                            text: Vec::new(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+            self.open.push(element.clone());
+            return Ok(());
+        }
+
+        if element.is_block() {
+            self.end_line()?;
+            let item = matches!(element, MarkdownElement::Item(_));
+            let nested_list =
+                matches!(element, MarkdownElement::List(_)) && !self.item_indents.is_empty();
+            if self.separate && !item && !nested_list {
+                self.blank_line()?;
+            }
+            self.separate = false;
+        }
+        match element {
+            MarkdownElement::Item(number) => {
+                let lists = self
+                    .open
+                    .iter()
+                    .filter(|e| matches!(e, MarkdownElement::List(_)))
+                    .count();
+                let level = lists.saturating_sub(1);
+                let marker = match number {
+                    Some(number) => format!("{}{}. ", "  ".repeat(level), number),
+                    None => format!("{}{} ", "  ".repeat(level), BULLETS[level % BULLETS.len()]),
+                };
+                self.item_indents.push(text_width(&marker));
+                self.marker = Some(marker);
+            }
+            MarkdownElement::CodeBlock(language) => {
+                self.highlighter = if self.highlight && !self.theme.plain && !language.is_empty() {
+                    CodeHighlighter::for_language(language, &self.theme.syntax_theme)
+                } else {
+                    None
+                };
+                if self.code_fences {
+                    self.start_line()?;
+                    self.print_styled(self.theme.code_block, &format!("```{}", language))?;
+                    self.newline()?;
+                }
+            }
+            MarkdownElement::Table => self.table = Some(Vec::new()),
+            MarkdownElement::Rule => {
+                self.start_line()?;
+                let length = self.width.unwrap_or(40).saturating_sub(self.column).max(3);
+                self.print_styled(self.theme.rule, &"─".repeat(length))?;
+                self.column += length;
+            }
+            MarkdownElement::Link(_) => self.link = Some(String::new()),
+            _ => {}
+        }
+        self.open.push(element.clone());
+        Ok(())
+    }
+
+    fn end(&mut self, element: &MarkdownElement) -> Result<()> {
+        if let Some(index) = self.open.iter().rposition(|open| open == element) {
+            self.open.remove(index);
+        }
+        if self.table.is_some() {
+            if *element == MarkdownElement::Table {
+                self.render_table()?;
+                self.separate = true;
+            }
+            return Ok(());
+        }
+
+        match element {
+            MarkdownElement::Link(url) => {
+                let text = self.link.take().unwrap_or_default();
+                self.emit_link(&text, url)?;
+            }
+            MarkdownElement::CodeBlock(_) => {
+                // A last line without its newline
+                let line = std::mem::take(&mut self.code_line);
+                self.print_code(&line)?;
+                self.end_line()?;
+                if self.code_fences {
+                    self.start_line()?;
+                    self.print_styled(self.theme.code_block, "```")?;
+                    self.newline()?;
+                }
+                self.highlighter = None;
+            }
+            MarkdownElement::Item(_) => {
+                self.item_indents.pop();
+            }
+            _ => {}
+        }
+        if element.is_block() {
+            self.end_line()?;
+            self.separate = match element {
+                MarkdownElement::Item(_) => false,
+                MarkdownElement::List(_) => self.item_indents.is_empty(),
+                _ => true,
+            };
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> Result<()> {
+        if self.table.is_some() {
+            let style = self.current_style();
+            if let Some(cell) = self.table.as_mut().and_then(|rows| rows.last_mut()?.last_mut()) {
+                cell.text.push((style, text.to_string()));
+            }
+            return Ok(());
+        }
+        if let Some(link) = &mut self.link {
+            link.push_str(text);
+            return Ok(());
+        }
+        if self.open.iter().any(|e| matches!(e, MarkdownElement::CodeBlock(_))) {
+            return self.code_text(text);
+        }
+        for c in text.chars() {
+            match c {
+                '\n' => self.end_line()?,
+                _ => self.emit_char(c)?,
+            }
+        }
+        Ok(())
+    }
+
+    // Highlighted code is printed a line at a time
+    fn code_text(&mut self, text: &str) -> Result<()> {
+        for piece in text.split_inclusive('\n') {
+            if self.highlighter.is_none() {
+                self.print_code(piece)?;
+                continue;
+            }
+            self.code_line.push_str(piece);
+            if piece.ends_with('\n') {
+                let line = std::mem::take(&mut self.code_line);
+                self.print_code(&line)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn print_code(&mut self, text: &str) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        let (content, newline) = match text.strip_suffix('\n') {
+            Some(content) => (content, true),
+            None => (text, false),
+        };
+        self.start_line()?;
+        match &mut self.highlighter {
+            Some(highlighter) if !self.theme.plain => {
+                for (color, piece) in highlighter.highlight_line(text) {
+                    let piece = piece.trim_end_matches('\n');
+                    queue!(self.writer, SetForegroundColor(color), Print(piece))?;
+                }
+                self.last_style = None;
+            }
+            _ => self.print_styled(self.theme.code_block, content)?,
+        }
+        self.column += text_width(content);
+        if newline {
+            self.newline()?;
+        }
+        Ok(())
+    }
+
+    fn current_style(&self) -> TextStyle {
+        let theme = &self.theme;
+        let mut style = TextStyle::new();
+        for element in &self.open {
+            let element = match element {
+                MarkdownElement::Heading(level) => theme.heading(*level),
+                MarkdownElement::Bold => theme.bold,
+                MarkdownElement::Italic => theme.italic,
+                MarkdownElement::BoldItalic => theme.bold_italic,
+                MarkdownElement::Strikethrough => theme.strikethrough,
+                MarkdownElement::InlineCode => theme.inline_code,
+                MarkdownElement::Link(_) => theme.link,
+                MarkdownElement::Blockquote => theme.blockquote,
+                _ => continue,
+            };
+            style = style.patch(element);
+        }
+        style
+    }
+
+    // Only emits escape codes when the style changes
+    fn print_styled(&mut self, style: TextStyle, text: &str) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        if self.last_style != Some(style) && !self.theme.plain {
+            queue!(self.writer, SetAttribute(Attribute::Reset))?;
+            if let Some(color) = style.foreground {
+                queue!(self.writer, SetForegroundColor(color))?;
+            }
+            if let Some(color) = style.background {
+                queue!(self.writer, SetBackgroundColor(color))?;
+            }
+            let attributes = [
+                (style.bold, Attribute::Bold),
+                (style.italic, Attribute::Italic),
+                (style.underline, Attribute::Underlined),
+                (style.strikethrough, Attribute::CrossedOut),
+                (style.dim, Attribute::Dim),
+            ];
+            for (_, attribute) in attributes.into_iter().filter(|(on, _)| *on) {
+                queue!(self.writer, SetAttribute(attribute))?;
+            }
+            self.last_style = Some(style);
+        }
+        queue!(self.writer, Print(text))?;
+        Ok(())
+    }
+
+    fn quote_depth(&self) -> usize {
+        self.open
+            .iter()
+            .filter(|e| **e == MarkdownElement::Blockquote)
+            .count()
+    }
+
+    // Quote bars, then the item's bullet on its first line or its indent on the others
+    fn start_line(&mut self) -> Result<()> {
+        if self.line_started {
+            return Ok(());
+        }
+        self.line_started = true;
+        self.printed = true;
+        self.column = 0;
+        let bars = self.quote_depth();
+        self.print_styled(self.theme.blockquote, &"│ ".repeat(bars))?;
+        self.column += 2 * bars;
+        match self.marker.take() {
+            Some(marker) => {
+                self.print_styled(self.theme.list_marker, &marker)?;
+                self.column += text_width(&marker);
+            }
+            None => {
+                let indent = self.item_indents.last().copied().unwrap_or(0);
+                self.print_styled(TextStyle::new(), &" ".repeat(indent))?;
+                self.column += indent;
+            }
+        }
+        Ok(())
+    }
+
+    fn newline(&mut self) -> Result<()> {
+        self.print_styled(TextStyle::new(), "\n")?;
+        self.column = 0;
+        self.line_started = false;
+        Ok(())
+    }
+
+    // End the current line, if anything is on it
+    fn end_line(&mut self) -> Result<()> {
+        self.flush_word()?;
+        self.gap.clear();
+        if self.marker.is_some() {
+            self.start_line()?;
+        }
+        if self.line_started {
+            self.newline()?;
+        }
+        Ok(())
+    }
+
+    fn blank_line(&mut self) -> Result<()> {
+        if !self.printed {
+            return Ok(());
+        }
+        let bars = "│ ".repeat(self.quote_depth());
+        self.print_styled(self.theme.blockquote, bars.trim_end())?;
+        self.print_styled(TextStyle::new(), "\n")?;
+        Ok(())
+    }
+
+    // Inline text in the current style, a word at a time so it can be wrapped
+    fn emit_char(&mut self, c: char) -> Result<()> {
+        let style = self.current_style();
+        if c == ' ' || c == '\t' {
+            self.flush_word()?;
+            if self.gap.is_empty() {
+                self.gap_style = style;
+            }
+            self.gap.push(c);
+            return Ok(());
+        }
+        match self.word.last_mut() {
+            Some((last, text)) if *last == style => text.push(c),
+            _ => self.word.push((style, c.to_string())),
+        }
+        self.word_width += char_width(c);
+        if self.width.is_none() {
+            self.flush_word()?;
+        }
+        Ok(())
+    }
+
+    fn emit_link(&mut self, text: &str, url: &str) -> Result<()> {
+        // The URL comes from the model: ESC or BEL in it would end the OSC 8 sequence early
+        let url: String = url.chars().filter(|c| !c.is_control()).collect();
+        let url = url.as_str();
+        self.flush_word()?;
+        self.open.push(MarkdownElement::Link(url.to_string()));
+        let style = self.current_style();
+        self.open.pop();
+        let hyperlinks = self.hyperlinks && !self.theme.plain;
+        let suffix = if hyperlinks {
+            String::new()
+        } else {
+            format!(" ({})", url)
+        };
+        let width = text_width(text) + text_width(&suffix);
+        self.place(width)?;
+
+        if hyperlinks {
+            queue!(self.writer, Print(format!("\x1b]8;;{}\x1b\\", url)))?;
+            self.print_styled(style, text)?;
+            queue!(self.writer, Print("\x1b]8;;\x1b\\"))?;
+        } else {
+            self.print_styled(style, text)?;
+            let style = self.current_style().patch(self.theme.link_url);
+            self.print_styled(style, &suffix)?;
+        }
+        self.column += width;
+        Ok(())
+    }
+
+    fn flush_word(&mut self) -> Result<()> {
+        if self.word.is_empty() {
+            return Ok(());
+        }
+        let width = std::mem::take(&mut self.word_width);
+        self.place(width)?;
+        for (style, text) in std::mem::take(&mut self.word) {
+            self.print_styled(style, &text)?;
+        }
+        self.column += width;
+        Ok(())
+    }
+
+    // Make room for `width` columns of text: wrap the line if it does not fit,
+    // otherwise print the whitespace before it
+    fn place(&mut self, width: usize) -> Result<()> {
+        if !self.line_started {
+            self.start_line()?;
+            self.gap.clear();
+        }
+        let gap = std::mem::take(&mut self.gap);
+        let indent = 2 * self.quote_depth() + self.item_indents.last().copied().unwrap_or(0);
+        let wrap = self.width.is_some_and(|max| {
+            self.column > indent && self.column + text_width(&gap) + width > max
+        });
+        if wrap {
+            self.newline()?;
+            self.start_line()?;
+        } else {
+            let style = self.gap_style;
+            self.print_styled(style, &gap)?;
+            self.column += text_width(&gap);
+        }
+        Ok(())
+    }
+
+    // Print the buffered table with its columns padded to the widest cell
+    fn render_table(&mut self) -> Result<()> {
+        let rows = self.table.take().unwrap_or_default();
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let mut widths = vec![0; columns];
+        for row in &rows {
+            for (index, cell) in row.iter().enumerate() {
+                widths[index] = widths[index].max(cell_width(cell));
+            }
+        }
+
+        let border = self.theme.table_border;
+        for row in &rows {
+            self.start_line()?;
+            for (index, cell) in row.iter().enumerate() {
+                if index > 0 {
+                    self.print_styled(border, " │ ")?;
+                }
+                let pad = widths[index] - cell_width(cell);
+                let (left, right) = match cell.align {
+                    TableAlign::Left => (0, pad),
+                    TableAlign::Center => (pad / 2, pad - pad / 2),
+                    TableAlign::Right => (pad, 0),
+                };
+                self.print_styled(TextStyle::new(), &" ".repeat(left))?;
+                for (style, text) in &cell.text {
+                    let style = if cell.header {
+                        self.theme.table_header.patch(*style)
+                    } else {
+                        *style
+                    };
+                    self.print_styled(style, text)?;
+                }
+                self.print_styled(TextStyle::new(), &" ".repeat(right))?;
+            }
+            self.newline()?;
+            if row.iter().any(|cell| cell.header) {
+                self.start_line()?;
+                let rule: Vec<String> = widths.iter().map(|width| "─".repeat(*width)).collect();
+                self.print_styled(border, &rule.join("─┼─"))?;
+                self.newline()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> MarkdownSink for TerminalSink<W> {
+    fn event(&mut self, event: &MarkdownEvent) -> Result<()> {
+        match event {
+            MarkdownEvent::Start(element) => self.start(element),
+            MarkdownEvent::End(element) => self.end(element),
+            MarkdownEvent::Text(text) => self.text(text),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    // Print whatever is still held back and reset the colors
+    fn finish(&mut self) -> Result<()> {
+        self.flush_word()?;
+        if !self.theme.plain {
+            queue!(self.writer, SetAttribute(Attribute::Reset), ResetColor)?;
+        }
+        self.last_style = None;
+        self.flush()
+    }
+}
+
+fn cell_width(cell: &Cell) -> usize {
+    cell.text.iter().map(|(_, text)| text_width(text)).sum()
+}
+
+// Columns taken by a character in a terminal: CJK and most emoji are two wide
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ if c.is_control() => 0,
+        _ => 1,
+    }
+}
+
+fn text_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hyperlinks need a theme that allows escape codes; this one has no styles
+    fn render_link(url: &str, hyperlinks: bool) -> String {
+        let theme = RenderTheme {
+            plain: false,
+            ..RenderTheme::plain()
+        };
+        let mut sink = TerminalSink::new(Vec::new())
+            .theme(theme)
+            .hyperlinks(hyperlinks);
+        let link = MarkdownElement::Link(url.to_string());
+        let events = [
+            MarkdownEvent::Start(MarkdownElement::Paragraph),
+            MarkdownEvent::Start(link.clone()),
+            MarkdownEvent::Text("docs".to_string()),
+            MarkdownEvent::End(link),
+            MarkdownEvent::End(MarkdownElement::Paragraph),
+        ];
+        sink.render(&events).unwrap();
+        String::from_utf8(sink.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn links_are_printed_as_hyperlinks() {
+        let output = render_link("https://example.com", true);
+
+        assert!(
+            output.starts_with("\x1b]8;;https://example.com\x1b\\"),
+            "{:?}",
+            output
+        );
+        assert!(output.contains("docs\x1b]8;;\x1b\\"), "{:?}", output);
+        assert!(render_link("https://example.com", false).contains("docs (https://example.com)"));
+    }
+
+    #[test]
+    fn control_characters_are_stripped_from_urls() {
+        let url = "https://example.com\x1b\\\x1b]0;pwned\x07\x1b[2J";
+
+        let hyperlink = render_link(url, true);
+        let plain = render_link(url, false);
+
+        assert!(
+            hyperlink.starts_with("\x1b]8;;https://example.com\\]0;pwned[2J\x1b\\"),
+            "{:?}",
+            hyperlink
+        );
+        assert!(
+            plain.contains("(https://example.com\\]0;pwned[2J)"),
+            "{:?}",
+            plain
+        );
+        for output in [hyperlink, plain] {
+            assert!(!output.contains('\x07'));
+            assert!(!output.contains("\x1b]0;"));
+            assert!(!output.contains("\x1b[2J"));
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use crossterm::style::Color;
+use std::io::{stdout, IsTerminal};
+
+// Colors and attributes of one kind of markdown element
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TextStyle {
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub dim: bool,
+}
+
+impl TextStyle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn fg(mut self, color: Color) -> Self {
+        self.foreground = Some(color);
+        self
+    }
+
+    pub fn bg(mut self, color: Color) -> Self {
+        self.background = Some(color);
+        self
+    }
+
+    pub fn bold(mut self) -> Self {
+        self.bold = true;
+        self
+    }
+
+    pub fn italic(mut self) -> Self {
+        self.italic = true;
+        self
+    }
+
+    pub fn underline(mut self) -> Self {
+        self.underline = true;
+        self
+    }
+
+    pub fn strikethrough(mut self) -> Self {
+        self.strikethrough = true;
+        self
+    }
+
+    pub fn dim(mut self) -> Self {
+        self.dim = true;
+        self
+    }
+
+    // `other` drawn over this style, e.g. bold text inside a heading
+    pub fn patch(self, other: TextStyle) -> Self {
+        Self {
+            foreground: other.foreground.or(self.foreground),
+            background: other.background.or(self.background),
+            bold: self.bold || other.bold,
+            italic: self.italic || other.italic,
+            underline: self.underline || other.underline,
+            strikethrough: self.strikethrough || other.strikethrough,
+            dim: self.dim || other.dim,
+        }
+    }
+}
+
+// How MarkdownStreamRenderer styles each element
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderTheme {
+    // Levels 1-6
+    pub headings: [TextStyle; 6],
+    pub bold: TextStyle,
+    pub italic: TextStyle,
+    pub bold_italic: TextStyle,
+    pub strikethrough: TextStyle,
+    pub inline_code: TextStyle,
+    // Fences, and code that is not highlighted
+    pub code_block: TextStyle,
+    pub link: TextStyle,
+    // The "(url)" after a link when hyperlinks are off
+    pub link_url: TextStyle,
+    // Bullets and numbers
+    pub list_marker: TextStyle,
+    pub blockquote: TextStyle,
+    pub rule: TextStyle,
+    pub table_header: TextStyle,
+    pub table_border: TextStyle,
+    // syntect theme for highlighted code blocks
+    pub syntax_theme: String,
+    // Emit no escape codes at all: no styles, highlighting or hyperlinks
+    pub plain: bool,
+}
+
+impl Default for RenderTheme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl RenderTheme {
+    // For dark terminal backgrounds
+    pub fn dark() -> Self {
+        let heading = TextStyle::new().bold();
+        Self {
+            headings: [
+                heading.fg(Color::Magenta),
+                heading.fg(Color::DarkMagenta),
+                heading.fg(Color::Cyan),
+                heading.fg(Color::White),
+                heading.fg(Color::White),
+                heading.fg(Color::White),
+            ],
+            bold: TextStyle::new().fg(Color::Yellow).bold(),
+            italic: TextStyle::new().fg(Color::Blue).italic(),
+            bold_italic: TextStyle::new().fg(Color::Magenta).bold().italic(),
+            strikethrough: TextStyle::new().strikethrough(),
+            inline_code: TextStyle::new().fg(Color::Yellow),
+            code_block: TextStyle::new().fg(Color::Yellow),
+            link: TextStyle::new().fg(Color::Blue).underline(),
+            link_url: TextStyle::new().fg(Color::DarkBlue),
+            list_marker: TextStyle::new().fg(Color::Green),
+            blockquote: TextStyle::new().fg(Color::Cyan),
+            rule: TextStyle::new().dim(),
+            table_header: TextStyle::new().bold(),
+            table_border: TextStyle::new().dim(),
+            syntax_theme: "base16-ocean.dark".to_string(),
+            plain: false,
+        }
+    }
+
+    // For light terminal backgrounds, where yellow and cyan are hard to read
+    pub fn light() -> Self {
+        let heading = TextStyle::new().bold();
+        Self {
+            headings: [
+                heading.fg(Color::DarkMagenta),
+                heading.fg(Color::DarkBlue),
+                heading.fg(Color::DarkCyan),
+                heading.fg(Color::Black),
+                heading.fg(Color::Black),
+                heading.fg(Color::Black),
+            ],
+            bold: TextStyle::new().fg(Color::DarkRed).bold(),
+            italic: TextStyle::new().fg(Color::DarkBlue).italic(),
+            bold_italic: TextStyle::new().fg(Color::DarkMagenta).bold().italic(),
+            strikethrough: TextStyle::new().strikethrough(),
+            inline_code: TextStyle::new().fg(Color::DarkRed),
+            code_block: TextStyle::new().fg(Color::DarkRed),
+            link: TextStyle::new().fg(Color::DarkBlue).underline(),
+            link_url: TextStyle::new().fg(Color::DarkGrey),
+            list_marker: TextStyle::new().fg(Color::DarkGreen),
+            blockquote: TextStyle::new().fg(Color::DarkCyan),
+            rule: TextStyle::new().dim(),
+            table_header: TextStyle::new().bold(),
+            table_border: TextStyle::new().dim(),
+            syntax_theme: "InspiredGitHub".to_string(),
+            plain: false,
+        }
+    }
+
+    // Attributes only, for NO_COLOR
+    pub fn no_color() -> Self {
+        let strip = |style: TextStyle| TextStyle {
+            foreground: None,
+            background: None,
+            ..style
+        };
+        let dark = Self::dark();
+        Self {
+            headings: dark.headings.map(strip),
+            bold: strip(dark.bold),
+            italic: strip(dark.italic),
+            bold_italic: strip(dark.bold_italic),
+            strikethrough: strip(dark.strikethrough),
+            inline_code: strip(dark.inline_code),
+            code_block: strip(dark.code_block),
+            link: strip(dark.link),
+            link_url: strip(dark.link_url),
+            list_marker: strip(dark.list_marker),
+            blockquote: strip(dark.blockquote),
+            rule: strip(dark.rule),
+            table_header: strip(dark.table_header),
+            table_border: strip(dark.table_border),
+            syntax_theme: dark.syntax_theme,
+            plain: false,
+        }
+    }
+
+    // Markers are still hidden, but nothing but text is written
+    pub fn plain() -> Self {
+        let none = TextStyle::new();
+        Self {
+            headings: [none; 6],
+            bold: none,
+            italic: none,
+            bold_italic: none,
+            strikethrough: none,
+            inline_code: none,
+            code_block: none,
+            link: none,
+            link_url: none,
+            list_marker: none,
+            blockquote: none,
+            rule: none,
+            table_header: none,
+            table_border: none,
+            syntax_theme: String::new(),
+            plain: true,
+        }
+    }
+
+    // no_color() when NO_COLOR is set, light() when COLORFGBG reports a light
+    // background, dark() otherwise
+    pub fn from_env() -> Self {
+        if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            return Self::no_color();
+        }
+        let background = std::env::var("COLORFGBG")
+            .ok()
+            .and_then(|value| value.rsplit(';').next()?.parse::<u8>().ok());
+        match background {
+            Some(7) | Some(15) => Self::light(),
+            _ => Self::dark(),
+        }
+    }
+
+    // Like from_env(), but plain() when stdout is not a terminal
+    pub fn detect() -> Self {
+        if stdout().is_terminal() {
+            Self::from_env()
+        } else {
+            Self::plain()
+        }
+    }
+
+    pub fn heading(&self, level: usize) -> TextStyle {
+        self.headings[level.clamp(1, 6) - 1]
+    }
+}
@@ -0,0 +1,182 @@
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::model::{Conversation, Turn};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+// What list() and search() return for each stored conversation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConversationInfo {
+    pub id: String,
+    pub system_prompt: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+    // Milliseconds since the Unix epoch
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub turn_count: usize,
+}
+
+// Persistent conversation history, e.g. one conversation per chat session of a user
+#[async_trait]
+pub trait ConversationStore: Send + Sync + fmt::Debug {
+    // Store the conversation's system prompt, context window and metadata; returns its new id
+    async fn create(&self, conversation: &Conversation) -> Result<String, String>;
+
+    // Replace the system prompt, context window and metadata of a stored conversation
+    async fn update(&self, id: &str, conversation: &Conversation) -> Result<(), String>;
+
+    async fn append(&self, id: &str, turns: &[Turn]) -> Result<(), String>;
+
+    // Remove every stored turn, keeping the conversation itself
+    async fn clear_turns(&self, id: &str) -> Result<(), String>;
+
+    // Most recently updated first
+    async fn list(&self) -> Result<Vec<ConversationInfo>, String>;
+
+    async fn load(&self, id: &str) -> Result<Conversation, String>;
+
+    // Returns false if there was no such conversation
+    async fn delete(&self, id: &str) -> Result<bool, String>;
+
+    // Conversations whose metadata has `key` set to `value`, most recently updated first
+    async fn search(&self, key: &str, value: &str) -> Result<Vec<ConversationInfo>, String>;
+}
+
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+pub(crate) fn new_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+#[derive(Debug, Clone)]
+struct StoredConversation {
+    info: ConversationInfo,
+    context_window: Option<u32>,
+    turns: Vec<Turn>,
+}
+
+// Store kept in process memory, shared by its clones; useful for tests and caches
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryStore {
+    conversations: Arc<Mutex<HashMap<String, StoredConversation>>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn sorted(mut infos: Vec<ConversationInfo>) -> Vec<ConversationInfo> {
+        infos.sort_by(|a, b| b.updated_at.cmp(&a.updated_at).then_with(|| a.id.cmp(&b.id)));
+        infos
+    }
+}
+
+#[async_trait]
+impl ConversationStore for InMemoryStore {
+    async fn create(&self, conversation: &Conversation) -> Result<String, String> {
+        let id = new_id();
+        let now = now_millis();
+        let stored = StoredConversation {
+            info: ConversationInfo {
+                id: id.clone(),
+                system_prompt: conversation.get_system_prompt().map(str::to_string),
+                metadata: conversation.get_metadata().clone(),
+                created_at: now,
+                updated_at: now,
+                turn_count: 0,
+            },
+            context_window: conversation.get_context_window(),
+            turns: Vec::new(),
+        };
+        self.conversations.lock().insert(id.clone(), stored);
+        Ok(id)
+    }
+
+    async fn update(&self, id: &str, conversation: &Conversation) -> Result<(), String> {
+        let mut conversations = self.conversations.lock();
+        let stored = conversations
+            .get_mut(id)
+            .ok_or_else(|| format!("Conversation {} not found", id))?;
+        stored.info.system_prompt = conversation.get_system_prompt().map(str::to_string);
+        stored.info.metadata = conversation.get_metadata().clone();
+        stored.info.updated_at = now_millis();
+        stored.context_window = conversation.get_context_window();
+        Ok(())
+    }
+
+    async fn append(&self, id: &str, turns: &[Turn]) -> Result<(), String> {
+        let mut conversations = self.conversations.lock();
+        let stored = conversations
+            .get_mut(id)
+            .ok_or_else(|| format!("Conversation {} not found", id))?;
+        stored.turns.extend(turns.iter().cloned());
+        stored.info.turn_count = stored.turns.len();
+        stored.info.updated_at = now_millis();
+        Ok(())
+    }
+
+    async fn clear_turns(&self, id: &str) -> Result<(), String> {
+        let mut conversations = self.conversations.lock();
+        let stored = conversations
+            .get_mut(id)
+            .ok_or_else(|| format!("Conversation {} not found", id))?;
+        stored.turns.clear();
+        stored.info.turn_count = 0;
+        stored.info.updated_at = now_millis();
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<ConversationInfo>, String> {
+        let infos = self
+            .conversations
+            .lock()
+            .values()
+            .map(|stored| stored.info.clone())
+            .collect();
+        Ok(Self::sorted(infos))
+    }
+
+    async fn load(&self, id: &str) -> Result<Conversation, String> {
+        let conversations = self.conversations.lock();
+        let stored = conversations
+            .get(id)
+            .ok_or_else(|| format!("Conversation {} not found", id))?;
+        Ok(Conversation::from_store(
+            id.to_string(),
+            stored.info.system_prompt.clone(),
+            stored.context_window,
+            stored.info.metadata.clone(),
+            stored.turns.clone(),
+        ))
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, String> {
+        Ok(self.conversations.lock().remove(id).is_some())
+    }
+
+    async fn search(&self, key: &str, value: &str) -> Result<Vec<ConversationInfo>, String> {
+        let infos = self
+            .conversations
+            .lock()
+            .values()
+            .filter(|stored| stored.info.metadata.get(key).map(String::as_str) == Some(value))
+            .map(|stored| stored.info.clone())
+            .collect();
+        Ok(Self::sorted(infos))
+    }
+}
@@ -0,0 +1,437 @@
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{new_id, now_millis, ConversationInfo, ConversationStore};
+use crate::model::{Conversation, Turn};
+
+const SCHEMA: &str = "
+PRAGMA foreign_keys = ON;
+
+CREATE TABLE IF NOT EXISTS conversations (
+    id TEXT PRIMARY KEY,
+    system_prompt TEXT,
+    context_window INTEGER,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS turns (
+    conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    turn TEXT NOT NULL,
+    PRIMARY KEY (conversation_id, position)
+);
+
+CREATE TABLE IF NOT EXISTS metadata (
+    conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (conversation_id, key)
+);
+
+CREATE INDEX IF NOT EXISTS metadata_key_value ON metadata (key, value);
+";
+
+fn sql_error(e: rusqlite::Error) -> String {
+    format!("SQLite error: {}", e)
+}
+
+// Store backed by an SQLite database file; clones share the connection
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        Self::from_connection(Connection::open(path).map_err(sql_error)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, String> {
+        Self::from_connection(Connection::open_in_memory().map_err(sql_error)?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<Self, String> {
+        connection.execute_batch(SCHEMA).map_err(sql_error)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    // Run blocking SQLite work off the async runtime
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, rusqlite::Error> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || f(&mut connection.lock()).map_err(sql_error))
+            .await
+            .map_err(|e| format!("SQLite task failed: {}", e))?
+    }
+}
+
+fn write_metadata(
+    transaction: &rusqlite::Transaction,
+    id: &str,
+    metadata: &BTreeMap<String, String>,
+) -> Result<(), rusqlite::Error> {
+    transaction.execute("DELETE FROM metadata WHERE conversation_id = ?1", params![id])?;
+    for (key, value) in metadata {
+        transaction.execute(
+            "INSERT INTO metadata (conversation_id, key, value) VALUES (?1, ?2, ?3)",
+            params![id, key, value],
+        )?;
+    }
+    Ok(())
+}
+
+fn read_metadata(connection: &Connection, id: &str) -> Result<BTreeMap<String, String>, rusqlite::Error> {
+    let mut statement = connection.prepare("SELECT key, value FROM metadata WHERE conversation_id = ?1")?;
+    let rows = statement.query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+// Conversations matching the WHERE clause, with their metadata and turn counts
+fn read_infos(
+    connection: &Connection,
+    filter: &str,
+    args: &[&dyn rusqlite::ToSql],
+) -> Result<Vec<ConversationInfo>, rusqlite::Error> {
+    let sql = format!(
+        "SELECT c.id, c.system_prompt, c.created_at, c.updated_at,
+                (SELECT COUNT(*) FROM turns t WHERE t.conversation_id = c.id)
+         FROM conversations c {}
+         ORDER BY c.updated_at DESC, c.id",
+        filter
+    );
+    let mut statement = connection.prepare(&sql)?;
+    let rows = statement.query_map(args, |row| {
+        Ok(ConversationInfo {
+            id: row.get(0)?,
+            system_prompt: row.get(1)?,
+            metadata: BTreeMap::new(),
+            created_at: row.get::<_, i64>(2)? as u64,
+            updated_at: row.get::<_, i64>(3)? as u64,
+            turn_count: row.get::<_, i64>(4)? as usize,
+        })
+    })?;
+
+    let mut infos = rows.collect::<Result<Vec<_>, _>>()?;
+    for info in &mut infos {
+        info.metadata = read_metadata(connection, &info.id)?;
+    }
+    Ok(infos)
+}
+
+#[async_trait]
+impl ConversationStore for SqliteStore {
+    async fn create(&self, conversation: &Conversation) -> Result<String, String> {
+        let id = new_id();
+        let system_prompt = conversation.get_system_prompt().map(str::to_string);
+        let context_window = conversation.get_context_window();
+        let metadata = conversation.get_metadata().clone();
+
+        let created = id.clone();
+        self.with_connection(move |connection| {
+            let now = now_millis() as i64;
+            let transaction = connection.transaction()?;
+            transaction.execute(
+                "INSERT INTO conversations (id, system_prompt, context_window, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?4)",
+                params![created, system_prompt, context_window, now],
+            )?;
+            write_metadata(&transaction, &created, &metadata)?;
+            transaction.commit()
+        })
+        .await?;
+        Ok(id)
+    }
+
+    async fn update(&self, id: &str, conversation: &Conversation) -> Result<(), String> {
+        let not_found = format!("Conversation {} not found", id);
+        let id = id.to_string();
+        let system_prompt = conversation.get_system_prompt().map(str::to_string);
+        let context_window = conversation.get_context_window();
+        let metadata = conversation.get_metadata().clone();
+
+        let updated = self
+            .with_connection(move |connection| {
+                let transaction = connection.transaction()?;
+                let updated = transaction.execute(
+                    "UPDATE conversations SET system_prompt = ?2, context_window = ?3, updated_at = ?4
+                     WHERE id = ?1",
+                    params![id, system_prompt, context_window, now_millis() as i64],
+                )?;
+                if updated > 0 {
+                    write_metadata(&transaction, &id, &metadata)?;
+                }
+                transaction.commit()?;
+                Ok(updated)
+            })
+            .await?;
+
+        if updated == 0 {
+            return Err(not_found);
+        }
+        Ok(())
+    }
+
+    async fn append(&self, id: &str, turns: &[Turn]) -> Result<(), String> {
+        let not_found = format!("Conversation {} not found", id);
+        let id = id.to_string();
+        let turns = turns
+            .iter()
+            .map(|turn| serde_json::to_string(turn).map_err(|e| format!("Failed to serialize turn: {}", e)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let updated = self
+            .with_connection(move |connection| {
+                let transaction = connection.transaction()?;
+                let updated = transaction.execute(
+                    "UPDATE conversations SET updated_at = ?2 WHERE id = ?1",
+                    params![id, now_millis() as i64],
+                )?;
+                if updated > 0 {
+                    let next: i64 = transaction.query_row(
+                        "SELECT COALESCE(MAX(position) + 1, 0) FROM turns WHERE conversation_id = ?1",
+                        params![id],
+                        |row| row.get(0),
+                    )?;
+                    for (offset, turn) in turns.iter().enumerate() {
+                        transaction.execute(
+                            "INSERT INTO turns (conversation_id, position, turn) VALUES (?1, ?2, ?3)",
+                            params![id, next + offset as i64, turn],
+                        )?;
+                    }
+                }
+                transaction.commit()?;
+                Ok(updated)
+            })
+            .await?;
+
+        if updated == 0 {
+            return Err(not_found);
+        }
+        Ok(())
+    }
+
+    async fn clear_turns(&self, id: &str) -> Result<(), String> {
+        let not_found = format!("Conversation {} not found", id);
+        let id = id.to_string();
+
+        let updated = self
+            .with_connection(move |connection| {
+                let transaction = connection.transaction()?;
+                let updated = transaction.execute(
+                    "UPDATE conversations SET updated_at = ?2 WHERE id = ?1",
+                    params![id, now_millis() as i64],
+                )?;
+                transaction.execute("DELETE FROM turns WHERE conversation_id = ?1", params![id])?;
+                transaction.commit()?;
+                Ok(updated)
+            })
+            .await?;
+
+        if updated == 0 {
+            return Err(not_found);
+        }
+        Ok(())
+    }
+
+    async fn list(&self) -> Result<Vec<ConversationInfo>, String> {
+        self.with_connection(|connection| read_infos(connection, "", &[])).await
+    }
+
+    async fn load(&self, id: &str) -> Result<Conversation, String> {
+        let id = id.to_string();
+        let loaded = id.clone();
+        let (header, metadata, turns) = self
+            .with_connection(move |connection| {
+                let header: Option<(Option<String>, Option<u32>)> = connection
+                    .query_row(
+                        "SELECT system_prompt, context_window FROM conversations WHERE id = ?1",
+                        params![loaded],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )
+                    .optional()?;
+                let metadata = read_metadata(connection, &loaded)?;
+                let mut statement = connection
+                    .prepare("SELECT turn FROM turns WHERE conversation_id = ?1 ORDER BY position")?;
+                let turns = statement
+                    .query_map(params![loaded], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((header, metadata, turns))
+            })
+            .await?;
+
+        let (system_prompt, context_window) = header.ok_or_else(|| format!("Conversation {} not found", id))?;
+        let turns = turns
+            .iter()
+            .map(|turn| serde_json::from_str::<Turn>(turn).map_err(|e| format!("Invalid stored turn: {}", e)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Conversation::from_store(id, system_prompt, context_window, metadata, turns))
+    }
+
+    async fn delete(&self, id: &str) -> Result<bool, String> {
+        let id = id.to_string();
+        self.with_connection(move |connection| {
+            Ok(connection.execute("DELETE FROM conversations WHERE id = ?1", params![id])? > 0)
+        })
+        .await
+    }
+
+    async fn search(&self, key: &str, value: &str) -> Result<Vec<ConversationInfo>, String> {
+        let key = key.to_string();
+        let value = value.to_string();
+        self.with_connection(move |connection| {
+            read_infos(
+                connection,
+                "WHERE EXISTS (SELECT 1 FROM metadata m
+                               WHERE m.conversation_id = c.id AND m.key = ?1 AND m.value = ?2)",
+                &[&key, &value],
+            )
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ChatMessage;
+
+    fn turn(content: &str) -> Turn {
+        Turn::new(ChatMessage {
+            role: "user".to_string(),
+            content: content.to_string(),
+        })
+    }
+
+    fn contents(conversation: &Conversation) -> Vec<&str> {
+        conversation
+            .turns()
+            .iter()
+            .map(|turn| turn.message.content.as_str())
+            .collect()
+    }
+
+    fn count(store: &SqliteStore, table: &str) -> i64 {
+        let sql = format!("SELECT COUNT(*) FROM {}", table);
+        store
+            .connection
+            .lock()
+            .query_row(&sql, [], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn with_user(user: &str) -> Conversation {
+        Conversation::new().metadata("user".to_string(), user.to_string())
+    }
+
+    #[tokio::test]
+    async fn appends_continue_after_the_last_position() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let id = store.create(&Conversation::new()).await.unwrap();
+        let other = store.create(&Conversation::new()).await.unwrap();
+
+        store
+            .append(&id, &[turn("one"), turn("two")])
+            .await
+            .unwrap();
+        store.append(&other, &[turn("elsewhere")]).await.unwrap();
+        store.append(&id, &[turn("three")]).await.unwrap();
+
+        let positions: Vec<i64> = {
+            let connection = store.connection.lock();
+            let mut statement = connection
+                .prepare("SELECT position FROM turns WHERE conversation_id = ?1 ORDER BY position")
+                .unwrap();
+            let rows = statement.query_map(params![id], |row| row.get(0)).unwrap();
+            rows.collect::<Result<_, _>>().unwrap()
+        };
+        assert_eq!(positions, [0, 1, 2]);
+        assert_eq!(
+            contents(&store.load(&id).await.unwrap()),
+            ["one", "two", "three"]
+        );
+        assert_eq!(contents(&store.load(&other).await.unwrap()), ["elsewhere"]);
+        assert!(store.append("missing", &[turn("lost")]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn search_matches_metadata_values() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let first = store.create(&with_user("ada")).await.unwrap();
+        store.create(&with_user("grace")).await.unwrap();
+        let second = store
+            .create(&with_user("ada").metadata("topic".to_string(), "rust".to_string()))
+            .await
+            .unwrap();
+        // Appending makes the first conversation the most recently updated one
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        store.append(&first, &[turn("hi")]).await.unwrap();
+
+        let found = store.search("user", "ada").await.unwrap();
+
+        let ids: Vec<&str> = found.iter().map(|info| info.id.as_str()).collect();
+        assert_eq!(ids, [first.as_str(), second.as_str()]);
+        assert_eq!(found[0].turn_count, 1);
+        assert_eq!(
+            found[1].metadata.get("topic").map(String::as_str),
+            Some("rust")
+        );
+        assert!(store.search("user", "linus").await.unwrap().is_empty());
+        assert!(store.search("ada", "user").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_removes_turns_and_metadata() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let id = store.create(&with_user("ada")).await.unwrap();
+        let kept = store.create(&with_user("grace")).await.unwrap();
+        store
+            .append(&id, &[turn("one"), turn("two")])
+            .await
+            .unwrap();
+        store.append(&kept, &[turn("three")]).await.unwrap();
+
+        assert!(store.delete(&id).await.unwrap());
+
+        assert!(!store.delete(&id).await.unwrap());
+        assert!(store.load(&id).await.is_err());
+        assert_eq!(count(&store, "turns"), 1);
+        assert_eq!(count(&store, "metadata"), 1);
+        assert_eq!(contents(&store.load(&kept).await.unwrap()), ["three"]);
+    }
+
+    #[tokio::test]
+    async fn clear_turns_keeps_the_conversation() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let id = store
+            .create(&with_user("ada").system_prompt("Be brief".to_string()))
+            .await
+            .unwrap();
+        store
+            .append(&id, &[turn("one"), turn("two")])
+            .await
+            .unwrap();
+
+        store.clear_turns(&id).await.unwrap();
+        store.append(&id, &[turn("three")]).await.unwrap();
+
+        let loaded = store.load(&id).await.unwrap();
+        assert_eq!(contents(&loaded), ["three"]);
+        assert_eq!(loaded.get_system_prompt(), Some("Be brief"));
+        assert_eq!(
+            loaded.get_metadata().get("user").map(String::as_str),
+            Some("ada")
+        );
+        assert!(store.clear_turns("missing").await.is_err());
+    }
+}
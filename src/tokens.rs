@@ -0,0 +1,399 @@
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+use crate::model::{ChatMessage, Model};
+
+// Models that share a tokenizer and chat template
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModelFamily {
+    Llama2,
+    Llama3,
+    Mistral,
+    Qwen,
+    DeepSeek,
+    Gemma,
+    Gpt,
+    Claude,
+    Gemini,
+    Other,
+}
+
+impl ModelFamily {
+    pub const ALL: [ModelFamily; 10] = [
+        ModelFamily::Llama2,
+        ModelFamily::Llama3,
+        ModelFamily::Mistral,
+        ModelFamily::Qwen,
+        ModelFamily::DeepSeek,
+        ModelFamily::Gemma,
+        ModelFamily::Gpt,
+        ModelFamily::Claude,
+        ModelFamily::Gemini,
+        ModelFamily::Other,
+    ];
+
+    pub fn from_model_id(model_id: &str) -> Self {
+        let id = model_id.to_lowercase();
+        // Distilled models use the tokenizer of their base model
+        if id.contains("distill-qwen") {
+            return ModelFamily::Qwen;
+        }
+        if id.contains("distill-llama") {
+            return ModelFamily::Llama3;
+        }
+
+        if id.contains("llama-2") || id.contains("llama2") || id.contains("mythomax") {
+            ModelFamily::Llama2
+        } else if id.contains("llama") || id.contains("hermes-3") {
+            ModelFamily::Llama3
+        } else if id.contains("deepseek") {
+            ModelFamily::DeepSeek
+        } else if id.contains("qwen") || id.contains("qwq") {
+            ModelFamily::Qwen
+        } else if id.contains("mistral") || id.contains("mixtral") || id.contains("wizardlm") {
+            ModelFamily::Mistral
+        } else if id.contains("gemma") {
+            ModelFamily::Gemma
+        } else if id.contains("gpt") || id.starts_with("openai/") {
+            ModelFamily::Gpt
+        } else if id.contains("claude") {
+            ModelFamily::Claude
+        } else if id.contains("gemini") {
+            ModelFamily::Gemini
+        } else {
+            ModelFamily::Other
+        }
+    }
+
+    // Name used for tokenizer files, e.g. `llama3.json`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModelFamily::Llama2 => "llama2",
+            ModelFamily::Llama3 => "llama3",
+            ModelFamily::Mistral => "mistral",
+            ModelFamily::Qwen => "qwen",
+            ModelFamily::DeepSeek => "deepseek",
+            ModelFamily::Gemma => "gemma",
+            ModelFamily::Gpt => "gpt",
+            ModelFamily::Claude => "claude",
+            ModelFamily::Gemini => "gemini",
+            ModelFamily::Other => "other",
+        }
+    }
+
+    // Tokens the chat template adds around every message (role header, separators)
+    pub fn message_overhead(&self) -> usize {
+        match self {
+            ModelFamily::Gpt => 3,
+            ModelFamily::Mistral | ModelFamily::DeepSeek => 4,
+            _ => 5,
+        }
+    }
+
+    // Tokens the template adds to prime the assistant reply
+    pub fn reply_overhead(&self) -> usize {
+        match self {
+            ModelFamily::Llama3 => 4,
+            ModelFamily::Mistral => 1,
+            _ => 3,
+        }
+    }
+
+    // Average characters per token of English text, used by the heuristic estimator
+    fn chars_per_token(&self) -> f32 {
+        match self {
+            ModelFamily::Llama2 | ModelFamily::Mistral | ModelFamily::Claude => 3.5,
+            ModelFamily::Gemma | ModelFamily::Gemini => 4.2,
+            ModelFamily::Other => 3.5,
+            _ => 4.0,
+        }
+    }
+}
+
+// Counts the tokens of a piece of text
+pub trait TokenCounter: Send + Sync + fmt::Debug {
+    fn count(&self, text: &str) -> usize;
+}
+
+// Fast estimate without a vocabulary: ASCII text by average token length,
+// other scripts (CJK, emoji, ...) at one token per character
+#[derive(Debug, Clone, Copy)]
+pub struct HeuristicCounter {
+    chars_per_token: f32,
+}
+
+impl HeuristicCounter {
+    pub fn new(chars_per_token: f32) -> Self {
+        Self {
+            chars_per_token: chars_per_token.max(1.0),
+        }
+    }
+
+    pub fn for_family(family: ModelFamily) -> Self {
+        Self::new(family.chars_per_token())
+    }
+}
+
+impl Default for HeuristicCounter {
+    fn default() -> Self {
+        Self::for_family(ModelFamily::Other)
+    }
+}
+
+impl TokenCounter for HeuristicCounter {
+    fn count(&self, text: &str) -> usize {
+        let mut ascii = 0usize;
+        let mut other = 0usize;
+        for c in text.chars() {
+            if c.is_ascii() {
+                ascii += 1;
+            } else {
+                other += 1;
+            }
+        }
+        (ascii as f32 / self.chars_per_token).ceil() as usize + other
+    }
+}
+
+// Exact counts from a Hugging Face `tokenizer.json`
+#[cfg(feature = "tokenizers")]
+pub struct HfTokenizer {
+    tokenizer: tokenizers::Tokenizer,
+}
+
+#[cfg(feature = "tokenizers")]
+impl fmt::Debug for HfTokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HfTokenizer")
+            .field("vocab_size", &self.tokenizer.get_vocab_size(true))
+            .finish()
+    }
+}
+
+#[cfg(feature = "tokenizers")]
+impl HfTokenizer {
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let tokenizer = tokenizers::Tokenizer::from_file(path)
+            .map_err(|e| format!("Failed to load tokenizer {}: {}", path.display(), e))?;
+        Ok(Self { tokenizer })
+    }
+}
+
+#[cfg(feature = "tokenizers")]
+impl TokenCounter for HfTokenizer {
+    fn count(&self, text: &str) -> usize {
+        match self.tokenizer.encode(text, false) {
+            Ok(encoding) => encoding.len(),
+            // Fall back to the estimate rather than failing a request over a count
+            Err(_) => HeuristicCounter::default().count(text),
+        }
+    }
+}
+
+// Token counters per model family, falling back to the heuristic estimator
+#[derive(Debug, Default, Clone)]
+pub struct TokenizerRegistry {
+    counters: HashMap<ModelFamily, Arc<dyn TokenCounter>>,
+}
+
+impl TokenizerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, family: ModelFamily, counter: Arc<dyn TokenCounter>) {
+        self.counters.insert(family, counter);
+    }
+
+    pub fn has_exact(&self, family: ModelFamily) -> bool {
+        self.counters.contains_key(&family)
+    }
+
+    // Load `<family>.json` or `<family>/tokenizer.json` for every family found in `dir`.
+    // Returns the families that were loaded.
+    #[cfg(feature = "tokenizers")]
+    pub fn load_dir<P: AsRef<std::path::Path>>(&mut self, dir: P) -> Result<Vec<ModelFamily>, String> {
+        let dir = dir.as_ref();
+        let mut loaded = Vec::new();
+        for family in ModelFamily::ALL {
+            let candidates = [
+                dir.join(format!("{}.json", family.name())),
+                dir.join(family.name()).join("tokenizer.json"),
+            ];
+            if let Some(path) = candidates.iter().find(|path| path.is_file()) {
+                self.register(family, Arc::new(HfTokenizer::from_file(path)?));
+                loaded.push(family);
+            }
+        }
+        Ok(loaded)
+    }
+
+    pub fn count_text(&self, text: &str, family: ModelFamily) -> usize {
+        match self.counters.get(&family) {
+            Some(counter) => counter.count(text),
+            None => HeuristicCounter::for_family(family).count(text),
+        }
+    }
+
+    // Prompt size including the chat template overhead of each message and the reply
+    pub fn count_messages(&self, messages: &[ChatMessage], family: ModelFamily) -> usize {
+        let content: usize = messages
+            .iter()
+            .map(|message| {
+                self.count_text(&message.content, family)
+                    + self.count_text(&message.role, family)
+                    + family.message_overhead()
+            })
+            .sum();
+        content + family.reply_overhead()
+    }
+}
+
+fn global() -> &'static RwLock<TokenizerRegistry> {
+    static REGISTRY: OnceLock<RwLock<TokenizerRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(TokenizerRegistry::new()))
+}
+
+// Use `counter` for every model of the family, process-wide
+pub fn register_tokenizer(family: ModelFamily, counter: Arc<dyn TokenCounter>) {
+    global().write().register(family, counter);
+}
+
+// Load tokenizer files from `dir` into the process-wide registry
+#[cfg(feature = "tokenizers")]
+pub fn load_tokenizers<P: AsRef<std::path::Path>>(dir: P) -> Result<Vec<ModelFamily>, String> {
+    global().write().load_dir(dir)
+}
+
+pub fn count_tokens<M: Model + ?Sized>(text: &str, model: &M) -> usize {
+    count_text_for(text, ModelFamily::from_model_id(model.model_id()))
+}
+
+pub fn count_text_for(text: &str, family: ModelFamily) -> usize {
+    global().read().count_text(text, family)
+}
+
+// Estimated prompt tokens for sending `messages` to `model`
+pub fn count_messages<M: Model + ?Sized>(messages: &[ChatMessage], model: &M) -> usize {
+    count_messages_for(messages, ModelFamily::from_model_id(model.model_id()))
+}
+
+pub fn count_messages_for(messages: &[ChatMessage], family: ModelFamily) -> usize {
+    global().read().count_messages(messages, family)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts words, to tell an override from the heuristic
+    #[derive(Debug)]
+    struct WordCounter;
+
+    impl TokenCounter for WordCounter {
+        fn count(&self, text: &str) -> usize {
+            text.split_whitespace().count()
+        }
+    }
+
+    struct Gemini;
+
+    impl Model for Gemini {
+        fn model_id(&self) -> &'static str {
+            "google/gemini-2.0-flash-001"
+        }
+    }
+
+    fn message(role: &str, content: &str) -> ChatMessage {
+        ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn families_come_from_model_ids() {
+        let family = ModelFamily::from_model_id;
+        assert_eq!(family("llama-3.3-70b-versatile"), ModelFamily::Llama3);
+        assert_eq!(family("gryphe/mythomax-l2-13b"), ModelFamily::Llama2);
+        assert_eq!(family("deepseek-r1-distill-qwen-32b"), ModelFamily::Qwen);
+        assert_eq!(family("deepseek-r1-distill-llama-70b"), ModelFamily::Llama3);
+        assert_eq!(family("DeepSeek-V3-0324"), ModelFamily::DeepSeek);
+        assert_eq!(family("mixtral-8x7b-32768"), ModelFamily::Mistral);
+        assert_eq!(family("openai/o3-mini"), ModelFamily::Gpt);
+        assert_eq!(family("whisper-large-v3"), ModelFamily::Other);
+    }
+
+    #[test]
+    fn heuristic_counts_ascii_by_length_and_other_scripts_per_character() {
+        let counter = HeuristicCounter::new(4.0);
+
+        assert_eq!(counter.count(""), 0);
+        assert_eq!(counter.count("abcd"), 1);
+        assert_eq!(counter.count("abcde"), 2);
+        assert_eq!(counter.count("日本語"), 3);
+        assert_eq!(counter.count("hi 😀"), 2);
+        // At least one character per token
+        assert_eq!(HeuristicCounter::new(0.1).count("abc"), 3);
+    }
+
+    #[test]
+    fn registry_falls_back_to_the_heuristic() {
+        let mut registry = TokenizerRegistry::new();
+        let text = "one two three four five six";
+
+        assert!(!registry.has_exact(ModelFamily::Llama3));
+        assert_eq!(
+            registry.count_text(text, ModelFamily::Llama3),
+            HeuristicCounter::for_family(ModelFamily::Llama3).count(text)
+        );
+
+        registry.register(ModelFamily::Llama3, Arc::new(WordCounter));
+        assert!(registry.has_exact(ModelFamily::Llama3));
+        assert_eq!(registry.count_text(text, ModelFamily::Llama3), 6);
+        assert_ne!(registry.count_text(text, ModelFamily::Mistral), 6);
+    }
+
+    #[test]
+    fn message_counts_include_the_template_overhead() {
+        let mut registry = TokenizerRegistry::new();
+        registry.register(ModelFamily::Gpt, Arc::new(WordCounter));
+        let messages = [
+            message("system", "Be brief"),
+            message("user", "Hello there friend"),
+        ];
+
+        // Words of content and role, 3 per message and 3 for the reply
+        assert_eq!(
+            registry.count_messages(&messages, ModelFamily::Gpt),
+            2 + 1 + 3 + 3 + 1 + 3 + 3
+        );
+        assert_eq!(registry.count_messages(&[], ModelFamily::Gpt), 3);
+    }
+
+    #[test]
+    fn the_global_registry_serves_registered_counters() {
+        let text = "counted by words in the global registry";
+        assert_eq!(
+            count_text_for(text, ModelFamily::Other),
+            HeuristicCounter::default().count(text)
+        );
+
+        // Only this test uses the Gemini family, so the override cannot leak into others
+        register_tokenizer(ModelFamily::Gemini, Arc::new(WordCounter));
+
+        assert_eq!(count_tokens(text, &Gemini), 7);
+        assert_eq!(count_text_for(text, ModelFamily::Gemini), 7);
+        assert_eq!(
+            count_messages(&[message("user", text)], &Gemini),
+            7 + 1 + 5 + 3
+        );
+        assert_eq!(
+            count_text_for(text, ModelFamily::Other),
+            HeuristicCounter::default().count(text)
+        );
+    }
+}
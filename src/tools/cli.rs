@@ -0,0 +1,736 @@
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+
+use super::Tool;
+
+// Environment variables passed through to commands unless the allowlist is replaced
+const DEFAULT_ENV_ALLOWLIST: &[&str] =
+    &["PATH", "HOME", "USER", "LANG", "LC_ALL", "TERM", "TMPDIR"];
+
+// Programs refused unless the deny list is replaced
+const DEFAULT_DENY: &[&str] = &[
+    "sudo", "su", "doas", "shutdown", "reboot", "halt", "poweroff", "mkfs", "dd",
+];
+
+// How long to keep reading output left behind by background processes once the command exits
+const OUTPUT_GRACE: Duration = Duration::from_millis(500);
+
+type Confirm = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+// Runs the shell commands a model asks for through the "cli" tool. Opt-in and guarded: a
+// cleared environment with an allowlist, a working directory, a timeout, capped output,
+// program allow/deny lists and an optional confirmation callback. The lists check the
+// programs named in the command line; they are a guard rail, not an OS-level sandbox.
+#[derive(Clone)]
+pub struct CliTool {
+    name: String,
+    working_dir: Option<PathBuf>,
+    env_allowlist: Vec<String>,
+    env: BTreeMap<String, String>,
+    timeout: Duration,
+    max_output: usize,
+    allow: Vec<String>,
+    deny: Vec<String>,
+    confirm: Option<Confirm>,
+}
+
+impl fmt::Debug for CliTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CliTool")
+            .field("name", &self.name)
+            .field("working_dir", &self.working_dir)
+            .field("env_allowlist", &self.env_allowlist)
+            .field("timeout", &self.timeout)
+            .field("max_output", &self.max_output)
+            .field("allow", &self.allow)
+            .field("deny", &self.deny)
+            .field("confirm", &self.confirm.is_some())
+            .finish()
+    }
+}
+
+impl Default for CliTool {
+    fn default() -> Self {
+        Self {
+            name: "cli".to_string(),
+            working_dir: None,
+            env_allowlist: DEFAULT_ENV_ALLOWLIST
+                .iter()
+                .map(|var| var.to_string())
+                .collect(),
+            env: BTreeMap::new(),
+            timeout: Duration::from_secs(30),
+            max_output: 16 * 1024,
+            allow: Vec::new(),
+            deny: DEFAULT_DENY
+                .iter()
+                .map(|program| program.to_string())
+                .collect(),
+            confirm: None,
+        }
+    }
+}
+
+// What a command printed and how it ended
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    // None when the command was killed or ended by a signal
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    // Some output was dropped to stay within max_output
+    pub truncated: bool,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    // The output as the tool result sent back to the model
+    pub fn format_for_model(&self) -> String {
+        let mut text = match (self.timed_out, self.exit_code) {
+            (true, _) => "The command timed out and was killed.\n".to_string(),
+            (false, Some(code)) => format!("Exit code: {}\n", code),
+            (false, None) => "The command was terminated by a signal.\n".to_string(),
+        };
+        for (label, output) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if output.is_empty() {
+                continue;
+            }
+            text.push_str(&format!("{}:\n{}", label, output));
+            if !output.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+        if self.stdout.is_empty() && self.stderr.is_empty() {
+            text.push_str("(no output)\n");
+        }
+        if self.truncated {
+            text.push_str("(output truncated)\n");
+        }
+        text
+    }
+}
+
+impl CliTool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The tool name the model calls, "cli" by default
+    pub fn tool_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    // Directory commands run in; defaults to the current directory
+    pub fn working_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.working_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    // Variables passed through from this process; everything else is cleared
+    pub fn env_allowlist(mut self, vars: &[&str]) -> Self {
+        self.env_allowlist = vars.iter().map(|var| var.to_string()).collect();
+        self
+    }
+
+    // Set a variable for every command
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.env.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    // Kill commands running longer than this (30 seconds by default)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // Bytes kept of stdout and of stderr each (16 KiB by default)
+    pub fn max_output(mut self, bytes: usize) -> Self {
+        self.max_output = bytes;
+        self
+    }
+
+    // Only run command lines whose programs are all listed, e.g. &["ls", "cat", "git"]
+    pub fn allow(mut self, programs: &[&str]) -> Self {
+        self.allow = programs.iter().map(|program| program.to_string()).collect();
+        self
+    }
+
+    // Refuse command lines naming any of these programs; replaces the default list
+    // (sudo, su, shutdown, mkfs, dd, ...)
+    pub fn deny(mut self, programs: &[&str]) -> Self {
+        self.deny = programs.iter().map(|program| program.to_string()).collect();
+        self
+    }
+
+    // Ask before running each command; returning false declines it. Called on the
+    // runtime thread, so keep it quick or move blocking prompts elsewhere.
+    pub fn confirm<F>(mut self, confirm: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.confirm = Some(Arc::new(confirm));
+        self
+    }
+
+    // Check a command line against the allow and deny lists
+    pub fn check(&self, command: &str) -> Result<(), String> {
+        if command.trim().is_empty() {
+            return Err("The command is empty".to_string());
+        }
+        let programs = programs(command);
+        if let Some(program) = programs.iter().find(|program| self.deny.contains(program)) {
+            return Err(format!(
+                "The command was refused: \"{}\" is not allowed",
+                program
+            ));
+        }
+        if !self.allow.is_empty() {
+            // Substitutions can run programs the lists never see
+            if command.contains("$(") || command.contains('`') || command.contains("<(") {
+                return Err(
+                    "The command was refused: command substitution is not allowed".to_string(),
+                );
+            }
+            if let Some(program) = programs
+                .iter()
+                .find(|program| !self.allow.contains(program))
+            {
+                return Err(format!(
+                    "The command was refused: \"{}\" is not allowed; allowed programs are {}",
+                    program,
+                    self.allow.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Check, confirm and run a command line with the system shell
+    pub async fn run(&self, command: &str) -> Result<CommandOutput, String> {
+        self.check(command)?;
+        if let Some(confirm) = &self.confirm {
+            if !confirm(command) {
+                return Err("The user declined to run the command".to_string());
+            }
+        }
+
+        let mut cmd = shell(command);
+        cmd.env_clear()
+            .envs(
+                self.env_allowlist
+                    .iter()
+                    .filter_map(|var| std::env::var(var).ok().map(|value| (var, value))),
+            )
+            .envs(&self.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(dir) = &self.working_dir {
+            cmd.current_dir(dir);
+        }
+        // Its own process group, so a timeout kills what the shell started too
+        #[cfg(unix)]
+        cmd.process_group(0);
+
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| format!("Failed to start the command: {}", e))?;
+        let stdout = CappedOutput::read(child.stdout.take(), self.max_output);
+        let stderr = CappedOutput::read(child.stderr.take(), self.max_output);
+
+        let (exit_code, timed_out) = match tokio::time::timeout(self.timeout, child.wait()).await {
+            Ok(status) => {
+                let status =
+                    status.map_err(|e| format!("Failed to wait for the command: {}", e))?;
+                (status.code(), false)
+            }
+            Err(_) => {
+                kill_process_group(&child);
+                let _ = child.kill().await;
+                (None, true)
+            }
+        };
+
+        let ((stdout, stdout_truncated), (stderr, stderr_truncated)) =
+            tokio::join!(stdout.finish(), stderr.finish());
+        Ok(CommandOutput {
+            stdout,
+            stderr,
+            exit_code,
+            timed_out,
+            truncated: stdout_truncated || stderr_truncated,
+        })
+    }
+}
+
+#[async_trait]
+impl Tool for CliTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        "Run a shell command; \"content\" is the command line"
+    }
+
+    async fn call(&self, content: &str) -> Result<String, String> {
+        self.run(content)
+            .await
+            .map(|output| output.format_for_model())
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("cmd");
+    cmd.arg("/C").arg(command);
+    cmd
+}
+
+// Kill the shell's process group, e.g. a `sleep` or `find /` the shell is waiting on
+#[cfg(unix)]
+fn kill_process_group(child: &tokio::process::Child) {
+    if let Some(pid) = child.id() {
+        // SAFETY: kill() only sends a signal; the group id is the shell's pid because
+        // the shell was spawned with process_group(0)
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(windows)]
+fn kill_process_group(_child: &tokio::process::Child) {}
+
+// Words of each simple command in a command line, split the way a shell would closely
+// enough to find the programs: quotes and `${...}` stay inside one word, redirects such as
+// `2>&1` and `&>` do not separate commands, and substitutions start commands of their own.
+#[derive(Default)]
+struct Words {
+    commands: Vec<Vec<String>>,
+    current: Vec<String>,
+    word: String,
+    // Distinguishes an empty quoted word ("") from no word at all
+    in_word: bool,
+}
+
+impl Words {
+    fn end_word(&mut self) {
+        if self.in_word {
+            self.current.push(std::mem::take(&mut self.word));
+            self.in_word = false;
+        }
+    }
+
+    fn end_command(&mut self) {
+        self.end_word();
+        if !self.current.is_empty() {
+            self.commands.push(std::mem::take(&mut self.current));
+        }
+    }
+
+    fn push(&mut self, c: char) {
+        self.word.push(c);
+        self.in_word = true;
+    }
+
+    fn split(command: &str) -> Vec<Vec<String>> {
+        let mut words = Self::default();
+        // Open double quotes, `$(` (closed by ')') and backticks, innermost last
+        let mut nesting: Vec<char> = Vec::new();
+        let mut chars = command.chars().peekable();
+        while let Some(c) = chars.next() {
+            if nesting.last() == Some(&'"') {
+                match c {
+                    '"' => {
+                        nesting.pop();
+                    }
+                    '\\' => {
+                        if let Some(next) = chars.next() {
+                            words.push(next);
+                        }
+                    }
+                    '$' if chars.peek() == Some(&'(') => {
+                        chars.next();
+                        words.end_command();
+                        nesting.push(')');
+                    }
+                    '`' => {
+                        words.end_command();
+                        nesting.push('`');
+                    }
+                    _ => words.push(c),
+                }
+                continue;
+            }
+            match c {
+                '\'' => {
+                    words.in_word = true;
+                    for c in chars.by_ref() {
+                        if c == '\'' {
+                            break;
+                        }
+                        words.word.push(c);
+                    }
+                }
+                '"' => {
+                    words.in_word = true;
+                    nesting.push('"');
+                }
+                '\\' => {
+                    if let Some(next) = chars.next() {
+                        words.push(next);
+                    }
+                }
+                '$' if chars.peek() == Some(&'{') => {
+                    let mut depth = 0;
+                    for c in chars.by_ref() {
+                        words.push(c);
+                        match c {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            _ => {}
+                        }
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                }
+                '$' if chars.peek() == Some(&'(') => {
+                    chars.next();
+                    words.end_command();
+                    nesting.push(')');
+                }
+                '`' => {
+                    words.end_command();
+                    if nesting.last() == Some(&'`') {
+                        nesting.pop();
+                    } else {
+                        nesting.push('`');
+                    }
+                }
+                ')' if nesting.last() == Some(&')') => {
+                    words.end_command();
+                    nesting.pop();
+                }
+                '<' | '>' => {
+                    // A redirect is a word of its own, keeping a file descriptor number before it
+                    if !words.word.chars().all(|c| c.is_ascii_digit()) {
+                        words.end_word();
+                    }
+                    if chars.peek() == Some(&'(') {
+                        // Process substitution
+                        chars.next();
+                        words.end_command();
+                        nesting.push(')');
+                        continue;
+                    }
+                    words.push(c);
+                    while let Some(&next) = chars.peek() {
+                        if !matches!(next, '<' | '>' | '&' | '|') {
+                            break;
+                        }
+                        words.push(next);
+                        chars.next();
+                    }
+                }
+                '&' if chars.peek() == Some(&'>') => {
+                    words.end_word();
+                    words.push(c);
+                    while let Some(&next) = chars.peek() {
+                        if next != '>' {
+                            break;
+                        }
+                        words.push(next);
+                        chars.next();
+                    }
+                }
+                ';' | '|' | '&' | '\n' | '(' | ')' => words.end_command(),
+                // Braces group commands only as words of their own; a{b,c} is one word
+                '{' | '}' if !words.in_word => words.end_command(),
+                c if c.is_whitespace() => words.end_word(),
+                _ => words.push(c),
+            }
+        }
+        words.end_command();
+        words.commands
+    }
+}
+
+fn is_redirect(word: &str) -> bool {
+    let operator = word.trim_start_matches(|c: char| c.is_ascii_digit());
+    operator.starts_with(['<', '>']) || operator.starts_with("&>")
+}
+
+// Names of the programs a command line runs: the first word of each simple command
+fn programs(command: &str) -> Vec<String> {
+    let mut programs = Vec::new();
+    for words in Words::split(command) {
+        let mut words = words.iter();
+        while let Some(word) = words.next() {
+            if is_redirect(word) {
+                // A bare operator such as `>` takes the next word as its target
+                let target =
+                    word.trim_start_matches(|c: char| c.is_ascii_digit() || "<>&|".contains(c));
+                if target.is_empty() {
+                    words.next();
+                }
+                continue;
+            }
+            let keyword = matches!(
+                word.as_str(),
+                "!" | "if"
+                    | "elif"
+                    | "then"
+                    | "else"
+                    | "fi"
+                    | "while"
+                    | "until"
+                    | "do"
+                    | "done"
+                    | "exec"
+                    | "time"
+            );
+            if keyword || (word.contains('=') && !word.starts_with('-')) {
+                continue;
+            }
+            let name = word.rsplit(['/', '\\']).next().unwrap_or(word);
+            programs.push(name.to_string());
+            break;
+        }
+    }
+    programs
+}
+
+// Stdout or stderr read in the background, keeping at most `cap` bytes
+struct CappedOutput {
+    buffer: Arc<Mutex<(Vec<u8>, bool)>>,
+    reader: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl CappedOutput {
+    fn read<R: AsyncRead + Unpin + Send + 'static>(pipe: Option<R>, cap: usize) -> Self {
+        let buffer = Arc::new(Mutex::new((Vec::new(), false)));
+        let reader = pipe.map(|mut pipe| {
+            let buffer = buffer.clone();
+            tokio::spawn(async move {
+                let mut chunk = [0u8; 8192];
+                // Keep draining past the cap so the command never blocks on a full pipe
+                while let Ok(n) = pipe.read(&mut chunk).await {
+                    if n == 0 {
+                        break;
+                    }
+                    let mut buffer = buffer.lock();
+                    let room = cap.saturating_sub(buffer.0.len());
+                    if n > room {
+                        buffer.1 = true;
+                    }
+                    buffer.0.extend_from_slice(&chunk[..n.min(room)]);
+                }
+            })
+        });
+        Self { buffer, reader }
+    }
+
+    // Background processes may hold the pipe open; stop reading after a short grace period
+    async fn finish(mut self) -> (String, bool) {
+        if let Some(mut reader) = self.reader.take() {
+            if tokio::time::timeout(OUTPUT_GRACE, &mut reader)
+                .await
+                .is_err()
+            {
+                reader.abort();
+            }
+        }
+        let buffer = self.buffer.lock();
+        (String::from_utf8_lossy(&buffer.0).into_owned(), buffer.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowing(programs: &[&str]) -> CliTool {
+        CliTool::new().allow(programs)
+    }
+
+    #[test]
+    fn programs_are_the_first_word_of_each_command() {
+        assert_eq!(
+            programs("cd src && FOO=1 /bin/ls -la | grep x; (cat y) || echo z &"),
+            ["cd", "ls", "grep", "cat", "echo"]
+        );
+        assert_eq!(programs("if test -f x; then cat x; fi"), ["test", "cat"]);
+        assert_eq!(programs("{ ls; pwd; }"), ["ls", "pwd"]);
+        assert_eq!(programs("> out.txt sort in.txt"), ["sort"]);
+    }
+
+    #[test]
+    fn redirects_expansions_and_quotes_do_not_split_commands() {
+        let tool = allowing(&["ls", "echo", "grep", "cat"]);
+        assert_eq!(tool.check("ls 2>&1"), Ok(()));
+        assert_eq!(tool.check("ls &>/dev/null"), Ok(()));
+        assert_eq!(tool.check("ls >&2"), Ok(()));
+        assert_eq!(tool.check("cat < in.txt >> out.txt"), Ok(()));
+        assert_eq!(tool.check("echo ${HOME}"), Ok(()));
+        assert_eq!(tool.check("echo ${HOME:-/tmp} a{b,c}"), Ok(()));
+        assert_eq!(tool.check("grep \"foo(\" x"), Ok(()));
+        assert_eq!(tool.check("grep 'a|b; c&d' x"), Ok(()));
+        assert_eq!(tool.check(r#"echo "it's" \; rm x"#), Ok(()));
+    }
+
+    #[test]
+    fn allow_list_refuses_other_programs() {
+        let tool = allowing(&["ls"]);
+        assert_eq!(
+            tool.check("ls | rm -rf x"),
+            Err(
+                "The command was refused: \"rm\" is not allowed; allowed programs are ls"
+                    .to_string()
+            )
+        );
+        assert!(tool.check("ls & rm x").is_err());
+        assert!(tool.check("ls\nrm x").is_err());
+        assert!(tool.check("ls 2>&1 && rm x").is_err());
+    }
+
+    #[test]
+    fn default_deny_list_refuses_privileged_programs() {
+        let tool = CliTool::new();
+        assert_eq!(tool.check("ls -la"), Ok(()));
+        assert_eq!(
+            tool.check("sudo ls"),
+            Err("The command was refused: \"sudo\" is not allowed".to_string())
+        );
+        assert!(tool.check("ls && /sbin/reboot").is_err());
+        assert!(tool.check("dd if=/dev/zero of=disk").is_err());
+        assert!(tool.check("echo \"$(sudo id)\"").is_err());
+        assert!(tool.check("echo `shutdown now`").is_err());
+        // Only the program position counts
+        assert_eq!(tool.check("echo sudo"), Ok(()));
+        assert_eq!(tool.check("grep 'sudo; reboot' log"), Ok(()));
+    }
+
+    #[test]
+    fn substitutions_are_refused_with_an_allow_list() {
+        let tool = allowing(&["echo", "cat", "diff"]);
+        let refused =
+            Err("The command was refused: command substitution is not allowed".to_string());
+        assert_eq!(tool.check("echo $(rm -rf x)"), refused);
+        assert_eq!(tool.check("echo `rm -rf x`"), refused);
+        assert_eq!(tool.check("diff <(cat a) b"), refused);
+    }
+
+    #[test]
+    fn empty_commands_are_refused() {
+        assert_eq!(
+            CliTool::new().check("  "),
+            Err("The command is empty".to_string())
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_kills_commands_that_time_out() {
+        let tool = CliTool::new().timeout(Duration::from_millis(200));
+
+        let started = std::time::Instant::now();
+        let output = tool.run("echo started; sleep 10").await.unwrap();
+
+        assert!(output.timed_out);
+        assert_eq!(output.exit_code, None);
+        assert_eq!(output.stdout, "started\n");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_kills_what_the_command_started_on_timeout() {
+        let marker = std::env::temp_dir().join(format!("babel-cli-{:016x}", rand::random::<u64>()));
+        let tool = CliTool::new()
+            .env_allowlist(&["PATH"])
+            .timeout(Duration::from_millis(200));
+
+        // The subshell outlives sh's own process unless the whole group is killed
+        let output = tool
+            .run(&format!("(sleep 1; touch {}) & wait", marker.display()))
+            .await
+            .unwrap();
+        assert!(output.timed_out);
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!marker.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_caps_the_output() {
+        let tool = CliTool::new().max_output(10);
+
+        let output = tool
+            .run("echo 0123456789abcdef; echo err >&2")
+            .await
+            .unwrap();
+
+        assert!(output.success());
+        assert_eq!(output.stdout, "0123456789");
+        assert_eq!(output.stderr, "err\n");
+        assert!(output.truncated);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_clears_the_environment() {
+        // cargo sets CARGO_MANIFEST_DIR for the test process
+        assert!(std::env::var_os("CARGO_MANIFEST_DIR").is_some());
+        let tool = CliTool::new()
+            .env_allowlist(&["PATH"])
+            .env("GREETING", "hello");
+
+        let output = tool
+            .run("echo \"${CARGO_MANIFEST_DIR:-unset} $GREETING ${HOME:-no-home}\"")
+            .await
+            .unwrap();
+
+        assert_eq!(output.stdout, "unset hello no-home\n");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_refuses_before_starting_the_command() {
+        let tool = allowing(&["echo"]).confirm(|_| false);
+
+        assert!(tool
+            .run("ls")
+            .await
+            .unwrap_err()
+            .contains("\"ls\" is not allowed"));
+        assert_eq!(
+            tool.run("echo hi").await,
+            Err("The user declined to run the command".to_string())
+        );
+    }
+}
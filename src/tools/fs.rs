@@ -0,0 +1,661 @@
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use super::patch::parse_patch;
+use super::{Tool, Toolbox};
+
+// Directories grep does not descend into
+const SKIPPED_DIRS: &[&str] = &[".git", "target", "node_modules"];
+
+// The directory filesystem tools are confined to. Paths from the model are relative to it;
+// ".." and symlinks leading outside it are refused. In dry-run mode the tools that write
+// report what they would change and leave the files alone.
+#[derive(Debug, Clone)]
+pub struct FsRoot {
+    root: PathBuf,
+    dry_run: bool,
+    max_read: usize,
+    max_results: usize,
+}
+
+impl FsRoot {
+    // The root must exist
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Self, String> {
+        let root = root.as_ref();
+        let root = root
+            .canonicalize()
+            .map_err(|e| format!("Failed to open root directory {}: {}", root.display(), e))?;
+        if !root.is_dir() {
+            return Err(format!("{} is not a directory", root.display()));
+        }
+        Ok(Self {
+            root,
+            dry_run: false,
+            max_read: 64 * 1024,
+            max_results: 200,
+        })
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    // Bytes read_file returns before truncating (64 KiB by default)
+    pub fn max_read(mut self, bytes: usize) -> Self {
+        self.max_read = bytes;
+        self
+    }
+
+    // Entries list_dir and matches grep return before stopping (200 by default)
+    pub fn max_results(mut self, count: usize) -> Self {
+        self.max_results = count;
+        self
+    }
+
+    pub fn get_root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    // All five tools: read_file, write_file, list_dir, grep and apply_patch
+    pub fn toolbox(&self) -> Toolbox {
+        Toolbox::new()
+            .tool(ReadFileTool::new(self.clone()))
+            .tool(WriteFileTool::new(self.clone()))
+            .tool(ListDirTool::new(self.clone()))
+            .tool(GrepTool::new(self.clone()))
+            .tool(ApplyPatchTool::new(self.clone()))
+    }
+
+    // The absolute path of `path` (relative to the root, or absolute inside it), refusing
+    // anything that leads outside the root
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, String> {
+        let path = path.trim();
+        let outside = || format!("Path \"{}\" is outside the root directory", path);
+        let relative = Path::new(path);
+        let relative = if relative.is_absolute() {
+            relative.strip_prefix(&self.root).map_err(|_| outside())?
+        } else {
+            relative
+        };
+
+        let mut resolved = self.root.clone();
+        for component in relative.components() {
+            match component {
+                Component::Normal(part) => resolved.push(part),
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if resolved == self.root {
+                        return Err(outside());
+                    }
+                    resolved.pop();
+                }
+                Component::RootDir | Component::Prefix(_) => return Err(outside()),
+            }
+        }
+
+        // Symlinks inside the root may point anywhere: check where the existing part leads
+        let mut existing = resolved.as_path();
+        while fs::symlink_metadata(existing).is_err() {
+            existing = existing.parent().ok_or_else(outside)?;
+        }
+        let canonical = existing.canonicalize().map_err(|_| outside())?;
+        if !canonical.starts_with(&self.root) {
+            return Err(outside());
+        }
+        Ok(resolved)
+    }
+
+    // `path` relative to the root with '/' separators, as shown to the model
+    fn display(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let display = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if display.is_empty() {
+            ".".to_string()
+        } else {
+            display
+        }
+    }
+
+    fn read_file(&self, path: &str) -> Result<String, String> {
+        let resolved = self.resolve(path)?;
+        let bytes = fs::read(&resolved)
+            .map_err(|e| format!("Failed to read {}: {}", self.display(&resolved), e))?;
+        if bytes.contains(&0) {
+            return Err(format!("{} is a binary file", self.display(&resolved)));
+        }
+        if bytes.len() <= self.max_read {
+            return Ok(String::from_utf8_lossy(&bytes).into_owned());
+        }
+        let mut text = String::from_utf8_lossy(&bytes[..self.max_read]).into_owned();
+        text.push_str(&format!(
+            "\n(truncated: showing {} of {} bytes)\n",
+            self.max_read,
+            bytes.len()
+        ));
+        Ok(text)
+    }
+
+    fn write_file(&self, content: &str) -> Result<String, String> {
+        let (path, text) = content.split_once('\n').unwrap_or((content, ""));
+        if path.trim().is_empty() {
+            return Err("The first line must be the path of the file to write".to_string());
+        }
+        let resolved = self.resolve(path)?;
+        if resolved.is_dir() {
+            return Err(format!("{} is a directory", self.display(&resolved)));
+        }
+        let action = match fs::metadata(&resolved) {
+            Ok(metadata) => format!("replacing {} bytes", metadata.len()),
+            Err(_) => "creating it".to_string(),
+        };
+        if self.dry_run {
+            return Ok(format!(
+                "Dry run: would write {} bytes to {} ({})",
+                text.len(),
+                self.display(&resolved),
+                action
+            ));
+        }
+        if let Some(parent) = resolved.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", self.display(parent), e))?;
+        }
+        fs::write(&resolved, text)
+            .map_err(|e| format!("Failed to write {}: {}", self.display(&resolved), e))?;
+        Ok(format!(
+            "Wrote {} bytes to {} ({})",
+            text.len(),
+            self.display(&resolved),
+            action
+        ))
+    }
+
+    fn list_dir(&self, path: &str) -> Result<String, String> {
+        let resolved = self.resolve(path)?;
+        let entries = fs::read_dir(&resolved)
+            .map_err(|e| format!("Failed to list {}: {}", self.display(&resolved), e))?;
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                    format!("{}/", name)
+                } else {
+                    name
+                }
+            })
+            .collect();
+        names.sort();
+        if names.is_empty() {
+            return Ok(format!("{} is empty", self.display(&resolved)));
+        }
+        let total = names.len();
+        names.truncate(self.max_results);
+        let mut listing = names.join("\n");
+        if total > self.max_results {
+            listing.push_str(&format!("\n({} more entries)", total - self.max_results));
+        }
+        Ok(listing)
+    }
+
+    fn grep(&self, content: &str) -> Result<String, String> {
+        let mut lines = content.lines();
+        let pattern = lines.next().unwrap_or("");
+        if pattern.is_empty() {
+            return Err("The first line must be the text to search for".to_string());
+        }
+        let start = self.resolve(lines.next().unwrap_or("."))?;
+
+        let mut matches = Vec::new();
+        let mut stack = vec![start];
+        while let Some(path) = stack.pop() {
+            if matches.len() >= self.max_results {
+                break;
+            }
+            if path.is_dir() {
+                let Ok(entries) = fs::read_dir(&path) else {
+                    continue;
+                };
+                let mut children: Vec<PathBuf> = entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| {
+                        let name = entry.file_name();
+                        !SKIPPED_DIRS.iter().any(|skipped| name == *skipped)
+                    })
+                    .map(|entry| entry.path())
+                    // Symlinks are followed only while they stay inside the root
+                    .filter(|child| {
+                        child
+                            .canonicalize()
+                            .is_ok_and(|canonical| canonical.starts_with(&self.root))
+                    })
+                    .collect();
+                // Visit in name order
+                children.sort();
+                children.reverse();
+                stack.extend(children);
+                continue;
+            }
+            let Ok(text) = fs::read_to_string(&path) else {
+                // Binary or unreadable
+                continue;
+            };
+            for (number, line) in text.lines().enumerate() {
+                if line.contains(pattern) {
+                    matches.push(format!(
+                        "{}:{}: {}",
+                        self.display(&path),
+                        number + 1,
+                        line.trim()
+                    ));
+                    if matches.len() >= self.max_results {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if matches.is_empty() {
+            return Ok(format!("No matches for \"{}\"", pattern));
+        }
+        if matches.len() >= self.max_results {
+            matches.push(format!("(stopped after {} matches)", self.max_results));
+        }
+        Ok(matches.join("\n"))
+    }
+
+    fn apply_patch(&self, content: &str) -> Result<String, String> {
+        let patches = parse_patch(content)?;
+
+        // Apply everything in memory first, so a hunk that does not match leaves every file
+        // untouched. Files are then written one by one, so an I/O error partway through can
+        // leave earlier files changed. Each section applies to the file as the earlier
+        // sections left it; None marks a deletion.
+        let mut files: BTreeMap<PathBuf, Option<String>> = BTreeMap::new();
+        let mut summary = Vec::new();
+        for patch in &patches {
+            let old = patch
+                .old_path
+                .as_deref()
+                .map(|path| self.resolve(path))
+                .transpose()?;
+            let new = patch
+                .new_path
+                .as_deref()
+                .map(|path| self.resolve(path))
+                .transpose()?;
+            let original = match (&old, &new) {
+                (None, None) => {
+                    return Err(
+                        "The patch has /dev/null as both the old and the new file".to_string()
+                    )
+                }
+                (Some(old), _) => match files.get(old) {
+                    Some(Some(text)) => text.clone(),
+                    Some(None) => {
+                        return Err(format!(
+                            "{} was deleted earlier in the patch",
+                            self.display(old)
+                        ))
+                    }
+                    None => fs::read_to_string(old)
+                        .map_err(|e| format!("Failed to read {}: {}", self.display(old), e))?,
+                },
+                (None, Some(new)) => {
+                    let exists = match files.get(new) {
+                        Some(text) => text.is_some(),
+                        None => new.exists(),
+                    };
+                    if exists {
+                        return Err(format!("{} already exists", patch.display_path()));
+                    }
+                    String::new()
+                }
+            };
+            let text = patch.apply(&original)?;
+            if new.is_none() && !text.is_empty() {
+                return Err(format!(
+                    "The patch deletes {} but its hunks do not remove all of its lines",
+                    patch.display_path()
+                ));
+            }
+
+            let (added, removed) = (patch.added(), patch.removed());
+            summary.push(match (&old, &new) {
+                (None, None) => unreachable!("checked above"),
+                (Some(old), None) => format!("Deleted {}", self.display(old)),
+                (None, Some(new)) => format!("Created {} (+{})", self.display(new), added),
+                (Some(old), Some(new)) if old != new => format!(
+                    "Renamed {} to {} (+{} -{})",
+                    self.display(old),
+                    self.display(new),
+                    added,
+                    removed
+                ),
+                (Some(_), Some(new)) => {
+                    format!("Updated {} (+{} -{})", self.display(new), added, removed)
+                }
+            });
+
+            if let Some(old) = old {
+                if new.as_ref() != Some(&old) {
+                    files.insert(old, None);
+                }
+            }
+            if let Some(new) = new {
+                files.insert(new, Some(text));
+            }
+        }
+
+        let summary = summary.join("\n");
+        if self.dry_run {
+            return Ok(format!("Dry run, nothing was written:\n{}", summary));
+        }
+        for (path, text) in &files {
+            self.write_change(path, text.as_deref())?;
+        }
+        Ok(summary)
+    }
+
+    // Write the patched text of a file, or delete it
+    fn write_change(&self, path: &Path, text: Option<&str>) -> Result<(), String> {
+        match text {
+            Some(text) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)
+                        .map_err(|e| format!("Failed to create {}: {}", self.display(parent), e))?;
+                }
+                fs::write(path, text)
+                    .map_err(|e| format!("Failed to write {}: {}", self.display(path), e))
+            }
+            // Files created and deleted again within the patch never reached the disk
+            None if !path.exists() => Ok(()),
+            None => fs::remove_file(path)
+                .map_err(|e| format!("Failed to delete {}: {}", self.display(path), e)),
+        }
+    }
+}
+
+// Run blocking filesystem work off the async runtime
+async fn blocking<F>(work: F) -> Result<String, String>
+where
+    F: FnOnce() -> Result<String, String> + Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| format!("The tool failed: {}", e))?
+}
+
+macro_rules! fs_tool {
+    ($tool:ident, $name:expr, $description:expr, $method:ident) => {
+        #[derive(Debug, Clone)]
+        pub struct $tool {
+            root: FsRoot,
+        }
+
+        impl $tool {
+            pub fn new(root: FsRoot) -> Self {
+                Self { root }
+            }
+        }
+
+        #[async_trait]
+        impl Tool for $tool {
+            fn name(&self) -> &str {
+                $name
+            }
+
+            fn description(&self) -> &str {
+                $description
+            }
+
+            async fn call(&self, content: &str) -> Result<String, String> {
+                let root = self.root.clone();
+                let content = content.to_string();
+                blocking(move || root.$method(&content)).await
+            }
+        }
+    };
+}
+
+fs_tool!(
+    ReadFileTool,
+    "read_file",
+    "Read a text file; \"content\" is its path",
+    read_file
+);
+fs_tool!(
+    WriteFileTool,
+    "write_file",
+    "Create or replace a file; \"content\" is the path on the first line, then the full new contents",
+    write_file
+);
+fs_tool!(
+    ListDirTool,
+    "list_dir",
+    "List a directory, directories ending in '/'; \"content\" is its path, \".\" for the project root",
+    list_dir
+);
+fs_tool!(
+    GrepTool,
+    "grep",
+    "Find lines containing some text, as \"path:line: text\"; \"content\" is the text on the first line, optionally followed by a file or directory to search on the second",
+    grep
+);
+fs_tool!(
+    ApplyPatchTool,
+    "apply_patch",
+    "Edit files with a unified diff (\"--- a/path\", \"+++ b/path\", \"@@\" hunks with context lines); \"content\" is the diff",
+    apply_patch
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory under the system temp dir, removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("babel-fs-{:016x}", rand::random::<u64>()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir.canonicalize().unwrap())
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn outside(path: &str) -> Result<PathBuf, String> {
+        Err(format!("Path \"{}\" is outside the root directory", path))
+    }
+
+    #[test]
+    fn resolve_refuses_parent_dirs_leaving_the_root() {
+        let dir = TempDir::new();
+        let root = FsRoot::new(&dir.0).unwrap();
+
+        assert_eq!(
+            root.resolve("src/../README.md"),
+            Ok(dir.0.join("README.md"))
+        );
+        assert_eq!(
+            root.resolve("./src/./main.rs"),
+            Ok(dir.0.join("src/main.rs"))
+        );
+        assert_eq!(root.resolve(".."), outside(".."));
+        assert_eq!(root.resolve("src/../../x"), outside("src/../../x"));
+    }
+
+    #[test]
+    fn resolve_refuses_absolute_paths_outside_the_root() {
+        let dir = TempDir::new();
+        let root = FsRoot::new(&dir.0).unwrap();
+        let inside = dir.0.join("notes.txt");
+
+        assert_eq!(root.resolve(inside.to_str().unwrap()), Ok(inside));
+        assert_eq!(root.resolve("/etc/passwd"), outside("/etc/passwd"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_refuses_symlinks_leading_outside_the_root() {
+        let dir = TempDir::new();
+        let other = TempDir::new();
+        fs::write(other.0.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(&other.0, dir.0.join("escape")).unwrap();
+        std::os::unix::fs::symlink(other.0.join("missing"), dir.0.join("dangling")).unwrap();
+        fs::create_dir(dir.0.join("src")).unwrap();
+        std::os::unix::fs::symlink(dir.0.join("src"), dir.0.join("inside")).unwrap();
+        let root = FsRoot::new(&dir.0).unwrap();
+
+        assert_eq!(root.resolve("escape"), outside("escape"));
+        assert_eq!(
+            root.resolve("escape/secret.txt"),
+            outside("escape/secret.txt")
+        );
+        assert_eq!(root.resolve("escape/new.txt"), outside("escape/new.txt"));
+        assert_eq!(root.resolve("dangling"), outside("dangling"));
+        assert_eq!(
+            root.resolve("dangling/new.txt"),
+            outside("dangling/new.txt")
+        );
+        assert_eq!(
+            root.resolve("inside/lib.rs"),
+            Ok(dir.0.join("inside/lib.rs"))
+        );
+
+        assert!(root.read_file("escape/secret.txt").is_err());
+        assert!(root.write_file("dangling\nowned").is_err());
+        assert!(!other.0.join("missing").exists());
+    }
+
+    #[test]
+    fn dry_run_leaves_files_unchanged() {
+        let dir = TempDir::new();
+        fs::write(dir.0.join("a.txt"), "one\ntwo\n").unwrap();
+        let root = FsRoot::new(&dir.0).unwrap().dry_run(true);
+
+        assert!(root
+            .write_file("b.txt\nnew")
+            .unwrap()
+            .starts_with("Dry run"));
+        let patch = "--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n one\n-two\n+three\n";
+        assert_eq!(
+            root.apply_patch(patch),
+            Ok("Dry run, nothing was written:\nUpdated a.txt (+1 -1)".to_string())
+        );
+
+        assert_eq!(
+            fs::read_to_string(dir.0.join("a.txt")).unwrap(),
+            "one\ntwo\n"
+        );
+        assert!(!dir.0.join("b.txt").exists());
+    }
+
+    #[test]
+    fn apply_patch_applies_sections_for_the_same_file_in_order() {
+        let dir = TempDir::new();
+        fs::write(dir.0.join("a.txt"), "one\ntwo\nthree\nfour\n").unwrap();
+        let root = FsRoot::new(&dir.0).unwrap();
+        let patch = "\
+--- a/a.txt
++++ b/a.txt
+@@ -1,2 +1,2 @@
+-one
++ONE
+ two
+--- a/a.txt
++++ b/a.txt
+@@ -3,2 +3,2 @@
+ three
+-four
++FOUR
+";
+
+        root.apply_patch(patch).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.0.join("a.txt")).unwrap(),
+            "ONE\ntwo\nthree\nFOUR\n"
+        );
+    }
+
+    #[test]
+    fn apply_patch_writes_nothing_when_a_section_fails() {
+        let dir = TempDir::new();
+        fs::write(dir.0.join("a.txt"), "one\n").unwrap();
+        fs::write(dir.0.join("b.txt"), "two\n").unwrap();
+        let root = FsRoot::new(&dir.0).unwrap();
+        let patch = "\
+--- a/a.txt
++++ b/a.txt
+@@ -1 +1 @@
+-one
++ONE
+--- a/b.txt
++++ b/b.txt
+@@ -1 +1 @@
+-missing
++MISSING
+";
+
+        assert!(root.apply_patch(patch).is_err());
+        assert_eq!(fs::read_to_string(dir.0.join("a.txt")).unwrap(), "one\n");
+        assert_eq!(fs::read_to_string(dir.0.join("b.txt")).unwrap(), "two\n");
+    }
+
+    #[test]
+    fn apply_patch_deletes_files_whose_hunks_remove_everything() {
+        let dir = TempDir::new();
+        fs::write(dir.0.join("a.txt"), "one\ntwo\n").unwrap();
+        let root = FsRoot::new(&dir.0).unwrap();
+        let patch = "\
+--- a/a.txt
++++ /dev/null
+@@ -1,2 +0,0 @@
+-one
+-two
+";
+
+        assert_eq!(root.apply_patch(patch).unwrap(), "Deleted a.txt");
+        assert!(!dir.0.join("a.txt").exists());
+    }
+
+    #[test]
+    fn apply_patch_refuses_deletions_that_leave_lines() {
+        let dir = TempDir::new();
+        fs::write(dir.0.join("a.txt"), "one\ntwo\n").unwrap();
+        let root = FsRoot::new(&dir.0).unwrap();
+        let patch = "\
+--- a/a.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-one
+";
+
+        assert_eq!(
+            root.apply_patch(patch).unwrap_err(),
+            "The patch deletes a.txt but its hunks do not remove all of its lines"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.0.join("a.txt")).unwrap(),
+            "one\ntwo\n"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::protocol::{AgentProtocol, ToolInvocation, ToolSpec};
+
+mod cli;
+mod fs;
+mod patch;
+#[cfg(feature = "tool-schemas")]
+mod typed;
+
+pub use cli::{CliTool, CommandOutput};
+pub use fs::{ApplyPatchTool, FsRoot, GrepTool, ListDirTool, ReadFileTool, WriteFileTool};
+#[cfg(feature = "tool-schemas")]
+pub use typed::{Typed, TypedTool};
+
+// Something the model can call by name through the {"tool": {"name", "content"}} envelope
+#[async_trait]
+pub trait Tool: Send + Sync + fmt::Debug {
+    fn name(&self) -> &str;
+
+    // Shown to the model in the system prompt; say what "content" should be
+    fn description(&self) -> &str;
+
+    // JSON schema of "content" for tools taking a JSON object of arguments instead of text
+    fn parameters(&self) -> Option<Value> {
+        None
+    }
+
+    // Run the tool with the call's "content". Both the output and the error are meant to
+    // be sent back to the model.
+    async fn call(&self, content: &str) -> Result<String, String>;
+}
+
+// The tools an agent offers, dispatching the model's calls to them by name
+#[derive(Debug, Clone, Default)]
+pub struct Toolbox {
+    tools: Vec<Arc<dyn Tool>>,
+}
+
+impl Toolbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Replaces a tool with the same name
+    pub fn tool<T: Tool + 'static>(mut self, tool: T) -> Self {
+        self.tools.retain(|existing| existing.name() != tool.name());
+        self.tools.push(Arc::new(tool));
+        self
+    }
+
+    // Add a tool taking typed arguments; same as tool(Typed::new(tool))
+    #[cfg(feature = "tool-schemas")]
+    pub fn typed<T: TypedTool>(self, tool: T) -> Self {
+        self.tool(Typed::new(tool))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Tool>> {
+        self.tools.iter().find(|tool| tool.name() == name)
+    }
+
+    pub fn get_tools(&self) -> &[Arc<dyn Tool>] {
+        &self.tools
+    }
+
+    // The reply protocol listing these tools, for the system prompt and parsing replies
+    pub fn protocol(&self) -> AgentProtocol {
+        self.tools
+            .iter()
+            .fold(AgentProtocol::new(), |protocol, tool| {
+                protocol.tool_spec(ToolSpec {
+                    name: tool.name().to_string(),
+                    description: tool.description().to_string(),
+                    parameters: tool.parameters(),
+                })
+            })
+    }
+
+    // Run the tool the model asked for
+    pub async fn call(&self, invocation: &ToolInvocation) -> Result<String, String> {
+        let tool = self
+            .get(&invocation.name)
+            .ok_or_else(|| format!("Unknown tool \"{}\"", invocation.name))?;
+        tool.call(&invocation.content).await
+    }
+}
@@ -0,0 +1,265 @@
+// Unified diffs as written by models: `--- a/path` / `+++ b/path` headers and `@@` hunks.
+// Line numbers are only a hint; hunks are placed by their context, and headers without
+// numbers (`@@ @@`) are accepted.
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FilePatch {
+    // None for /dev/null, i.e. a new or deleted file
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Hunk {
+    // 1-based line of the hunk in the original file, if given
+    old_start: Option<usize>,
+    lines: Vec<HunkLine>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl FilePatch {
+    pub fn added(&self) -> usize {
+        self.count(|line| matches!(line, HunkLine::Add(_)))
+    }
+
+    pub fn removed(&self) -> usize {
+        self.count(|line| matches!(line, HunkLine::Remove(_)))
+    }
+
+    fn count(&self, filter: impl Fn(&HunkLine) -> bool) -> usize {
+        self.hunks
+            .iter()
+            .flat_map(|hunk| &hunk.lines)
+            .filter(|line| filter(line))
+            .count()
+    }
+
+    // The patched text of `original` ("" for a new file)
+    pub fn apply(&self, original: &str) -> Result<String, String> {
+        let mut patched: Vec<String> = original.lines().map(String::from).collect();
+        // Shift of later hunks caused by the lines earlier hunks added or removed
+        let mut offset: isize = 0;
+
+        for (number, hunk) in self.hunks.iter().enumerate() {
+            let old: Vec<&str> = hunk
+                .lines
+                .iter()
+                .filter_map(|line| match line {
+                    HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                    HunkLine::Add(_) => None,
+                })
+                .collect();
+            let expected = hunk
+                .old_start
+                .map(|start| (start.saturating_sub(1) as isize + offset).max(0) as usize)
+                .unwrap_or(0);
+            let at = find_lines(&patched, &old, expected).ok_or_else(|| {
+                format!(
+                    "Hunk {} of {} does not match the file: its context and removed lines were not found",
+                    number + 1,
+                    self.display_path()
+                )
+            })?;
+
+            let new: Vec<String> = hunk
+                .lines
+                .iter()
+                .filter_map(|line| match line {
+                    HunkLine::Context(text) | HunkLine::Add(text) => Some(text.clone()),
+                    HunkLine::Remove(_) => None,
+                })
+                .collect();
+            offset += new.len() as isize - old.len() as isize;
+            patched.splice(at..at + old.len(), new);
+        }
+
+        let mut text = patched.join("\n");
+        if !patched.is_empty() && (original.is_empty() || original.ends_with('\n')) {
+            text.push('\n');
+        }
+        Ok(text)
+    }
+
+    pub fn display_path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or("/dev/null")
+    }
+}
+
+// Position of `needle` in `lines` closest to `expected`, comparing exactly first and then
+// ignoring trailing whitespace
+fn find_lines(lines: &[String], needle: &[&str], expected: usize) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(expected.min(lines.len()));
+    }
+    if needle.len() > lines.len() {
+        return None;
+    }
+    let last = lines.len() - needle.len();
+    let matches_at = |at: usize, exact: bool| {
+        needle.iter().enumerate().all(|(i, line)| {
+            if exact {
+                lines[at + i] == *line
+            } else {
+                lines[at + i].trim_end() == line.trim_end()
+            }
+        })
+    };
+    for exact in [true, false] {
+        let expected = expected.min(last);
+        for distance in 0..=last {
+            let candidates = [
+                expected.checked_sub(distance),
+                expected.checked_add(distance),
+            ];
+            for at in candidates.into_iter().flatten() {
+                if at <= last && matches_at(at, exact) {
+                    return Some(at);
+                }
+            }
+        }
+    }
+    None
+}
+
+// Split a unified diff into its files
+pub(crate) fn parse_patch(text: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut patches = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let Some(old) = lines[i].strip_prefix("--- ") else {
+            // Prose, "diff --git" and "index" lines
+            i += 1;
+            continue;
+        };
+        let Some(new) = lines.get(i + 1).and_then(|line| line.strip_prefix("+++ ")) else {
+            i += 1;
+            continue;
+        };
+        let mut patch = FilePatch {
+            old_path: header_path(old),
+            new_path: header_path(new),
+            hunks: Vec::new(),
+        };
+        i += 2;
+
+        while i < lines.len() && lines[i].starts_with("@@") {
+            let (old_start, counts) = hunk_header(lines[i]);
+            i += 1;
+            let mut hunk = Hunk {
+                old_start,
+                lines: Vec::new(),
+            };
+            let (mut old_left, mut new_left) = counts.unwrap_or((usize::MAX, usize::MAX));
+            while i < lines.len() && (old_left > 0 || new_left > 0) {
+                let line = lines[i];
+                if counts.is_none() && (line.starts_with("@@") || is_file_header(&lines, i)) {
+                    break;
+                }
+                let parsed = match line.chars().next() {
+                    Some('+') => HunkLine::Add(line[1..].to_string()),
+                    Some('-') => HunkLine::Remove(line[1..].to_string()),
+                    Some(' ') => HunkLine::Context(line[1..].to_string()),
+                    // Editors and models drop the space of empty context lines
+                    None => HunkLine::Context(String::new()),
+                    Some('\\') => {
+                        // "\ No newline at end of file"
+                        i += 1;
+                        continue;
+                    }
+                    Some(_) if counts.is_none() => break,
+                    Some(_) => {
+                        return Err(format!(
+                            "Malformed hunk in the patch for {}: unexpected line {:?}",
+                            patch.display_path(),
+                            line
+                        ))
+                    }
+                };
+                match parsed {
+                    HunkLine::Add(_) => new_left = new_left.saturating_sub(1),
+                    HunkLine::Remove(_) => old_left = old_left.saturating_sub(1),
+                    HunkLine::Context(_) => {
+                        old_left = old_left.saturating_sub(1);
+                        new_left = new_left.saturating_sub(1);
+                    }
+                }
+                hunk.lines.push(parsed);
+                i += 1;
+            }
+            if counts.is_none() {
+                // Blank lines after the diff are not context
+                while hunk.lines.last() == Some(&HunkLine::Context(String::new())) {
+                    hunk.lines.pop();
+                }
+            }
+            patch.hunks.push(hunk);
+        }
+
+        if patch.hunks.is_empty() {
+            return Err(format!(
+                "The patch for {} has no hunks",
+                patch.display_path()
+            ));
+        }
+        patches.push(patch);
+    }
+
+    if patches.is_empty() {
+        return Err(
+            "No unified diff found: expected \"--- a/path\", \"+++ b/path\" and \"@@\" hunks"
+                .to_string(),
+        );
+    }
+    Ok(patches)
+}
+
+fn is_file_header(lines: &[&str], i: usize) -> bool {
+    lines[i].starts_with("--- ")
+        && lines
+            .get(i + 1)
+            .is_some_and(|line| line.starts_with("+++ "))
+}
+
+// "a/src/main.rs\t2024-01-01 ..." -> "src/main.rs"
+fn header_path(header: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or(header).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+    Some(path.to_string())
+}
+
+// "@@ -12,5 +12,7 @@ fn main()" -> (Some(12), Some((5, 7)))
+fn hunk_header(header: &str) -> (Option<usize>, Option<(usize, usize)>) {
+    let mut ranges = header.trim_start_matches('@').split_whitespace();
+    let old = ranges.next().and_then(|range| range.strip_prefix('-'));
+    let new = ranges.next().and_then(|range| range.strip_prefix('+'));
+    let parse = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    match (old.and_then(parse), new.and_then(parse)) {
+        (Some((old_start, old_count)), Some((_, new_count))) => {
+            (Some(old_start), Some((old_count, new_count)))
+        }
+        _ => (None, None),
+    }
+}
@@ -0,0 +1,210 @@
+use async_trait::async_trait;
+use schemars::{schema_for, JsonSchema};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt;
+
+use super::Tool;
+use crate::utils::extract_json_as;
+
+// A tool taking a JSON object of arguments, deserialized into `Args` before run() is
+// called. Wrapped in Typed, its name, description and parameter schema come from `Args`:
+// the type name in snake_case without an "Args" suffix, the type's doc comment, and the
+// fields with their doc comments. Needs the `tool-schemas` feature.
+#[async_trait]
+pub trait TypedTool: Send + Sync + fmt::Debug + 'static {
+    type Args: DeserializeOwned + JsonSchema + Send;
+
+    async fn run(&self, args: Self::Args) -> Result<String, String>;
+}
+
+// Adapts a TypedTool to Tool, e.g. Toolbox::new().tool(Typed::new(SearchTool))
+#[derive(Debug, Clone)]
+pub struct Typed<T: TypedTool> {
+    tool: T,
+    name: String,
+    description: String,
+    parameters: Value,
+}
+
+impl<T: TypedTool> Typed<T> {
+    pub fn new(tool: T) -> Self {
+        let mut parameters = schema_for!(T::Args).to_value();
+        let description = match parameters.as_object_mut() {
+            Some(schema) => {
+                // Shown next to the tool rather than in the schema
+                schema.remove("$schema");
+                schema.remove("title");
+                schema
+                    .remove("description")
+                    .and_then(|description| description.as_str().map(String::from))
+                    .unwrap_or_default()
+            }
+            None => String::new(),
+        };
+        Self {
+            tool,
+            name: tool_name(&T::Args::schema_name()),
+            description,
+            parameters,
+        }
+    }
+
+    // Override the name derived from the argument type
+    pub fn tool_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    // Override the argument type's doc comment
+    pub fn tool_description(mut self, description: &str) -> Self {
+        self.description = description.to_string();
+        self
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.tool
+    }
+}
+
+#[async_trait]
+impl<T: TypedTool> Tool for Typed<T> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> Option<Value> {
+        Some(self.parameters.clone())
+    }
+
+    async fn call(&self, content: &str) -> Result<String, String> {
+        let args = match serde_json::from_str(content) {
+            Ok(args) => args,
+            // Fenced or slightly malformed JSON
+            Err(e) => extract_json_as(content)
+                .map_err(|_| format!("Invalid arguments for tool \"{}\": {}", self.name, e))?,
+        };
+        self.tool.run(args).await
+    }
+}
+
+// "ReadLinesArgs" -> "read_lines"
+fn tool_name(type_name: &str) -> String {
+    let type_name = type_name
+        .strip_suffix("Args")
+        .or_else(|| type_name.strip_suffix("Arguments"))
+        .filter(|name| !name.is_empty())
+        .unwrap_or(type_name);
+    let chars: Vec<char> = type_name.chars().collect();
+    let mut name = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            // A new word: "readLines" or the end of an acronym, "HTTPRequest"
+            let previous = chars[i - 1];
+            let next_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_lower)
+            {
+                name.push('_');
+            }
+        }
+        name.extend(c.to_lowercase());
+    }
+    name
+}
+
+#[cfg(all(test, feature = "tool-schemas"))]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    /// Add two numbers
+    #[derive(Debug, Deserialize, JsonSchema)]
+    struct AddNumbersArgs {
+        /// The first number
+        a: i64,
+        b: i64,
+    }
+
+    #[derive(Debug)]
+    struct Adder;
+
+    #[async_trait]
+    impl TypedTool for Adder {
+        type Args = AddNumbersArgs;
+
+        async fn run(&self, args: AddNumbersArgs) -> Result<String, String> {
+            Ok((args.a + args.b).to_string())
+        }
+    }
+
+    #[test]
+    fn tool_names_are_snake_case_without_the_args_suffix() {
+        assert_eq!(tool_name("ReadLinesArgs"), "read_lines");
+        assert_eq!(tool_name("HTTPRequestArgs"), "http_request");
+        assert_eq!(tool_name("FetchURL"), "fetch_url");
+        assert_eq!(tool_name("SearchArguments"), "search");
+        assert_eq!(tool_name("Utf8DecodeArgs"), "utf8_decode");
+        assert_eq!(tool_name("Sha256Args"), "sha256");
+        assert_eq!(tool_name("Args"), "args");
+        assert_eq!(tool_name("grep"), "grep");
+    }
+
+    #[test]
+    fn name_and_description_come_from_the_argument_type() {
+        let tool = Typed::new(Adder);
+
+        assert_eq!(tool.name(), "add_numbers");
+        assert_eq!(tool.description(), "Add two numbers");
+        let parameters = tool.parameters().unwrap();
+        assert!(parameters.get("$schema").is_none());
+        assert!(parameters.get("title").is_none());
+        assert!(parameters.get("description").is_none());
+        assert_eq!(parameters["type"], "object");
+        assert_eq!(parameters["required"], json!(["a", "b"]));
+        assert_eq!(
+            parameters["properties"]["a"]["description"],
+            "The first number"
+        );
+    }
+
+    #[test]
+    fn overrides_replace_the_derived_name_and_description() {
+        let tool = Typed::new(Adder)
+            .tool_name("add")
+            .tool_description("Sum a and b");
+
+        assert_eq!(tool.name(), "add");
+        assert_eq!(tool.description(), "Sum a and b");
+    }
+
+    #[tokio::test]
+    async fn call_accepts_plain_and_fenced_json() {
+        let tool = Typed::new(Adder);
+
+        assert_eq!(tool.call(r#"{"a": 1, "b": 2}"#).await, Ok("3".to_string()));
+        assert_eq!(
+            tool.call("```json\n{\"a\": 40, \"b\": 2}\n```").await,
+            Ok("42".to_string())
+        );
+        assert_eq!(
+            tool.call("Sure: {\"a\": 1, \"b\": 1,}").await,
+            Ok("2".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn call_reports_invalid_arguments() {
+        let tool = Typed::new(Adder);
+
+        let error = tool.call(r#"{"a": 1}"#).await.unwrap_err();
+
+        assert!(error.starts_with("Invalid arguments for tool \"add_numbers\": missing field `b`"));
+    }
+}
@@ -0,0 +1,209 @@
+// End-to-end tests: real clients talking HTTP to babel-mock-server
+use babel::mock_server::{MockServer, MockServerConfig};
+use babel::model::{
+    ChatMessage, Groq, GroqModel, LLMBuilder, LLMClient, OpenRouter, OpenRouterModel, Provider,
+    RateLimit, RateLimiter, SambaNova, SambaNovaModel,
+};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+
+fn user(content: &str) -> Vec<ChatMessage> {
+    vec![ChatMessage {
+        role: "user".to_string(),
+        content: content.to_string(),
+    }]
+}
+
+fn client<P: Provider>(server: &MockServer, model: P::ModelType) -> LLMClient<P> {
+    LLMBuilder::<P>::new()
+        .model(model)
+        .api_key("test-key".to_string())
+        .base_url(server.base_url())
+        .build()
+        .unwrap()
+}
+
+// Content of every chunk, and the usage reported at the end
+async fn stream<P: Provider>(client: &LLMClient<P>, content: &str) -> (Vec<String>, Option<u32>) {
+    let mut stream = client.stream_chat(user(content)).await;
+    let mut chunks = Vec::new();
+    let mut total_tokens = None;
+    while let Some(response) = stream.next().await {
+        let response = response.unwrap();
+        if let Some(content) = response.get_content().filter(|content| !content.is_empty()) {
+            chunks.push(content);
+        }
+        if let Some(usage) = response.get_usage() {
+            total_tokens = usage.total_tokens;
+        }
+    }
+    (chunks, total_tokens)
+}
+
+#[tokio::test]
+async fn providers_stream_from_the_mock_server() {
+    let server = MockServer::start(MockServerConfig::new().echo())
+        .await
+        .unwrap();
+
+    let groq = client::<Groq>(&server, GroqModel::Llama33_70bVersatile);
+    let (chunks, total_tokens) = stream(&groq, "hello from groq").await;
+    assert_eq!(chunks, ["hello ", "from ", "groq"]);
+    assert!(total_tokens.is_some_and(|tokens| tokens > 0));
+
+    let openrouter = client::<OpenRouter>(&server, OpenRouterModel::MetaLlama3370BInstruct);
+    let (chunks, _) = stream(&openrouter, "hello from openrouter").await;
+    assert_eq!(chunks.concat(), "hello from openrouter");
+
+    let sambanova = client::<SambaNova>(&server, SambaNovaModel::DeepSeekV3_0324);
+    let (chunks, _) = stream(&sambanova, "hello from sambanova").await;
+    assert_eq!(chunks.concat(), "hello from sambanova");
+
+    assert_eq!(server.completions(), 3);
+}
+
+#[tokio::test]
+async fn chat_collects_a_canned_reply() {
+    let config = MockServerConfig::new()
+        .reply("The answer is 42")
+        .chunk_delay(Duration::from_millis(5));
+    let server = MockServer::start(config).await.unwrap();
+    let client = client::<Groq>(&server, GroqModel::Llama31_8bInstant);
+
+    let reply = client.chat(user("What is the answer?")).await;
+
+    assert_eq!(reply, Ok("The answer is 42".to_string()));
+}
+
+#[tokio::test]
+async fn non_streaming_completions_return_one_message() {
+    let server = MockServer::start(MockServerConfig::new().reply("Hi there"))
+        .await
+        .unwrap();
+    let http = reqwest::Client::new();
+
+    let response = http
+        .post(format!("{}/chat/completions", server.base_url()))
+        .json(&json!({
+            "model": "llama-3.1-8b-instant",
+            "messages": [{ "role": "user", "content": "Hello" }],
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["object"], "chat.completion");
+    assert_eq!(body["model"], "llama-3.1-8b-instant");
+    assert_eq!(body["choices"][0]["message"]["content"], "Hi there");
+    assert_eq!(body["choices"][0]["finish_reason"], "stop");
+    assert!(body["usage"]["total_tokens"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn oversized_bodies_are_refused_before_they_are_read() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let server = MockServer::start(MockServerConfig::new().reply("Hi there"))
+        .await
+        .unwrap();
+
+    let mut socket = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+    socket
+        .write_all(b"POST /v1/chat/completions HTTP/1.1\r\nContent-Length: 1000000000000\r\n\r\n{}")
+        .await
+        .unwrap();
+    // The server hangs up without answering; a reset counts as hanging up too
+    let mut answer = Vec::new();
+    let _ = socket.read_to_end(&mut answer).await;
+
+    assert!(answer.is_empty());
+    let groq = client::<Groq>(&server, GroqModel::Llama33_70bVersatile);
+    assert_eq!(groq.chat(user("Hello")).await.unwrap(), "Hi there");
+}
+
+#[tokio::test]
+async fn failed_completions_surface_the_status() {
+    let server = MockServer::start(MockServerConfig::new().echo().fail_first(1))
+        .await
+        .unwrap();
+    let client = client::<SambaNova>(&server, SambaNovaModel::DeepSeekV3_0324);
+
+    let first = client.chat(user("one")).await.unwrap_err();
+    let second = client.chat(user("two")).await;
+
+    assert!(
+        first.starts_with("HTTP 500 Internal Server Error"),
+        "{}",
+        first
+    );
+    assert!(first.contains("Injected error from babel-mock-server"));
+    assert_eq!(second, Ok("two".to_string()));
+}
+
+#[tokio::test]
+async fn rate_limited_requests_fail_without_a_limiter() {
+    let config = MockServerConfig::new()
+        .echo()
+        .error_status(429)
+        .fail_first(1);
+    let server = MockServer::start(config).await.unwrap();
+    let client = client::<OpenRouter>(&server, OpenRouterModel::MetaLlama3370BInstruct);
+
+    let reply = client.chat(user("hello")).await;
+
+    assert!(reply.unwrap_err().starts_with("HTTP 429 Too Many Requests"));
+    assert_eq!(server.completions(), 1);
+}
+
+#[tokio::test]
+async fn rate_limited_requests_are_retried_after_retry_after() {
+    let config = MockServerConfig::new()
+        .echo()
+        .error_status(429)
+        .fail_first(1);
+    let server = MockServer::start(config).await.unwrap();
+    let client = LLMBuilder::<Groq>::new()
+        .model(GroqModel::Llama33_70bVersatile)
+        .api_key("test-key".to_string())
+        .base_url(server.base_url())
+        .rate_limiter(RateLimiter::new(RateLimit::default()).max_retries(2))
+        .build()
+        .unwrap();
+
+    let started = Instant::now();
+    let reply = client.chat(user("hello")).await;
+
+    assert_eq!(reply, Ok("hello".to_string()));
+    assert_eq!(server.completions(), 2);
+    // The server asks for a one second wait
+    assert!(started.elapsed() >= Duration::from_millis(900));
+}
+
+#[tokio::test]
+async fn the_binary_serves_clients() {
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    let mut server = tokio::process::Command::new(env!("CARGO_BIN_EXE_babel-mock-server"))
+        .args(["--port", "0", "--reply", "pong"])
+        .stdout(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+    let mut stdout = BufReader::new(server.stdout.take().unwrap()).lines();
+    let line = stdout.next_line().await.unwrap().unwrap();
+    let base_url = line.strip_prefix("Listening on ").unwrap().to_string();
+    let client = LLMBuilder::<Groq>::new()
+        .model(GroqModel::Llama31_8bInstant)
+        .api_key("test-key".to_string())
+        .base_url(base_url)
+        .build()
+        .unwrap();
+
+    let reply = client.chat(user("ping")).await;
+
+    assert_eq!(reply, Ok("pong".to_string()));
+    server.kill().await.unwrap();
+}